@@ -19,8 +19,18 @@ pub struct SockMsgEvent {
     pub ret: c_int,
     /// Process ID.
     pub pid: u32,
+    /// User ID of the calling task.
+    pub uid: u32,
+    /// Group ID of the calling task.
+    pub gid: u32,
+    /// Command name of the calling task, nul padded.
+    pub comm: [u8; TASK_COMM_LEN],
     /// Channel, `Rx: remote -> local`, `Tx: local -> remote`
     pub channel: Channel,
+    /// State of a stream socket after the event, unknown for the other types.
+    pub state: TcpState,
+    /// Time of the event in nanoseconds of `CLOCK_MONOTONIC` (`bpf_ktime_get_ns`).
+    pub ts: u64,
 }
 
 impl SockMsgEvent {
@@ -33,6 +43,16 @@ impl SockMsgEvent {
         }
     }
 
+    /// Returns the command name of the calling task.
+    pub fn comm(&self) -> &[u8] {
+        let len = self
+            .comm
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(TASK_COMM_LEN);
+        &self.comm[..len]
+    }
+
     #[cfg(feature = "user")]
     pub fn local_sock_addr(&self) -> std::net::SocketAddr {
         std::net::SocketAddr::new(self.local_addr.into(), u16::from_be(self.local_port))
@@ -44,6 +64,61 @@ impl SockMsgEvent {
     }
 }
 
+/// Size of the command name of a task, including the nul terminator.
+pub const TASK_COMM_LEN: usize = 16;
+
+/// Size of the path of a unix socket address (`sun_path` in `struct sockaddr_un`).
+pub const UNIX_PATH_MAX: usize = 108;
+
+/// Event triggered on send or receive on an `AF_UNIX` socket.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "user", derive(Debug))]
+pub struct UnixMsgEvent {
+    /// Socket type
+    pub sock_type: SockType,
+    /// Path of the socket, or of its peer if the socket is not bound.
+    /// Abstract names start with a nul byte.
+    pub path: [u8; UNIX_PATH_MAX],
+    /// Length of the path.
+    pub path_len: u8,
+    /// Length of the payload. If negative contains and error `-errno`.
+    pub ret: c_int,
+    /// Process ID.
+    pub pid: u32,
+    /// Process ID of the peer at the time of `connect`, or 0 if unknown.
+    pub peer_pid: u32,
+    /// Channel, `Rx: peer -> local`, `Tx: local -> peer`
+    pub channel: Channel,
+    /// Time of the event in nanoseconds of `CLOCK_MONOTONIC` (`bpf_ktime_get_ns`).
+    pub ts: u64,
+}
+
+impl UnixMsgEvent {
+    /// Returns `Ok(size)` if the probed call was successful or `Err(errno)`.
+    pub fn packet_size(&self) -> Result<u32, i32> {
+        if self.ret >= 0 {
+            Ok(self.ret as u32)
+        } else {
+            Err(-self.ret)
+        }
+    }
+
+    /// Returns the raw path of the socket.
+    pub fn path(&self) -> &[u8] {
+        &self.path[..(self.path_len as usize).min(UNIX_PATH_MAX)]
+    }
+}
+
+/// Index of the settings in the `SETTINGS` array shared with the BPF program.
+#[repr(u32)]
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "user", derive(Debug))]
+pub enum Setting {
+    /// Enables `AF_UNIX` traffic accounting when non zero.
+    UnixSockets = 0,
+}
+
 #[repr(u8)]
 #[derive(Clone, Copy)]
 #[cfg_attr(feature = "user", derive(Debug))]
@@ -197,6 +272,69 @@ impl ToString for SockType {
     }
 }
 
+/// State of a TCP socket, as in `include/net/tcp_states.h`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum TcpState {
+    #[default]
+    Unknown = 0,
+    Established = 1,
+    SynSent = 2,
+    SynRecv = 3,
+    FinWait1 = 4,
+    FinWait2 = 5,
+    TimeWait = 6,
+    Close = 7,
+    CloseWait = 8,
+    LastAck = 9,
+    Listen = 10,
+    Closing = 11,
+    NewSynRecv = 12,
+}
+
+impl From<u8> for TcpState {
+    #[inline]
+    fn from(val: u8) -> Self {
+        match val {
+            1 => Self::Established,
+            2 => Self::SynSent,
+            3 => Self::SynRecv,
+            4 => Self::FinWait1,
+            5 => Self::FinWait2,
+            6 => Self::TimeWait,
+            7 => Self::Close,
+            8 => Self::CloseWait,
+            9 => Self::LastAck,
+            10 => Self::Listen,
+            11 => Self::Closing,
+            12 => Self::NewSynRecv,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[cfg(feature = "user")]
+impl TcpState {
+    /// Returns the name of the state, as in the filters.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "",
+            Self::Established => "established",
+            Self::SynSent => "syn_sent",
+            Self::SynRecv => "syn_recv",
+            Self::FinWait1 => "fin_wait1",
+            Self::FinWait2 => "fin_wait2",
+            Self::TimeWait => "time_wait",
+            Self::Close => "close",
+            Self::CloseWait => "close_wait",
+            Self::LastAck => "last_ack",
+            Self::Listen => "listen",
+            Self::Closing => "closing",
+            Self::NewSynRecv => "new_syn_recv",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "user")]
@@ -43562,3 +43562,38 @@ pub struct sem_undo_list {
     pub lock: spinlock_t,
     pub list_proc: list_head,
 }
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sockaddr_un {
+    pub sun_family: __kernel_sa_family_t,
+    pub sun_path: [::aya_bpf::cty::c_char; 108usize],
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct unix_address {
+    pub refcnt: refcount_t,
+    pub len: ::aya_bpf::cty::c_int,
+    pub name: __IncompleteArrayField<sockaddr_un>,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct scm_stat {
+    pub nr_fds: atomic_t,
+}
+#[repr(C)]
+pub struct unix_sock {
+    pub sk: sock,
+    pub addr: *mut unix_address,
+    pub path: path,
+    pub iolock: mutex,
+    pub bindlock: mutex,
+    pub peer: *mut sock,
+    pub link: list_head,
+    pub inflight: atomic_long_t,
+    pub lock: spinlock_t,
+    pub gc_flags: ::aya_bpf::cty::c_ulong,
+    pub peer_wq: socket_wq,
+    pub peer_wake: wait_queue_entry_t,
+    pub scm_stat: scm_stat,
+    pub oob_skb: *mut sk_buff,
+}
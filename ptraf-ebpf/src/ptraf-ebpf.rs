@@ -1,12 +1,14 @@
 #![no_std]
 #![no_main]
 
-use core::ffi::c_int;
+use core::{ffi::c_int, mem};
 
-use aya_bpf::helpers::{bpf_get_current_pid_tgid, bpf_probe_read_kernel};
-use aya_bpf::macros::tracepoint;
-use aya_bpf::maps::{HashMap, PerfEventArray};
-use aya_bpf::programs::TracePointContext;
+use aya_bpf::helpers::{
+    bpf_get_current_pid_tgid, bpf_ktime_get_ns, bpf_probe_read_kernel, bpf_probe_read_kernel_buf,
+};
+use aya_bpf::macros::{fexit, tracepoint};
+use aya_bpf::maps::{Array, HashMap, PerfEventArray};
+use aya_bpf::programs::{FExitContext, TracePointContext};
 use aya_bpf::BpfContext;
 use aya_bpf::{
     macros::{kprobe, kretprobe, map},
@@ -14,7 +16,9 @@ use aya_bpf::{
 };
 // use aya_log_ebpf::debug;
 
-use ptraf_common::types::{Channel, IpAddr, SockMsgEvent};
+use ptraf_common::types::{
+    Channel, IpAddr, Setting, SockMsgEvent, TcpState, UnixMsgEvent, TASK_COMM_LEN, UNIX_PATH_MAX,
+};
 
 #[allow(non_upper_case_globals)]
 #[allow(non_snake_case)]
@@ -28,7 +32,7 @@ use ptraf_common::types::{Channel, IpAddr, SockMsgEvent};
 #[allow(clippy::wrong_self_convention)]
 mod bindings;
 
-use bindings::{sock as Sock, sock_common as SockCommon, socket as Socket};
+use bindings::{sock as Sock, sock_common as SockCommon, socket as Socket, unix_sock as UnixSock};
 
 // Force aya_log_epbf to be linked.
 const _UNUSED: usize = aya_log_ebpf::LOG_BUF_CAPACITY;
@@ -39,6 +43,14 @@ const _UNUSED: usize = aya_log_ebpf::LOG_BUF_CAPACITY;
 #[map]
 static mut EVENTS: PerfEventArray<SockMsgEvent> = PerfEventArray::new(0);
 
+/// Shared `AF_UNIX` events with the userland program.
+#[map]
+static mut UNIX_EVENTS: PerfEventArray<UnixMsgEvent> = PerfEventArray::new(0);
+
+/// Settings written by the userland program, indexed by [Setting].
+#[map]
+static mut SETTINGS: Array<u32> = Array::with_max_entries(1, 0);
+
 /// Internal temporary cache to store the socket between the probe and the return probe.
 #[map]
 static mut CACHE: HashMap<u64, *const Socket> = HashMap::with_max_entries(16384, 0);
@@ -61,6 +73,22 @@ pub fn send_msg_ret(ctx: ProbeContext) -> u32 {
     unsafe { try_msg_ret(ctx, Channel::Tx) }.unwrap_or(1)
 }
 
+/// Exit program for sock_sendmsg, used instead of the kprobes when the kernel
+/// supports BPF trampolines.
+#[fexit(name = "sendmsg_fexit")]
+pub fn send_msg_fexit(ctx: FExitContext) -> u32 {
+    // int sock_sendmsg(struct socket *sock, struct msghdr *msg)
+    unsafe { try_msg_fexit(ctx, 2, Channel::Tx) }.unwrap_or(1)
+}
+
+/// Exit program for sock_recvmsg, used instead of the kprobes when the kernel
+/// supports BPF trampolines.
+#[fexit(name = "recvmsg_fexit")]
+pub fn recv_msg_fexit(ctx: FExitContext) -> u32 {
+    // int sock_recvmsg(struct socket *sock, struct msghdr *msg, int flags)
+    unsafe { try_msg_fexit(ctx, 3, Channel::Rx) }.unwrap_or(1)
+}
+
 #[tracepoint(name = "sock_set_state")]
 pub fn inet_sock_set_state(ctx: TracePointContext) -> u32 {
     /*
@@ -105,7 +133,7 @@ pub fn inet_sock_set_state(ctx: TracePointContext) -> u32 {
 
     if matches!(args.family, AF_INET | AF_INET6) {
         unsafe {
-            notify(ctx, args.skaddr, 0, Channel::Tx)
+            notify(ctx, args.skaddr, 0, Channel::Tx, Some(args.newstate as u8))
                 .map(|_| 0)
                 .unwrap_or(1)
         }
@@ -114,14 +142,19 @@ pub fn inet_sock_set_state(ctx: TracePointContext) -> u32 {
     }
 }
 
+const AF_UNIX: u16 = 1;
 const AF_INET: u16 = 2;
 const AF_INET6: u16 = 10;
 
+const SOCK_STREAM: u16 = 1;
+
+/// `state` is the new state of the socket on a state change, the current one is read otherwise.
 unsafe fn notify(
     ctx: impl BpfContext,
     sk: *const Sock,
     ret: c_int,
     channel: Channel,
+    state: Option<u8>,
 ) -> Result<(), i64> {
     let sk_common = bpf_probe_read_kernel(&(*sk).__sk_common as *const SockCommon)?;
     let sk_type = bpf_probe_read_kernel(&(*sk).sk_type)?;
@@ -176,18 +209,31 @@ unsafe fn notify(
 
             (local_addr, remote_addr)
         }
+        AF_UNIX => return notify_unix(ctx, sk, sk_type, ret, channel),
         _ => return Ok(()),
     };
 
+    // Connected datagram sockets are also marked as established.
+    let state = if sk_type == SOCK_STREAM {
+        state.unwrap_or(sk_common.skc_state).into()
+    } else {
+        TcpState::Unknown
+    };
+
     let event = SockMsgEvent {
         sock_type: sk_type.into(),
         pid: ctx.pid(),
+        uid: ctx.uid(),
+        gid: ctx.gid(),
+        comm: ctx.command().unwrap_or([0; TASK_COMM_LEN]),
         local_addr,
         remote_addr,
         ret,
         local_port,
         remote_port,
         channel,
+        state,
+        ts: bpf_ktime_get_ns(),
     };
 
     EVENTS.output(&ctx, &event, 0);
@@ -195,6 +241,95 @@ unsafe fn notify(
     Ok(())
 }
 
+unsafe fn notify_unix(
+    ctx: impl BpfContext,
+    sk: *const Sock,
+    sk_type: u16,
+    ret: c_int,
+    channel: Channel,
+) -> Result<(), i64> {
+    let unix_sk = sk as *const UnixSock;
+
+    // Client sockets are usually not bound, use the address of the peer instead.
+    let mut addr = bpf_probe_read_kernel(&(*unix_sk).addr)?;
+    if addr.is_null() {
+        let peer = bpf_probe_read_kernel(&(*unix_sk).peer)?;
+        if !peer.is_null() {
+            addr = bpf_probe_read_kernel(&(*(peer as *const UnixSock)).addr)?;
+        }
+    }
+
+    let peer_pid = bpf_probe_read_kernel(&(*sk).sk_peer_pid)?;
+    let peer_pid = if peer_pid.is_null() {
+        0
+    } else {
+        bpf_probe_read_kernel(&(*peer_pid).numbers[0].nr)? as u32
+    };
+
+    let mut event = UnixMsgEvent {
+        sock_type: sk_type.into(),
+        path: [0; UNIX_PATH_MAX],
+        path_len: 0,
+        ret,
+        pid: ctx.pid(),
+        peer_pid,
+        channel,
+        ts: bpf_ktime_get_ns(),
+    };
+
+    if !addr.is_null() {
+        // `len` includes the address family, the path is not nul terminated and only `len`
+        // bytes of `sun_path` are allocated.
+        let len = bpf_probe_read_kernel(&(*addr).len)? - mem::size_of::<u16>() as c_int;
+        let len = len.clamp(0, UNIX_PATH_MAX as c_int) as usize;
+        if len > 0 {
+            let name = (*addr).name.as_ptr();
+            bpf_probe_read_kernel_buf(
+                (*name).sun_path.as_ptr() as *const u8,
+                &mut event.path[..len],
+            )?;
+            event.path_len = len as u8;
+        }
+    }
+
+    UNIX_EVENTS.output(&ctx, &event, 0);
+
+    Ok(())
+}
+
+#[inline(always)]
+unsafe fn setting_enabled(setting: Setting) -> bool {
+    SETTINGS
+        .get(setting as u32)
+        .map(|val| *val != 0)
+        .unwrap_or(false)
+}
+
+/// Tells whether messages on sockets of the `family` are reported.
+#[inline(always)]
+unsafe fn is_tracked_family(family: u16) -> bool {
+    matches!(family, AF_INET | AF_INET6)
+        || (family == AF_UNIX && setting_enabled(Setting::UnixSockets))
+}
+
+/// `ret_idx` is the index of the return value in the arguments of the traced function.
+unsafe fn try_msg_fexit(ctx: FExitContext, ret_idx: usize, channel: Channel) -> Result<u32, i64> {
+    let socket: *const Socket = ctx.arg(0);
+    let ret: c_int = ctx.arg(ret_idx);
+
+    let sk = bpf_probe_read_kernel(&(*socket).sk)?;
+    let sk_common = bpf_probe_read_kernel(&(*sk).__sk_common as *const SockCommon)?;
+
+    if !is_tracked_family(sk_common.skc_family) {
+        return Ok(0);
+    }
+
+    match notify(ctx, sk, ret, channel, None) {
+        Ok(_) => Ok(0),
+        Err(_) => Err(1),
+    }
+}
+
 unsafe fn try_msg_ret(ctx: ProbeContext, channel: Channel) -> Result<u32, i64> {
     let pid_tgid = bpf_get_current_pid_tgid();
     let socket = if let Some(socket) = CACHE.get(&pid_tgid) {
@@ -207,7 +342,7 @@ unsafe fn try_msg_ret(ctx: ProbeContext, channel: Channel) -> Result<u32, i64> {
     let val: c_int = ctx.ret().ok_or(1i64)?;
     let sk = bpf_probe_read_kernel(&(*socket).sk)?;
 
-    match notify(ctx, sk, val, channel) {
+    match notify(ctx, sk, val, channel, None) {
         Ok(_) => Ok(0),
         Err(_) => Err(1),
     }
@@ -218,7 +353,7 @@ unsafe fn try_msg(ctx: ProbeContext) -> Result<u32, i64> {
     let sk = bpf_probe_read_kernel(&(*socket).sk)?;
     let sk_common = bpf_probe_read_kernel(&(*sk).__sk_common as *const SockCommon)?;
 
-    if matches!(sk_common.skc_family, AF_INET | AF_INET6) {
+    if is_tracked_family(sk_common.skc_family) {
         let pid_tgid = bpf_get_current_pid_tgid();
         CACHE.insert(&pid_tgid, &socket, 0)?;
     }
@@ -0,0 +1,180 @@
+//! Compares the compiled filters with the walk of their expressions.
+//!
+//! Run with `cargo bench -p ptraf-filter`.
+
+use std::{
+    borrow::Cow,
+    hint::black_box,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ptraf_filter::{
+    Filterable, Interpretor, IpVersion, Lookup, Protocol, SockType, TcpState, Traffic,
+};
+
+const FILTERS: &[&str] = &[
+    "tcp and rport[443]",
+    "cmdline =~ \"worker\" and exe[/usr/*] and (rport[443] or lport[80])",
+    "not (udp or ipv6) and not (remote private or loopback) and state[established]",
+    "rhost[*.amazonaws.com] and tcp and port >= 0",
+    "(user[alice] or group[staff]) and rate > 1MiB and not raddr[10.0.0.0/8, 192.168.0.0/16]",
+];
+
+const SOCKETS: usize = 10_000;
+
+const ROUNDS: usize = 20;
+
+struct Socket {
+    pid: u32,
+    protocol: Protocol,
+    remote_address: IpAddr,
+    remote_port: u16,
+    uid: u32,
+    exe: Arc<str>,
+    cmdline: Arc<str>,
+    traffic: Traffic,
+}
+
+impl Filterable for Socket {
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn protocol(&self) -> Option<Protocol> {
+        Some(self.protocol)
+    }
+
+    fn sock_type(&self) -> Option<SockType> {
+        Some(match self.protocol {
+            Protocol::Tcp => SockType::Stream,
+            Protocol::Udp => SockType::Dgram,
+        })
+    }
+
+    fn state(&self) -> Option<TcpState> {
+        (self.protocol == Protocol::Tcp).then_some(TcpState::Established)
+    }
+
+    fn ip_version(&self) -> IpVersion {
+        IpVersion::IpV4
+    }
+
+    fn local_address(&self) -> IpAddr {
+        Ipv4Addr::new(192, 168, 1, 32).into()
+    }
+
+    fn remote_address(&self) -> IpAddr {
+        self.remote_address
+    }
+
+    fn local_port(&self) -> u16 {
+        43210
+    }
+
+    fn remote_port(&self) -> u16 {
+        self.remote_port
+    }
+
+    fn uid(&self) -> Option<u32> {
+        Some(self.uid)
+    }
+
+    fn user_name(&self) -> Option<&str> {
+        (self.uid == 1000).then_some("alice")
+    }
+
+    fn gid(&self) -> Option<u32> {
+        Some(self.uid)
+    }
+
+    fn group_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn comm(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed("nginx"))
+    }
+
+    fn exe(&self) -> Option<Arc<str>> {
+        Some(self.exe.clone())
+    }
+
+    fn cmdline(&self) -> Option<Arc<str>> {
+        Some(self.cmdline.clone())
+    }
+
+    fn host_name(&self, addr: IpAddr) -> Lookup<Arc<str>> {
+        match addr {
+            IpAddr::V4(addr) if addr.octets()[0] == 52 => {
+                Lookup::Found(format!("ec2-{addr}.compute.amazonaws.com").into())
+            }
+            _ => Lookup::NotFound,
+        }
+    }
+
+    fn host_addrs(&self, _name: &str) -> Lookup<Arc<[IpAddr]>> {
+        Lookup::NotFound
+    }
+
+    fn traffic(&self) -> Option<Traffic> {
+        Some(self.traffic)
+    }
+}
+
+fn sockets() -> Vec<Socket> {
+    let exe: Arc<str> = "/usr/sbin/nginx".into();
+
+    (0..SOCKETS as u32)
+        .map(|i| Socket {
+            pid: 100 + i % 50,
+            protocol: if i % 3 == 0 {
+                Protocol::Udp
+            } else {
+                Protocol::Tcp
+            },
+            remote_address: Ipv4Addr::new([1, 10, 52, 192][i as usize % 4], 0, 0, i as u8).into(),
+            remote_port: [443, 80, 53, 8443][i as usize % 4],
+            uid: if i % 2 == 0 { 1000 } else { 0 },
+            exe: Arc::clone(&exe),
+            cmdline: format!("nginx: worker process {}", 100 + i % 50).into(),
+            traffic: Traffic {
+                rx_rate: f64::from(i) * 1000.0,
+                ..Traffic::default()
+            },
+        })
+        .collect()
+}
+
+/// Returns the time to evaluate `eval` on every socket, the best of the rounds.
+fn bench(sockets: &[Socket], eval: impl Fn(&Socket) -> Option<bool>) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for socket in sockets {
+                black_box(eval(black_box(socket)));
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let sockets = sockets();
+
+    for filter in FILTERS {
+        let interpretor = Interpretor::parse(filter).unwrap();
+
+        let walk = bench(&sockets, |socket| interpretor.walk(socket));
+        let compiled = bench(&sockets, |socket| interpretor.eval(socket));
+
+        println!("{filter}");
+        println!(
+            "    walk {:>8.1} ns/socket, compiled {:>8.1} ns/socket",
+            walk.as_nanos() as f64 / SOCKETS as f64,
+            compiled.as_nanos() as f64 / SOCKETS as f64,
+        );
+    }
+}
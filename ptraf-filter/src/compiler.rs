@@ -0,0 +1,361 @@
+//! Compilation of the filter expressions to a flat program.
+//!
+//! The expression is simplified first: the negations are pushed down to the predicates
+//! (De Morgan), the constant predicates are folded, the nested conjunctions and disjunctions
+//! are flattened and their operands ordered from the cheapest to the most expensive, the
+//! evaluation short-circuiting the expensive ones. The simplifications hold in the
+//! three-valued logic of the filters.
+
+use std::ops::RangeInclusive;
+
+use crate::{frontend::Expr, Filterable, Interpretor};
+
+/// A filter expression compiled to a flat list of operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Program {
+    ops: Vec<Op>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op {
+    /// The result of a constant expression, only found alone in a program.
+    Const(bool),
+    /// A predicate which is never unknown.
+    Test { predicate: Expr, negated: bool },
+    /// A predicate which may be unknown, see [may_be_unknown].
+    Eval { predicate: Expr, negated: bool },
+    /// Conjunction of the operands in the next `len` operations.
+    All { len: usize },
+    /// Disjunction of the operands in the next `len` operations.
+    Any { len: usize },
+}
+
+impl Program {
+    pub(crate) fn compile(expr: &Expr) -> Self {
+        let mut ops = Vec::new();
+        Node::simplify(expr, false).emit(&mut ops);
+        Self { ops }
+    }
+
+    /// Evaluates the program in a three-valued logic, `None` if it is unknown.
+    pub(crate) fn eval<F: Filterable>(&self, f: &F) -> Option<bool> {
+        match self.ops.split_first() {
+            Some((Op::All { .. }, operands)) => Self::group(f, operands, false),
+            Some((Op::Any { .. }, operands)) => Self::group(f, operands, true),
+            // The conjunction of a single operand is the operand.
+            _ => Self::group(f, &self.ops, false),
+        }
+    }
+
+    /// Evaluates the conjunction of the operands in `ops`, their disjunction if `any`.
+    fn group<F: Filterable>(f: &F, mut ops: &[Op], any: bool) -> Option<bool> {
+        let mut result = Some(!any);
+        while let Some((op, rest)) = ops.split_first() {
+            let value = match op {
+                Op::Const(value) => {
+                    ops = rest;
+                    Some(*value)
+                }
+                Op::Test { predicate, negated } => {
+                    ops = rest;
+                    Some(Interpretor::test(f, predicate) != *negated)
+                }
+                Op::Eval { predicate, negated } => {
+                    ops = rest;
+                    Interpretor::predicate(f, predicate).map(|value| value != *negated)
+                }
+                Op::All { len } | Op::Any { len } => {
+                    let (operands, rest) = rest.split_at(*len);
+                    ops = rest;
+                    Self::group(f, operands, matches!(op, Op::Any { .. }))
+                }
+            };
+
+            match value {
+                Some(value) if value == any => return Some(any),
+                Some(_) => {}
+                None => result = None,
+            }
+        }
+        result
+    }
+}
+
+/// A simplified expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Const(bool),
+    Test { predicate: Expr, negated: bool },
+    All(Vec<Node>),
+    Any(Vec<Node>),
+}
+
+impl Node {
+    /// Simplifies the expression, negated if `negated`.
+    fn simplify(expr: &Expr, negated: bool) -> Self {
+        match expr {
+            Expr::Not(a) => Self::simplify(a, !negated),
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                // not (a and b) = not a or not b, not (a or b) = not a and not b
+                let any = matches!(expr, Expr::Or(..)) != negated;
+                Self::group(
+                    vec![Self::simplify(a, negated), Self::simplify(b, negated)],
+                    any,
+                )
+            }
+            predicate => match fold(predicate) {
+                Some(value) => Node::Const(value != negated),
+                None => Node::Test {
+                    predicate: predicate.clone(),
+                    negated,
+                },
+            },
+        }
+    }
+
+    /// Returns the conjunction of the simplified operands, their disjunction if `any`.
+    fn group(operands: Vec<Node>, any: bool) -> Self {
+        let mut flat = Vec::with_capacity(operands.len());
+        for operand in operands {
+            match operand {
+                // The identity, true for a conjunction.
+                Node::Const(value) if value != any => {}
+                // The absorbing element, false for a conjunction.
+                Node::Const(_) => return Node::Const(any),
+                Node::All(nested) if !any => flat.extend(nested),
+                Node::Any(nested) if any => flat.extend(nested),
+                operand => flat.push(operand),
+            }
+        }
+
+        let mut operands: Vec<Node> = Vec::with_capacity(flat.len());
+        for operand in flat {
+            // p and not p = false, p or not p = true
+            if operands.iter().any(|other| operand.complements(other)) {
+                return Node::Const(any);
+            }
+            if !operands.contains(&operand) {
+                operands.push(operand);
+            }
+        }
+        operands.sort_by_key(Node::cost);
+
+        match operands.len() {
+            0 => Node::Const(!any),
+            1 => operands.pop().unwrap(),
+            _ if any => Node::Any(operands),
+            _ => Node::All(operands),
+        }
+    }
+
+    /// Tells whether the nodes are the negation of each other, only for the predicates which
+    /// are never unknown.
+    fn complements(&self, other: &Node) -> bool {
+        match (self, other) {
+            (
+                Node::Test { predicate, negated },
+                Node::Test {
+                    predicate: other,
+                    negated: other_negated,
+                },
+            ) => negated != other_negated && predicate == other && !may_be_unknown(predicate),
+            _ => false,
+        }
+    }
+
+    fn cost(&self) -> u32 {
+        match self {
+            Node::Const(_) => 0,
+            Node::Test { predicate, .. } => cost(predicate),
+            Node::All(nodes) | Node::Any(nodes) => nodes.iter().map(Node::cost).sum(),
+        }
+    }
+
+    fn emit(self, ops: &mut Vec<Op>) {
+        let (nodes, any) = match self {
+            Node::Const(value) => return ops.push(Op::Const(value)),
+            Node::Test { predicate, negated } if may_be_unknown(&predicate) => {
+                return ops.push(Op::Eval { predicate, negated })
+            }
+            Node::Test { predicate, negated } => return ops.push(Op::Test { predicate, negated }),
+            Node::All(nodes) => (nodes, false),
+            Node::Any(nodes) => (nodes, true),
+        };
+
+        let at = ops.len();
+        ops.push(Op::All { len: 0 });
+        for node in nodes {
+            node.emit(ops);
+        }
+        let len = ops.len() - at - 1;
+        ops[at] = if any {
+            Op::Any { len }
+        } else {
+            Op::All { len }
+        };
+    }
+}
+
+/// Returns the value of a constant predicate.
+fn fold(predicate: &Expr) -> Option<bool> {
+    match predicate {
+        Expr::SockType(types) => types.is_empty().then_some(false),
+        Expr::State(states) => states.is_empty().then_some(false),
+        Expr::PortIn(ranges) | Expr::LocalPortIn(ranges) | Expr::RemotePortIn(ranges) => {
+            if ranges.iter().all(RangeInclusive::is_empty) {
+                Some(false)
+            } else {
+                all_ports(ranges).then_some(true)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Tells whether the ranges cover all the ports.
+fn all_ports(ranges: &[RangeInclusive<u16>]) -> bool {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|range| *range.start());
+
+    // The first port not covered yet.
+    let mut next = 0u32;
+    for range in ranges.iter().filter(|range| !range.is_empty()) {
+        if u32::from(*range.start()) > next {
+            break;
+        }
+        next = next.max(u32::from(*range.end()) + 1);
+    }
+    next > u32::from(u16::MAX)
+}
+
+/// Tells whether the predicate may be unknown, see [Filterable::traffic], [Filterable::exe]
+/// and [crate::Lookup].
+fn may_be_unknown(predicate: &Expr) -> bool {
+    matches!(
+        predicate,
+        Expr::Traffic(..)
+            | Expr::Exe(_)
+            | Expr::Cmdline(_)
+            | Expr::Host(_)
+            | Expr::LocalHost(_)
+            | Expr::RemoteHost(_)
+    )
+}
+
+/// Returns the relative cost of evaluating the predicate.
+fn cost(predicate: &Expr) -> u32 {
+    match predicate {
+        Expr::Pid(_)
+        | Expr::Protocol(_)
+        | Expr::IpVersion(_)
+        | Expr::SockType(_)
+        | Expr::State(_)
+        | Expr::Addr(_)
+        | Expr::LocalAddr(_)
+        | Expr::RemoteAddr(_)
+        | Expr::Port(_)
+        | Expr::LocalPort(_)
+        | Expr::RemotePort(_)
+        | Expr::Uid(_)
+        | Expr::Gid(_)
+        | Expr::Traffic(..) => 1,
+        Expr::AddrIn(_)
+        | Expr::LocalAddrIn(_)
+        | Expr::RemoteAddrIn(_)
+        | Expr::AddrClass(_)
+        | Expr::LocalAddrClass(_)
+        | Expr::RemoteAddrClass(_)
+        | Expr::PortIn(_)
+        | Expr::LocalPortIn(_)
+        | Expr::RemotePortIn(_) => 2,
+        // Looked up in the user and group databases.
+        Expr::User(_) | Expr::Group(_) => 4,
+        // Matched against a pattern, the executable and command line are read from procfs.
+        Expr::Comm(_) => 8,
+        Expr::Exe(_) | Expr::Cmdline(_) => 16,
+        // Looked up in the DNS cache.
+        Expr::Host(_) | Expr::LocalHost(_) | Expr::RemoteHost(_) => 32,
+        Expr::And(..) | Expr::Or(..) | Expr::Not(_) => unreachable!("not a predicate"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::frontend::{parser, Protocol};
+
+    use super::*;
+
+    fn compile(input: &str) -> Vec<Op> {
+        Program::compile(&parser::filter(input).unwrap()).ops
+    }
+
+    fn test(input: &str, negated: bool) -> Op {
+        let predicate = parser::filter(input).unwrap();
+        if may_be_unknown(&predicate) {
+            Op::Eval { predicate, negated }
+        } else {
+            Op::Test { predicate, negated }
+        }
+    }
+
+    #[test]
+    fn compilation() {
+        // De Morgan.
+        assert_eq!(
+            compile("not (tcp and not (rport[443] or ipv6))"),
+            vec![
+                Op::Any { len: 3 },
+                test("tcp", true),
+                test("rport[443]", false),
+                test("ipv6", false),
+            ]
+        );
+
+        // Cheap predicates first, nested groups flattened.
+        assert_eq!(
+            compile("cmdline[*nginx*] and (host[db] or udp) and (tcp and pid[1])"),
+            vec![
+                Op::All { len: 6 },
+                test("tcp", false),
+                test("pid[1]", false),
+                test("cmdline[*nginx*]", false),
+                Op::Any { len: 2 },
+                test("udp", false),
+                test("host[db]", false),
+            ]
+        );
+
+        // Duplicates.
+        assert_eq!(compile("tcp and tcp or tcp"), vec![test("tcp", false)]);
+
+        // Constants.
+        assert_eq!(compile("port >= 0"), vec![Op::Const(true)]);
+        assert_eq!(compile("rport[0-100, 50-65535]"), vec![Op::Const(true)]);
+        assert_eq!(compile("lport < 0 or tcp"), vec![test("tcp", false)]);
+        assert_eq!(compile("not lport < 0 or exe[*]"), vec![Op::Const(true)]);
+        assert_eq!(
+            compile("tcp and not tcp and exe[*]"),
+            vec![Op::Const(false)]
+        );
+        assert_eq!(
+            compile("uid[0] or not uid[0] or host[db]"),
+            vec![Op::Const(true)]
+        );
+        assert_eq!(
+            Program::compile(&Expr::Protocol(Protocol::Tcp)).ops,
+            vec![test("tcp", false)]
+        );
+
+        // Unknown predicates are not the negation of each other.
+        assert_eq!(
+            compile("rate > 1k or not rate > 1k"),
+            vec![
+                Op::Any { len: 2 },
+                test("rate > 1k", false),
+                test("rate > 1k", true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::{collections::BTreeSet, net::IpAddr, ops::Range};
+
+use crate::frontend::parser;
+
+/// Values observed in the dataset, completed inside the brackets of the predicates.
+#[derive(Debug, Clone, Default)]
+pub struct Values {
+    pub pids: BTreeSet<u32>,
+    /// Command names of the processes.
+    pub process_names: BTreeSet<String>,
+    pub remote_addrs: BTreeSet<IpAddr>,
+    pub remote_ports: BTreeSet<u16>,
+}
+
+/// Candidates to replace the word before the cursor with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Completion {
+    /// Byte range of the input replaced by a candidate.
+    pub span: Range<usize>,
+    pub candidates: Vec<String>,
+}
+
+/// Completes the word before the byte offset `cursor` of the `input`, with the keywords
+/// expected there and the `values` of the dataset in the brackets of `pid[`, `comm[`,
+/// `addr[`, `raddr[`, `port[` and `rport[`.
+///
+/// A `cursor` past the end or inside a character is moved back to the previous character.
+pub fn complete(input: &str, cursor: usize, values: &Values) -> Completion {
+    let mut cursor = cursor.min(input.len());
+    while !input.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+
+    let before = &input[..cursor];
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(cursor, |(i, _)| i);
+    let prefix = &input[start..cursor];
+
+    let keywords: Vec<&str> = keywords(&input[..start])
+        .filter(|keyword| keyword.starts_with(prefix))
+        .collect();
+    // Only the bracketed form of the predicates taking arguments, e.g. `rport[` for `rport`.
+    let mut candidates: Vec<String> = keywords
+        .iter()
+        .filter(|keyword| !keywords.contains(&format!("{keyword}[").as_str()))
+        .map(|keyword| keyword.to_string())
+        .collect();
+
+    let values: Vec<String> = match bracket(&input[..start]) {
+        Some("pid") => values.pids.iter().map(u32::to_string).collect(),
+        Some("comm") => values.process_names.iter().cloned().collect(),
+        Some("addr" | "raddr") => values.remote_addrs.iter().map(IpAddr::to_string).collect(),
+        Some("port" | "rport") => values.remote_ports.iter().map(u16::to_string).collect(),
+        _ => Vec::new(),
+    };
+    candidates.extend(values.into_iter().filter(|value| value.starts_with(prefix)));
+
+    Completion {
+        span: start..cursor,
+        candidates,
+    }
+}
+
+/// Returns the keywords expected at the end of `input`, e.g. `rport[` or `established`.
+fn keywords(input: &str) -> impl Iterator<Item = &'static str> {
+    // No rule accepts a nul, the parse fails where the keywords are expected.
+    let err = parser::filter(&format!("{input}\0"))
+        .err()
+        .filter(|err| err.location.offset == input.len());
+
+    err.into_iter()
+        .flat_map(|err| err.expected.tokens().collect::<Vec<_>>())
+        .filter(|token| token.starts_with('"'))
+        .map(|token| token.trim_matches('"'))
+        .filter(|keyword| keyword.starts_with(|c: char| c.is_ascii_alphanumeric()))
+}
+
+/// Returns the keyword of the bracket open at the end of `input`, e.g. `rport` in
+/// `rport[80, `.
+fn bracket(input: &str) -> Option<&str> {
+    let open = input.rfind('[')?;
+    if input[open..].contains(']') {
+        return None;
+    }
+
+    let keyword = &input[..open];
+    let start = keyword
+        .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map_or(0, |i| i + 1);
+    Some(&keyword[start..])
+}
+
+#[inline]
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn candidates(input: &str, values: &Values) -> Vec<String> {
+        complete(input, input.len(), values).candidates
+    }
+
+    #[test]
+    fn completion() {
+        let values = Values {
+            pids: [1, 12, 213].into(),
+            process_names: ["curl".to_string(), "nginx".to_string()].into(),
+            remote_addrs: ["1.1.1.1".parse().unwrap(), "10.1.2.3".parse().unwrap()].into(),
+            remote_ports: [53, 443, 8443].into(),
+        };
+
+        assert_eq!(candidates("tcp and rp", &values), ["rport["]);
+        assert_eq!(candidates("tcp ", &values), ["and", "or"]);
+        assert_eq!(candidates("(udp or ip", &values), ["ipv4", "ipv6"]);
+        assert_eq!(candidates("remote pr", &values), ["private"]);
+        assert_eq!(candidates("state[est", &values), ["established"]);
+        assert_eq!(candidates("state[listen|c", &values).len(), 3);
+
+        assert_eq!(candidates("pid[1", &values), ["1", "12"]);
+        assert_eq!(candidates("comm[", &values), ["curl", "nginx"]);
+        assert_eq!(candidates("raddr[1.1.1.1, 10.", &values), ["10.1.2.3"]);
+        assert_eq!(candidates("rport[80, 8", &values), ["8443"]);
+        assert_eq!(candidates("lport[", &values), Vec::<String>::new());
+        assert_eq!(candidates("rport[443] and po", &values), ["port["]);
+
+        // Nothing is expected after an error.
+        assert_eq!(candidates("tcp adn ud", &values), Vec::<String>::new());
+
+        let completion = complete("tcp and rp and udp", 10, &values);
+        assert_eq!(completion.span, 8..10);
+        assert_eq!(completion.candidates, ["rport["]);
+
+        // The cursor is moved back to the end of the input or to a character.
+        let completion = complete("tcp and rp", 42, &values);
+        assert_eq!(completion.span, 8..10);
+        assert_eq!(completion.candidates, ["rport["]);
+        let completion = complete("comm[é", 6, &values);
+        assert_eq!(completion.span, 5..5);
+        assert_eq!(completion.candidates, ["curl", "nginx"]);
+    }
+}
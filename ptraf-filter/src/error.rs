@@ -0,0 +1,317 @@
+use std::{fmt, ops::Range};
+
+use peg::{error::ParseError, str::LineCol};
+
+use crate::frontend::parser;
+
+/// A filter which failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// Byte range of the input in error, empty at the end of the input.
+    pub span: Range<usize>,
+    /// Column of the start of the span, from 1.
+    pub column: usize,
+    pub message: String,
+    /// What was expected at the start of the span, e.g. `rport[` or digit.
+    pub expected: Vec<String>,
+    /// The keyword likely meant instead of a misspelt one.
+    pub suggestion: Option<String>,
+}
+
+impl Error {
+    pub(crate) fn new(input: &str, err: &ParseError<LineCol>) -> Self {
+        let offset = err.location.offset.min(input.len());
+        let tokens: Vec<&'static str> = err.expected.tokens().collect();
+
+        // The values failing validation, e.g. `rport[70000]`, are before the error.
+        if let Some(invalid) = tokens.iter().find(|token| token.starts_with("invalid ")) {
+            let start = value_start(input, offset);
+            let message = match hint(invalid) {
+                Some(hint) => format!("{invalid}, {hint}"),
+                None => invalid.to_string(),
+            };
+            return Self::with_span(input, start..offset, message, Vec::new(), None);
+        }
+
+        let expected = tokens.iter().filter_map(|token| describe(token)).collect();
+
+        let span = word(input, offset);
+        if span.is_empty() {
+            let message = match input[offset..].chars().next() {
+                Some(c) => format!("unexpected `{c}`"),
+                None => "unexpected end of filter".to_string(),
+            };
+            let end = offset + input[offset..].chars().next().map_or(0, char::len_utf8);
+            return Self::with_span(input, offset..end, message, expected, None);
+        }
+
+        let word = &input[span.clone()];
+        // A word failing in its middle, e.g. `udpp`, may be any keyword.
+        let suggestion = if span.start < offset {
+            suggest(word, keywords().into_iter().chain(tokens))
+        } else {
+            suggest(word, tokens.into_iter())
+        };
+
+        Self::with_span(
+            input,
+            span,
+            format!("unexpected `{word}`"),
+            expected,
+            suggestion.map(ToOwned::to_owned),
+        )
+    }
+
+    fn with_span(
+        input: &str,
+        span: Range<usize>,
+        message: String,
+        expected: Vec<String>,
+        suggestion: Option<String>,
+    ) -> Self {
+        Self {
+            column: input[..span.start].chars().count() + 1,
+            span,
+            message,
+            expected,
+            suggestion,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)?;
+
+        const MAX_EXPECTED: usize = 6;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{suggestion}`?")
+        } else if self.expected.len() == 1 {
+            write!(f, ", expected {}", self.expected[0])
+        } else if !self.expected.is_empty() {
+            write!(f, ", expected one of ")?;
+            for (i, expected) in self.expected.iter().take(MAX_EXPECTED).enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{expected}")?;
+            }
+            if self.expected.len() > MAX_EXPECTED {
+                write!(f, ", ...")?;
+            }
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the hint of a validation error.
+fn hint(invalid: &str) -> Option<&'static str> {
+    match invalid {
+        "invalid ip address" => Some("e.g. 10.0.0.1, 10.0.0.0/8 or 2001:db8::1"),
+        "invalid port number" => Some("ports are 0 to 65535"),
+        "invalid port range" => Some("the first port of a range is the lowest"),
+        _ => None,
+    }
+}
+
+/// Describes an expected token, `None` for the ones the user can't make sense of.
+fn describe(token: &str) -> Option<String> {
+    match token {
+        "EOF" => Some("end of filter".to_string()),
+        "['0'..='9']" => Some("digit".to_string()),
+        token if token.starts_with('"') => Some(format!("`{}`", token.trim_matches('"'))),
+        // Character classes.
+        token if token.starts_with('[') => None,
+        // Named tokens, e.g. host name.
+        token => Some(token.to_string()),
+    }
+}
+
+/// Returns the keywords of the language, e.g. `rport`.
+fn keywords() -> Vec<&'static str> {
+    // The tokens expected before an operand and after an operand.
+    ["", "tcp "]
+        .into_iter()
+        .filter_map(|input| parser::filter(input).err())
+        .flat_map(|err| err.expected.tokens().collect::<Vec<_>>())
+        .collect()
+}
+
+/// Returns the keyword closest to the misspelt `word` amongst the `tokens`.
+fn suggest<'a>(word: &str, tokens: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_ascii_lowercase();
+    let max_distance = if word.len() <= 4 { 1 } else { 2 };
+
+    let keywords: Vec<&str> = tokens
+        .filter(|token| token.starts_with('"'))
+        .map(|token| token.trim_matches('"').trim_end_matches('['))
+        .filter(|keyword| {
+            keyword
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .collect();
+
+    keywords
+        .iter()
+        .map(|keyword| (distance(&word, keyword), *keyword))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
+        .or_else(|| {
+            // A keyword cut short, e.g. `estab`.
+            keywords
+                .iter()
+                .find(|keyword| word.len() >= 2 && keyword.starts_with(word.as_str()))
+                .copied()
+        })
+        .filter(|keyword| *keyword != word)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[inline]
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | ':' | '/' | '*' | '?' | '-')
+}
+
+/// Returns the span of the word around `offset`, empty if there is none.
+fn word(input: &str, offset: usize) -> Range<usize> {
+    let start = input[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+
+    if input[offset..].chars().next().map(is_word_char) != Some(true) {
+        return offset..offset;
+    }
+
+    let end = input[offset..]
+        .find(|c| !is_word_char(c))
+        .map_or(input.len(), |i| offset + i);
+
+    start..end
+}
+
+/// Returns the start of the value ending at `offset`, e.g. a port, an address or a quoted
+/// regular expression.
+fn value_start(input: &str, offset: usize) -> usize {
+    let value = &input[..offset];
+    if let Some(quoted) = value.strip_suffix('"') {
+        return quoted.rfind('"').unwrap_or(0);
+    }
+
+    value
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| !matches!(c, '[' | ',' | '|' | ' ' | '\t' | '='))
+        .last()
+        .map_or(offset, |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::Interpretor;
+
+    use super::*;
+
+    fn error(input: &str) -> Error {
+        Interpretor::parse(input).unwrap_err()
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = error("tcp and rprt[443]");
+        assert_eq!(err.span, 8..12);
+        assert_eq!(err.suggestion.as_deref(), Some("rport"));
+        assert_eq!(
+            err.to_string(),
+            "column 9: unexpected `rprt`, did you mean `rport`?"
+        );
+
+        let err = error("udpp");
+        assert_eq!(err.span, 0..4);
+        assert_eq!(err.suggestion.as_deref(), Some("udp"));
+
+        let err = error("tcp an udp");
+        assert_eq!(err.span, 4..6);
+        assert_eq!(err.suggestion.as_deref(), Some("and"));
+
+        assert_eq!(
+            error("remote privat").suggestion.as_deref(),
+            Some("private")
+        );
+        assert_eq!(
+            error("state[estab]").suggestion.as_deref(),
+            Some("established")
+        );
+
+        let err = error("type[foo]");
+        assert_eq!(err.span, 5..8);
+        assert_eq!(err.suggestion, None);
+        assert_eq!(
+            err.to_string(),
+            "column 6: unexpected `foo`, expected one of `dccp`, `dgram`, `packet`, `raw`, \
+             `rdm`, `seqpacket`, ..."
+        );
+
+        let err = error("rport[70000]");
+        assert_eq!(err.span, 6..11);
+        assert_eq!(
+            err.to_string(),
+            "column 7: invalid port number, ports are 0 to 65535"
+        );
+        assert_eq!(error("laddr[10.0.0.1, 1.2.3]").span, 16..21);
+        assert_eq!(error("lport[80-20]").span, 6..11);
+        assert_eq!(error("comm =~ \"[\" and tcp").span, 8..11);
+
+        let err = error("(tcp");
+        assert_eq!(err.span, 4..4);
+        assert_eq!(
+            err.to_string(),
+            "column 5: unexpected end of filter, expected one of `&&`, `)`, `and`, `or`, `||`"
+        );
+        let err = error("tcp)");
+        assert_eq!(err.span, 3..4);
+        assert_eq!(err.message, "unexpected `)`");
+
+        assert_eq!(
+            error("host[]").to_string(),
+            "column 6: unexpected `]`, expected host name"
+        );
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("rprt", "rport"), 1);
+        assert_eq!(distance("rprt", "port"), 2);
+        assert_eq!(distance("", "tcp"), 3);
+        assert_eq!(distance("tcp", "tcp"), 0);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+}
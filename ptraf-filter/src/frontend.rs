@@ -1,4 +1,12 @@
-use std::net::IpAddr;
+use std::{
+    hash::{Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+};
+
+use regex::Regex;
+
+use crate::Traffic;
 
 /// Protocol.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -7,6 +15,35 @@ pub enum Protocol {
     Udp,
 }
 
+/// Socket type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SockType {
+    Stream,
+    Dgram,
+    Raw,
+    Rdm,
+    Seqpacket,
+    Dccp,
+    Packet,
+}
+
+/// State of a TCP socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TcpState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+    NewSynRecv,
+}
+
 /// The IP version.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IpVersion {
@@ -14,8 +51,220 @@ pub enum IpVersion {
     IpV6,
 }
 
+/// An IP network, e.g. `10.0.0.0/8`.
+///
+/// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) are handled as IPv4 addresses, both in the
+/// networks and in the addresses they are matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns the network of the `addr` with a prefix of `prefix_len` bits, the bits of the
+    /// host are cleared. Returns `None` if the prefix is longer than the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let (addr, prefix_len) = match addr {
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) if prefix_len >= 96 => (IpAddr::V4(v4), prefix_len - 96),
+                _ => (addr, prefix_len),
+            },
+            IpAddr::V4(_) => (addr, prefix_len),
+        };
+
+        let addr = match addr {
+            IpAddr::V4(v4) if prefix_len <= 32 => {
+                Ipv4Addr::from(u32::from(v4) & mask::<u32>(prefix_len, 32)).into()
+            }
+            IpAddr::V6(v6) if prefix_len <= 128 => {
+                Ipv6Addr::from(u128::from(v6) & mask::<u128>(prefix_len, 128)).into()
+            }
+            _ => return None,
+        };
+
+        Some(Self { addr, prefix_len })
+    }
+
+    /// Returns the network of the single address `addr`.
+    pub fn host(addr: IpAddr) -> Self {
+        let addr = canonical(addr);
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix_len }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, canonical(*addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                u32::from(addr) & mask::<u32>(self.prefix_len, 32) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                u128::from(addr) & mask::<u128>(self.prefix_len, 128) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A class of IP addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddrClass {
+    /// `127.0.0.0/8` and `::1`.
+    Loopback,
+    /// `10.0.0.0/8`, `172.16.0.0/12`, `192.168.0.0/16` and `fc00::/7`.
+    Private,
+    /// `169.254.0.0/16` and `fe80::/10`.
+    LinkLocal,
+    /// `224.0.0.0/4` and `ff00::/8`.
+    Multicast,
+    /// None of the other classes.
+    Public,
+    /// `0.0.0.0` and `::`.
+    Unspecified,
+}
+
+impl AddrClass {
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = canonical(*addr);
+        match self {
+            AddrClass::Loopback => addr.is_loopback(),
+            AddrClass::Private => match addr {
+                IpAddr::V4(v4) => v4.is_private(),
+                IpAddr::V6(v6) => v6.segments()[0] & 0xfe00 == 0xfc00,
+            },
+            AddrClass::LinkLocal => match addr {
+                IpAddr::V4(v4) => v4.is_link_local(),
+                IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 == 0xfe80,
+            },
+            AddrClass::Multicast => addr.is_multicast(),
+            AddrClass::Public => [
+                AddrClass::Loopback,
+                AddrClass::Private,
+                AddrClass::LinkLocal,
+                AddrClass::Multicast,
+                AddrClass::Unspecified,
+            ]
+            .iter()
+            .all(|class| !class.contains(&addr)),
+            AddrClass::Unspecified => addr.is_unspecified(),
+        }
+    }
+}
+
+/// Returns the IPv4 address of an IPv4-mapped IPv6 address, the address otherwise.
+pub fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// Returns a mask of the `prefix_len` most significant bits of a `bits` wide integer.
+fn mask<T>(prefix_len: u8, bits: u8) -> T
+where
+    T: From<u8> + std::ops::Not<Output = T> + std::ops::Shl<u32, Output = T>,
+{
+    if prefix_len == 0 {
+        T::from(0)
+    } else {
+        !T::from(0) << u32::from(bits - prefix_len)
+    }
+}
+
+/// A pattern matched against the names and the command lines of the processes.
+///
+/// Globs match the whole text, `*` matching any sequence of characters and `?` any character.
+/// Regular expressions match anywhere in the text unless anchored.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn glob(glob: &str) -> Self {
+        let mut pattern = String::with_capacity(glob.len() + 2);
+        pattern.push('^');
+        for c in glob.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        pattern.push('$');
+
+        Self {
+            regex: Regex::new(&pattern).expect("escaped glob"),
+        }
+    }
+
+    pub fn regex(regex: &str) -> Result<Self, regex::Error> {
+        Regex::new(regex).map(|regex| Self { regex })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Returns the regular expression of the pattern.
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+/// A pattern matched against the host names, case insensitive.
+///
+/// Either a glob, e.g. `*.amazonaws.com`, or a host name, e.g. `db-primary`, also matching
+/// the addresses the name resolves to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HostPattern {
+    name: Option<String>,
+    pattern: Pattern,
+}
+
+impl HostPattern {
+    pub fn new(glob: &str) -> Self {
+        let glob = glob.trim_end_matches('.').to_ascii_lowercase();
+        Self {
+            name: (!glob.contains(['*', '?'])).then(|| glob.clone()),
+            pattern: Pattern::glob(&glob),
+        }
+    }
+
+    /// Returns the host name to resolve, if the pattern has no wildcard.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_match(&self, host_name: &str) -> bool {
+        self.pattern
+            .is_match(&host_name.trim_end_matches('.').to_ascii_lowercase())
+    }
+}
+
 // TODO(gwik): Make it no_std to push down filter to BPF
-// TODO(gwik): Add netmask support
 
 /// The expression of the filter language.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -25,14 +274,52 @@ pub enum Expr {
     Protocol(Protocol),
     IpVersion(IpVersion),
 
+    /// Any of the socket types, e.g. `type[raw|packet]`.
+    SockType(Vec<SockType>),
+    /// Any of the TCP states, e.g. `state[established|time_wait]`.
+    State(Vec<TcpState>),
+
     Addr(IpAddr),
     LocalAddr(IpAddr),
     RemoteAddr(IpAddr),
 
+    /// Any of the networks, for the address lists and the CIDR notation.
+    AddrIn(Vec<Cidr>),
+    LocalAddrIn(Vec<Cidr>),
+    RemoteAddrIn(Vec<Cidr>),
+
+    /// Address class keyword, e.g. `remote private`.
+    AddrClass(AddrClass),
+    LocalAddrClass(AddrClass),
+    RemoteAddrClass(AddrClass),
+
     Port(u16),
     LocalPort(u16),
     RemotePort(u16),
 
+    /// In any of the ranges, for the port lists, ranges and comparisons.
+    PortIn(Vec<RangeInclusive<u16>>),
+    LocalPortIn(Vec<RangeInclusive<u16>>),
+    RemotePortIn(Vec<RangeInclusive<u16>>),
+
+    Uid(u32),
+    User(String),
+    Gid(u32),
+    Group(String),
+
+    Comm(Pattern),
+    Exe(Pattern),
+    Cmdline(Pattern),
+
+    /// Host name of the remote address (`host` and `rhost`) or of the local one (`lhost`),
+    /// e.g. `rhost[*.amazonaws.com]`.
+    Host(HostPattern),
+    LocalHost(HostPattern),
+    RemoteHost(HostPattern),
+
+    /// Traffic threshold, e.g. `rate > 1MiB`.
+    Traffic(Metric, Cmp, u64),
+
     And(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
     Not(Box<Expr>),
@@ -44,13 +331,107 @@ macro_rules! b {
     };
 }
 
+/// Comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    /// Returns `a <cmp> b`.
+    pub fn eval<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Cmp::Eq => a == b,
+            Cmp::Ne => a != b,
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+        }
+    }
+}
+
+/// A quantity of traffic, in both directions unless received (rx) or sent (tx) only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    /// Bytes per second.
+    Rate,
+    RxRate,
+    TxRate,
+    Bytes,
+    RxBytes,
+    TxBytes,
+    Packets,
+    RxPackets,
+    TxPackets,
+}
+
+impl Metric {
+    pub fn value(self, traffic: &Traffic) -> f64 {
+        match self {
+            Metric::Rate => traffic.rx_rate + traffic.tx_rate,
+            Metric::RxRate => traffic.rx_rate,
+            Metric::TxRate => traffic.tx_rate,
+            Metric::Bytes => (traffic.rx_bytes + traffic.tx_bytes) as f64,
+            Metric::RxBytes => traffic.rx_bytes as f64,
+            Metric::TxBytes => traffic.tx_bytes as f64,
+            Metric::Packets => (traffic.rx_packets + traffic.tx_packets) as f64,
+            Metric::RxPackets => traffic.rx_packets as f64,
+            Metric::TxPackets => traffic.tx_packets as f64,
+        }
+    }
+}
+
+/// Returns the multiplier of a unit prefix, binary if `binary`, e.g. `Ki`, decimal otherwise.
+fn unit_scale(prefix: &str, binary: bool) -> f64 {
+    let exp = match prefix {
+        "k" | "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        _ => 4,
+    };
+    if binary {
+        1024f64.powi(exp)
+    } else {
+        1000f64.powi(exp)
+    }
+}
+
+/// Returns the expression of `port <cmp> n`, given the expressions of a single port and of
+/// a list of port ranges.
+fn port_cmp(
+    cmp: Cmp,
+    n: u16,
+    single: fn(u16) -> Expr,
+    ranges: fn(Vec<RangeInclusive<u16>>) -> Expr,
+) -> Expr {
+    // Empty if there is no port on this side of `n`.
+    #[allow(clippy::reversed_empty_ranges)]
+    let empty = 1..=0;
+
+    match cmp {
+        Cmp::Eq => single(n),
+        Cmp::Ne => Expr::Not(b!(single(n))),
+        Cmp::Lt => ranges(vec![n.checked_sub(1).map_or(empty, |n| 0..=n)]),
+        Cmp::Le => ranges(vec![0..=n]),
+        Cmp::Gt => ranges(vec![n.checked_add(1).map_or(empty, |n| n..=u16::MAX)]),
+        Cmp::Ge => ranges(vec![n..=u16::MAX]),
+    }
+}
+
 peg::parser!(pub grammar parser() for str {
 
     pub rule filter() -> Expr
         = logic()
 
     rule operand() -> Expr
-        = pid() / udp() / tcp() / ipv4() / ipv6() / ports() / addrs()
+        = pid() / udp() / tcp() / ipv4() / ipv6() / ports() / addrs() / users() / processes()
+        / traffic() / sock_type() / state() / hosts()
 
     rule pid() -> Expr
         = _ "pid[" n:$(['0'..='9']+) "]" _ {? n.parse::<u32>().or(Err("invalid pid number")).map(Expr::Pid) }
@@ -67,36 +448,193 @@ peg::parser!(pub grammar parser() for str {
     rule ipv6() -> Expr
         = _ "ipv6" _ { Expr::IpVersion(IpVersion::IpV6) }
 
+    rule sock_type() -> Expr
+        = _ "type[" l:(_ t:sock_type_name() _ { t }) ++ "|" "]" _ { Expr::SockType(l) }
+
+    rule sock_type_name() -> SockType
+        = "stream" { SockType::Stream }
+        / "dgram" { SockType::Dgram }
+        / "raw" { SockType::Raw }
+        / "rdm" { SockType::Rdm }
+        / "seqpacket" { SockType::Seqpacket }
+        / "dccp" { SockType::Dccp }
+        / "packet" { SockType::Packet }
+
+    rule state() -> Expr
+        = _ "state[" l:(_ s:state_name() _ { s }) ++ "|" "]" _ { Expr::State(l) }
+
+    rule state_name() -> TcpState
+        = "established" { TcpState::Established }
+        / "syn_sent" { TcpState::SynSent }
+        / "syn_recv" { TcpState::SynRecv }
+        / "fin_wait1" { TcpState::FinWait1 }
+        / "fin_wait2" { TcpState::FinWait2 }
+        / "time_wait" { TcpState::TimeWait }
+        / "close_wait" { TcpState::CloseWait }
+        / "closing" { TcpState::Closing }
+        / "close" { TcpState::Close }
+        / "last_ack" { TcpState::LastAck }
+        / "listen" { TcpState::Listen }
+        / "new_syn_recv" { TcpState::NewSynRecv }
+
     rule ports() -> Expr
         = port() / local_port() / remote_port()
 
     rule port() -> Expr
         = _ "port[" n:port_number() "]" _ { Expr::Port(n) }
+        / _ "port[" l:port_list() "]" _ { Expr::PortIn(l) }
+        / _ "port" _ c:cmp() _ n:port_number() _ { port_cmp(c, n, Expr::Port, Expr::PortIn) }
 
     rule local_port() -> Expr
         = _ "lport[" n:port_number() "]" _ { Expr::LocalPort(n) }
+        / _ "lport[" l:port_list() "]" _ { Expr::LocalPortIn(l) }
+        / _ "lport" _ c:cmp() _ n:port_number() _ {
+            port_cmp(c, n, Expr::LocalPort, Expr::LocalPortIn)
+        }
 
     rule remote_port() -> Expr
         = _ "rport[" n:port_number() "]" _ { Expr::RemotePort(n) }
+        / _ "rport[" l:port_list() "]" _ { Expr::RemotePortIn(l) }
+        / _ "rport" _ c:cmp() _ n:port_number() _ {
+            port_cmp(c, n, Expr::RemotePort, Expr::RemotePortIn)
+        }
+
+    rule port_list() -> Vec<RangeInclusive<u16>>
+        = l:(_ r:port_range() _ { r }) ++ "," { l }
+
+    rule port_range() -> RangeInclusive<u16>
+        = a:port_number() _ "-" _ b:port_number() {?
+            if a <= b { Ok(a..=b) } else { Err("invalid port range") }
+        }
+        / n:port_number() { n..=n }
 
     rule port_number() -> u16
         = n:$(['0'..='9']+) {? n.parse::<u16>().or(Err("invalid port number")) }
 
+    rule cmp() -> Cmp
+        = "<=" { Cmp::Le }
+        / ">=" { Cmp::Ge }
+        / "<" { Cmp::Lt }
+        / ">" { Cmp::Gt }
+        / "!=" { Cmp::Ne }
+        / "==" { Cmp::Eq }
+        / "=" { Cmp::Eq }
+
     rule addrs() -> Expr
-        = addr() / local_addr() / remote_addr()
+        = addr() / local_addr() / remote_addr() / addr_classes()
 
     rule addr() -> Expr
         = _ "addr[" n:addr_any() "]" _ { Expr::Addr(n) }
+        / _ "addr[" n:cidr_list() "]" _ { Expr::AddrIn(n) }
 
     rule local_addr() -> Expr
         = _ "laddr[" n:addr_any() "]" _ { Expr::LocalAddr(n) }
+        / _ "laddr[" n:cidr_list() "]" _ { Expr::LocalAddrIn(n) }
 
     rule remote_addr() -> Expr
         = _ "raddr[" n:addr_any() "]" _ { Expr::RemoteAddr(n) }
+        / _ "raddr[" n:cidr_list() "]" _ { Expr::RemoteAddrIn(n) }
+
+    rule addr_classes() -> Expr
+        = _ "local" __ c:addr_class() _ { Expr::LocalAddrClass(c) }
+        / _ "remote" __ c:addr_class() _ { Expr::RemoteAddrClass(c) }
+        / _ c:addr_class() _ { Expr::AddrClass(c) }
+
+    rule addr_class() -> AddrClass
+        = "loopback" { AddrClass::Loopback }
+        / "private" { AddrClass::Private }
+        / "link_local" { AddrClass::LinkLocal }
+        / "multicast" { AddrClass::Multicast }
+        / "public" { AddrClass::Public }
+        / "any" { AddrClass::Unspecified }
+
+    rule cidr_list() -> Vec<Cidr>
+        = l:(_ c:cidr() _ { c }) ++ "," { l }
+
+    rule cidr() -> Cidr
+        = a:addr_any() "/" n:$(['0'..='9']*<1,3>) {?
+            n.parse::<u8>().ok().and_then(|n| Cidr::new(a, n)).ok_or("invalid prefix length")
+        }
+        / a:addr_any() { Cidr::host(a) }
 
     rule addr_any() -> IpAddr
         = n:$(['0'..='9' | 'a'..='f' | 'A'..='F' | ':' | '.' ]+) {? n.parse::<IpAddr>().or(Err("invalid ip address")).map(Into::into) }
 
+    rule users() -> Expr
+        = uid() / user() / gid() / group()
+
+    rule uid() -> Expr
+        = _ "uid[" n:id() "]" _ { Expr::Uid(n) }
+
+    rule user() -> Expr
+        = _ "user[" n:name() "]" _ { Expr::User(n) }
+
+    rule gid() -> Expr
+        = _ "gid[" n:id() "]" _ { Expr::Gid(n) }
+
+    rule group() -> Expr
+        = _ "group[" n:name() "]" _ { Expr::Group(n) }
+
+    rule processes() -> Expr
+        = _ "comm" p:pattern() _ { Expr::Comm(p) }
+        / _ "exe" p:pattern() _ { Expr::Exe(p) }
+        / _ "cmdline" p:pattern() _ { Expr::Cmdline(p) }
+
+    /// A glob in brackets, e.g. `[python*]`, or a regular expression, e.g. `=~ "^nginx: "`.
+    rule pattern() -> Pattern
+        = "[" g:$((!"]" [_])+) "]" { Pattern::glob(g) }
+        / _ "=~" _ "\"" r:regex_literal() "\"" {? Pattern::regex(&r).or(Err("invalid regex")) }
+
+    rule regex_literal() -> String
+        = s:(("\\\"" { '"' }) / (!"\"" c:[_] { c }))* { s.into_iter().collect() }
+
+    rule traffic() -> Expr
+        = _ m:metric() _ c:cmp() _ n:quantity() _ { Expr::Traffic(m, c, n) }
+        / _ "rx" _ { Expr::Traffic(Metric::RxBytes, Cmp::Gt, 0) }
+        / _ "tx" _ { Expr::Traffic(Metric::TxBytes, Cmp::Gt, 0) }
+
+    rule metric() -> Metric
+        = "rate" { Metric::Rate }
+        / "rxrate" { Metric::RxRate }
+        / "txrate" { Metric::TxRate }
+        / "bytes" { Metric::Bytes }
+        / "rxbytes" { Metric::RxBytes }
+        / "txbytes" { Metric::TxBytes }
+        / "packets" { Metric::Packets }
+        / "rxpackets" { Metric::RxPackets }
+        / "txpackets" { Metric::TxPackets }
+        / "rx" { Metric::RxBytes }
+        / "tx" { Metric::TxBytes }
+
+    /// A number with an optional unit, e.g. `100k`, `1.5MiB` or `10MB/s`.
+    rule quantity() -> u64
+        = n:$(['0'..='9']+ ("." ['0'..='9']+)?) s:unit() "/s"? {?
+            n.parse::<f64>()
+                .ok()
+                .map(|n| n * s)
+                .filter(|n| *n <= u64::MAX as f64)
+                .map(|n| n.round() as u64)
+                .ok_or("invalid quantity")
+        }
+
+    rule unit() -> f64
+        = p:$(['k' | 'K' | 'M' | 'G' | 'T']) i:"i"? "B"? { unit_scale(p, i.is_some()) }
+        / "B"? { 1.0 }
+
+    rule hosts() -> Expr
+        = _ "host" h:host_pattern() _ { Expr::Host(h) }
+        / _ "lhost" h:host_pattern() _ { Expr::LocalHost(h) }
+        / _ "rhost" h:host_pattern() _ { Expr::RemoteHost(h) }
+
+    rule host_pattern() -> HostPattern
+        = "[" g:(quiet!{ $((!"]" [_])+) } / expected!("host name")) "]" { HostPattern::new(g) }
+
+    rule id() -> u32
+        = n:$(['0'..='9']+) {? n.parse::<u32>().or(Err("invalid id")) }
+
+    rule name() -> String
+        = n:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' | '$']+) { n.to_string() }
+
     rule logic() -> Expr = precedence!{
         a:(@) _ ("or" / "||") _ b:@ { Expr::Or(b!(a), b!(b)) }
         a:(@) _ ("and" / "&&") _ b:@ { Expr::And(b!(a), b!(b)) }
@@ -109,6 +647,8 @@ peg::parser!(pub grammar parser() for str {
     }
 
     rule _() =  quiet!{[' ' | '\t']*}
+
+    rule __() =  quiet!{[' ' | '\t']+}
 });
 
 #[cfg(test)]
@@ -154,6 +694,14 @@ mod tests {
 
         assert_error!("port[1239921232]", 16);
 
+        assert_parse!("uid[1000]", Expr::Uid(1000));
+        assert_parse!("user[build-bot]", Expr::User("build-bot".to_string()));
+        assert_parse!("gid[0]", Expr::Gid(0));
+        assert_parse!("group[docker]", Expr::Group("docker".to_string()));
+
+        assert_error!("uid[alice]", 5);
+        assert_error!("user[]", 6);
+
         let ex_v6_addr = "1050:0:0:0:5:600:300c:326b".parse::<IpAddr>().unwrap();
 
         assert_parse!(
@@ -179,6 +727,254 @@ mod tests {
         );
     }
 
+    #[test]
+    fn processes() {
+        assert_parse!("comm[nginx]", Expr::Comm(Pattern::glob("nginx")));
+        assert_parse!(
+            "exe[/usr/bin/python3*]",
+            Expr::Exe(Pattern::glob("/usr/bin/python3*"))
+        );
+        assert_parse!(
+            "cmdline[*manage.py runserver*]",
+            Expr::Cmdline(Pattern::glob("*manage.py runserver*"))
+        );
+        assert_parse!(
+            r#"cmdline =~ "--config[= ]\"?/etc""#,
+            Expr::Cmdline(Pattern::regex(r#"--config[= ]"?/etc"#).unwrap())
+        );
+        assert_parse!(
+            "comm=~\"^(nginx|haproxy)$\" and tcp",
+            Expr::And(
+                b!(Expr::Comm(Pattern::regex("^(nginx|haproxy)$").unwrap())),
+                b!(Expr::Protocol(Protocol::Tcp))
+            )
+        );
+
+        assert_error!("comm[]", 5);
+        assert_error!("cmdline =~ \"(\"", 15);
+
+        let glob = Pattern::glob("py?hon*.[0-9]");
+        assert!(glob.is_match("python3.[0-9]"));
+        assert!(!glob.is_match("python3.9"));
+        assert!(!glob.is_match("/usr/bin/python3.[0-9]"));
+        assert!(Pattern::regex("gunicorn")
+            .unwrap()
+            .is_match("/usr/bin/gunicorn app:app"));
+    }
+
+    #[test]
+    fn hosts() {
+        assert_parse!(
+            "host[*.amazonaws.com]",
+            Expr::Host(HostPattern::new("*.amazonaws.com"))
+        );
+        assert_parse!(
+            "rhost[db-primary] or lhost[web-?]",
+            Expr::Or(
+                b!(Expr::RemoteHost(HostPattern::new("db-primary"))),
+                b!(Expr::LocalHost(HostPattern::new("web-?")))
+            )
+        );
+
+        assert_error!("rhost[]", 7);
+
+        let host = HostPattern::new("*.Amazonaws.com.");
+        assert_eq!(None, host.name());
+        assert!(host.is_match("ec2-1-2-3-4.compute-1.amazonaws.com."));
+        assert!(!host.is_match("amazonaws.com"));
+
+        let host = HostPattern::new("DB-primary");
+        assert_eq!(Some("db-primary"), host.name());
+        assert!(host.is_match("db-Primary"));
+        assert!(!host.is_match("db-primary.internal"));
+    }
+
+    #[test]
+    fn port_ranges() {
+        assert_parse!("rport[1024-65535]", Expr::RemotePortIn(vec![1024..=65535]));
+        assert_parse!(
+            "lport[80,443, 8000 - 8080]",
+            Expr::LocalPortIn(vec![80..=80, 443..=443, 8000..=8080])
+        );
+        assert_parse!("port[22,22]", Expr::PortIn(vec![22..=22, 22..=22]));
+
+        assert_parse!("rport < 1024", Expr::RemotePortIn(vec![0..=1023]));
+        assert_parse!("rport<=1024", Expr::RemotePortIn(vec![0..=1024]));
+        assert_parse!("lport > 32767", Expr::LocalPortIn(vec![32768..=65535]));
+        assert_parse!("port >= 80", Expr::PortIn(vec![80..=65535]));
+        assert_parse!("lport = 80", Expr::LocalPort(80));
+        assert_parse!("rport == 80", Expr::RemotePort(80));
+        assert_parse!("port != 22", Expr::Not(b!(Expr::Port(22))));
+        assert_parse!(
+            "rport < 1024 and tcp",
+            Expr::And(
+                b!(Expr::RemotePortIn(vec![0..=1023])),
+                b!(Expr::Protocol(Protocol::Tcp))
+            )
+        );
+
+        // Nothing is on the other side of the bounds.
+        match parser::filter("rport < 0") {
+            Ok(Expr::RemotePortIn(ranges)) => assert!(ranges[0].is_empty()),
+            res => panic!("unexpected {res:?}"),
+        }
+
+        assert_error!("rport[1024-80]", 14);
+        assert_error!("rport[80-70000]", 15);
+        assert_error!("rport < 70000", 14);
+        assert_error!("rport <", 8);
+    }
+
+    #[test]
+    fn traffic() {
+        assert_parse!(
+            "bytes > 100k",
+            Expr::Traffic(Metric::Bytes, Cmp::Gt, 100_000)
+        );
+        assert_parse!(
+            "rate > 1MiB and tx",
+            Expr::And(
+                b!(Expr::Traffic(Metric::Rate, Cmp::Gt, 1_048_576)),
+                b!(Expr::Traffic(Metric::TxBytes, Cmp::Gt, 0))
+            )
+        );
+        assert_parse!(
+            "txrate>=1.5MB/s",
+            Expr::Traffic(Metric::TxRate, Cmp::Ge, 1_500_000)
+        );
+        assert_parse!(
+            "rxpackets < 10",
+            Expr::Traffic(Metric::RxPackets, Cmp::Lt, 10)
+        );
+        assert_parse!(
+            "rx != 2GiB",
+            Expr::Traffic(Metric::RxBytes, Cmp::Ne, 2 << 30)
+        );
+        assert_parse!("rxbytes = 12B", Expr::Traffic(Metric::RxBytes, Cmp::Eq, 12));
+        assert_parse!(
+            "not rx",
+            Expr::Not(b!(Expr::Traffic(Metric::RxBytes, Cmp::Gt, 0)))
+        );
+
+        assert_error!("bytes > 1X", 10);
+        assert_error!("rate >", 7);
+        assert_error!("rate > 100000000000000000T", 27);
+    }
+
+    #[test]
+    fn networks() {
+        let cidr = |s: &str| {
+            let (addr, prefix_len) = s.split_once('/').unwrap();
+            Cidr::new(addr.parse().unwrap(), prefix_len.parse().unwrap()).unwrap()
+        };
+
+        assert_parse!(
+            "raddr[10.0.0.0/8]",
+            Expr::RemoteAddrIn(vec![cidr("10.0.0.0/8")])
+        );
+        assert_parse!("addr[fe80::/10]", Expr::AddrIn(vec![cidr("fe80::/10")]));
+        assert_parse!(
+            "laddr[10.0.0.1, 192.168.0.0/16]",
+            Expr::LocalAddrIn(vec![cidr("10.0.0.1/32"), cidr("192.168.0.0/16")])
+        );
+
+        assert_error!("raddr[10.0.0.0/33]", 18);
+        assert_error!("raddr[10.0.0.0/]", 16);
+        assert_error!("raddr[10.0.0.0,]", 16);
+
+        // The bits of the host are cleared.
+        assert_eq!(cidr("10.1.2.3/8"), cidr("10.0.0.0/8"));
+        // IPv4-mapped networks are IPv4 networks.
+        assert_eq!(cidr("::ffff:10.0.0.0/104"), cidr("10.0.0.0/8"));
+
+        let net = cidr("10.0.0.0/8");
+        assert!(net.contains(&"10.255.0.1".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.1.1".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(!net.contains(&"::a01:101".parse().unwrap()));
+
+        let net = cidr("fe80::/10");
+        assert!(net.contains(&"fe80::1".parse().unwrap()));
+        assert!(net.contains(&"febf::1".parse().unwrap()));
+        assert!(!net.contains(&"fec0::1".parse().unwrap()));
+
+        assert!(cidr("0.0.0.0/0").contains(&"1.1.1.1".parse().unwrap()));
+        assert!(!cidr("0.0.0.0/0").contains(&"::1".parse().unwrap()));
+        assert!(Cidr::host("::ffff:1.1.1.1".parse().unwrap()).contains(&"1.1.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn socket_types_and_states() {
+        assert_parse!("type[raw]", Expr::SockType(vec![SockType::Raw]));
+        assert_parse!(
+            "type[ seqpacket | dccp ]",
+            Expr::SockType(vec![SockType::Seqpacket, SockType::Dccp])
+        );
+        assert_parse!(
+            "state[established|time_wait|listen]",
+            Expr::State(vec![
+                TcpState::Established,
+                TcpState::TimeWait,
+                TcpState::Listen
+            ])
+        );
+        assert_parse!(
+            "state[close|close_wait|closing] and tcp",
+            Expr::And(
+                b!(Expr::State(vec![
+                    TcpState::Close,
+                    TcpState::CloseWait,
+                    TcpState::Closing
+                ])),
+                b!(Expr::Protocol(Protocol::Tcp))
+            )
+        );
+
+        assert_error!("type[]", 6);
+        assert_error!("type[tcp]", 6);
+        assert_error!("state[established|]", 19);
+        assert_error!("state[closed]", 12);
+    }
+
+    #[test]
+    fn address_classes() {
+        assert_parse!("private", Expr::AddrClass(AddrClass::Private));
+        assert_parse!(
+            "remote public and not local loopback",
+            Expr::And(
+                b!(Expr::RemoteAddrClass(AddrClass::Public)),
+                b!(Expr::Not(b!(Expr::LocalAddrClass(AddrClass::Loopback))))
+            )
+        );
+        assert_parse!("local any", Expr::LocalAddrClass(AddrClass::Unspecified));
+        assert_parse!(
+            "remote  link_local",
+            Expr::RemoteAddrClass(AddrClass::LinkLocal)
+        );
+
+        assert_error!("remoteprivate", 1);
+        assert_error!("remote home", 8);
+
+        let is = |class: AddrClass, addr: &str| class.contains(&addr.parse().unwrap());
+
+        assert!(is(AddrClass::Loopback, "127.0.0.53"));
+        assert!(is(AddrClass::Loopback, "::1"));
+        assert!(is(AddrClass::Loopback, "::ffff:127.0.0.1"));
+        assert!(is(AddrClass::Private, "172.31.255.1"));
+        assert!(is(AddrClass::Private, "fd12:3456::1"));
+        assert!(!is(AddrClass::Private, "172.32.0.1"));
+        assert!(is(AddrClass::LinkLocal, "169.254.169.254"));
+        assert!(is(AddrClass::LinkLocal, "fe80::1"));
+        assert!(is(AddrClass::Multicast, "239.255.255.250"));
+        assert!(is(AddrClass::Multicast, "ff02::fb"));
+        assert!(is(AddrClass::Unspecified, "0.0.0.0"));
+        assert!(is(AddrClass::Unspecified, "::"));
+        assert!(is(AddrClass::Public, "1.1.1.1"));
+        assert!(is(AddrClass::Public, "2606:4700::1111"));
+        assert!(!is(AddrClass::Public, "::ffff:10.0.0.1"));
+        assert!(!is(AddrClass::Public, "::1"));
+    }
+
     #[test]
     fn logical_operators() {
         assert_parse!(
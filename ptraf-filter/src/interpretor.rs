@@ -1,72 +1,241 @@
-pub use peg::{error::ParseError, str::LineCol};
+use std::{
+    net::IpAddr,
+    ops::{Deref, RangeInclusive},
+};
 
 use crate::{
-    frontend::{parser, Expr},
-    Filterable,
+    compiler::Program,
+    frontend::{canonical, parser, AddrClass, Cidr, Expr, HostPattern, Pattern},
+    Error, Filterable, Lookup,
 };
 
-pub type Error = ParseError<LineCol>;
-
 #[derive(Debug, Clone)]
 pub struct Interpretor {
     ast: Expr,
+    program: Program,
 }
 
 impl Interpretor {
     pub fn parse(input: &str) -> Result<Self, Error> {
-        parser::filter(input).map(|expr| Self { ast: expr })
+        parser::filter(input)
+            .map(Self::new)
+            .map_err(|err| Error::new(input, &err))
     }
 
     pub fn new(ast: Expr) -> Self {
-        Self { ast }
+        let program = Program::compile(&ast);
+        Self { ast, program }
     }
 
+    /// Tells whether `f` matches the filter, the unknown predicates don't match.
     pub fn filter<F: Filterable>(&self, f: &F) -> bool {
-        Self::eval(f, &self.ast)
+        self.eval(f) == Some(true)
+    }
+
+    /// Tells whether `f` may match the filter once its unknown predicates are known, e.g. the
+    /// traffic of a bare socket before its traffic is aggregated.
+    pub fn may_match<F: Filterable>(&self, f: &F) -> bool {
+        self.eval(f) != Some(false)
+    }
+
+    /// Evaluates the filter in a three-valued logic, `None` if it is unknown.
+    pub fn eval<F: Filterable>(&self, f: &F) -> Option<bool> {
+        self.program.eval(f)
+    }
+
+    /// Evaluates the filter by walking its expression rather than running its compiled
+    /// program, the reference of the compilation.
+    pub fn walk<F: Filterable>(&self, f: &F) -> Option<bool> {
+        Self::walk_expr(f, &self.ast)
+    }
+
+    /// Tells whether the filter has traffic thresholds, see [Filterable::traffic].
+    pub fn has_traffic(&self) -> bool {
+        fn has_traffic(expr: &Expr) -> bool {
+            match expr {
+                Expr::Traffic(..) => true,
+                Expr::And(a, b) | Expr::Or(a, b) => has_traffic(a) || has_traffic(b),
+                Expr::Not(a) => has_traffic(a),
+                _ => false,
+            }
+        }
+
+        has_traffic(&self.ast)
     }
 
-    fn eval<F: Filterable>(f: &F, o: &Expr) -> bool {
+    fn walk_expr<F: Filterable>(f: &F, o: &Expr) -> Option<bool> {
+        match o {
+            Expr::And(a, b) => Self::and(f, a, b),
+            Expr::Or(a, b) => Self::or(f, a, b),
+            Expr::Not(a) => Self::not(f, a),
+            predicate => Self::predicate(f, predicate),
+        }
+    }
+
+    /// Evaluates the predicate in a three-valued logic, `None` if it is unknown.
+    pub(crate) fn predicate<F: Filterable>(f: &F, o: &Expr) -> Option<bool> {
+        match o {
+            Expr::Traffic(metric, cmp, n) => f
+                .traffic()
+                .map(|traffic| cmp.eval(metric.value(&traffic), *n as f64)),
+            // The process may have exited, or be unreadable without privileges.
+            Expr::Exe(pattern) => f.exe().map(|exe| pattern.is_match(&exe)),
+            Expr::Cmdline(pattern) => f.cmdline().map(|cmdline| pattern.is_match(&cmdline)),
+            Expr::LocalHost(host) => Self::host(f, host, f.local_address()),
+            // The local host is rarely of interest and is looked up for every socket.
+            Expr::Host(host) | Expr::RemoteHost(host) => Self::host(f, host, f.remote_address()),
+            predicate => Some(Self::test(f, predicate)),
+        }
+    }
+
+    /// Tests the predicate, see [Self::predicate] for the ones which may be unknown.
+    pub(crate) fn test<F: Filterable>(f: &F, o: &Expr) -> bool {
         match o {
             Expr::Pid(pid) => f.pid() == *pid,
             Expr::Protocol(p) => f.protocol().map(|fp| fp == *p).unwrap_or(false),
             Expr::IpVersion(v) => f.ip_version() == *v,
-            Expr::Addr(addr) => &f.local_address() == addr || &f.remote_address() == addr,
-            Expr::LocalAddr(addr) => &f.local_address() == addr,
-            Expr::RemoteAddr(addr) => &f.remote_address() == addr,
+            Expr::SockType(types) => f.sock_type().map(|t| types.contains(&t)).unwrap_or(false),
+            Expr::State(states) => f.state().map(|s| states.contains(&s)).unwrap_or(false),
+            Expr::Addr(addr) => {
+                Self::same_addr(f.local_address(), *addr)
+                    || Self::same_addr(f.remote_address(), *addr)
+            }
+            Expr::LocalAddr(addr) => Self::same_addr(f.local_address(), *addr),
+            Expr::RemoteAddr(addr) => Self::same_addr(f.remote_address(), *addr),
+            Expr::AddrIn(nets) => {
+                Self::in_any(nets, &f.local_address()) || Self::in_any(nets, &f.remote_address())
+            }
+            Expr::LocalAddrIn(nets) => Self::in_any(nets, &f.local_address()),
+            Expr::RemoteAddrIn(nets) => Self::in_any(nets, &f.remote_address()),
+            Expr::AddrClass(class) => Self::in_class(class, f),
+            Expr::LocalAddrClass(class) => class.contains(&f.local_address()),
+            Expr::RemoteAddrClass(class) => class.contains(&f.remote_address()),
             Expr::Port(p) => &f.local_port() == p || &f.remote_port() == p,
             Expr::LocalPort(p) => &f.local_port() == p,
             Expr::RemotePort(p) => &f.remote_port() == p,
-            Expr::And(a, b) => Self::and(f, a, b),
-            Expr::Or(a, b) => Self::or(f, a, b),
-            Expr::Not(a) => Self::not(f, a),
+            Expr::PortIn(ranges) => {
+                Self::in_ranges(ranges, f.local_port()) || Self::in_ranges(ranges, f.remote_port())
+            }
+            Expr::LocalPortIn(ranges) => Self::in_ranges(ranges, f.local_port()),
+            Expr::RemotePortIn(ranges) => Self::in_ranges(ranges, f.remote_port()),
+            Expr::Uid(uid) => f.uid() == Some(*uid),
+            Expr::User(name) => f.user_name() == Some(name.as_str()),
+            Expr::Gid(gid) => f.gid() == Some(*gid),
+            Expr::Group(name) => f.group_name() == Some(name.as_str()),
+            Expr::Comm(pattern) => Self::matches(pattern, f.comm()),
+            Expr::Traffic(..)
+            | Expr::Exe(_)
+            | Expr::Cmdline(_)
+            | Expr::Host(_)
+            | Expr::LocalHost(_)
+            | Expr::RemoteHost(_)
+            | Expr::And(..)
+            | Expr::Or(..)
+            | Expr::Not(_) => unreachable!("not a predicate"),
+        }
+    }
+
+    /// Tells whether the addresses are the same, an IPv4-mapped IPv6 address being the same
+    /// as its IPv4 address.
+    #[inline]
+    fn same_addr(a: IpAddr, b: IpAddr) -> bool {
+        canonical(a) == canonical(b)
+    }
+
+    #[inline]
+    fn in_any(nets: &[Cidr], addr: &IpAddr) -> bool {
+        nets.iter().any(|net| net.contains(addr))
+    }
+
+    #[inline]
+    fn in_class<F: Filterable>(class: &AddrClass, f: &F) -> bool {
+        class.contains(&f.local_address()) || class.contains(&f.remote_address())
+    }
+
+    /// Tells whether the `text` is known and matches the `pattern`.
+    #[inline]
+    fn matches(pattern: &Pattern, text: Option<impl Deref<Target = str>>) -> bool {
+        text.map(|text| pattern.is_match(&text)).unwrap_or(false)
+    }
+
+    /// Tells whether the host at `addr` matches, by its name or, for a host name without
+    /// wildcard, by the addresses of the name.
+    fn host<F: Filterable>(f: &F, host: &HostPattern, addr: IpAddr) -> Option<bool> {
+        let by_addr = match host.name() {
+            Some(name) => Self::lookup(f.host_addrs(name), |addrs| {
+                addrs
+                    .iter()
+                    .any(|host_addr| Self::same_addr(*host_addr, addr))
+            }),
+            None => Some(false),
+        };
+
+        Self::any(by_addr, || {
+            Self::lookup(f.host_name(canonical(addr)), |name| host.is_match(&name))
+        })
+    }
+
+    /// Tests the result of the `lookup`, unknown while pending.
+    #[inline]
+    fn lookup<T>(lookup: Lookup<T>, test: impl FnOnce(T) -> bool) -> Option<bool> {
+        match lookup {
+            Lookup::Pending => None,
+            Lookup::NotFound => Some(false),
+            Lookup::Found(value) => Some(test(value)),
         }
     }
 
     #[inline]
-    fn or<F: Filterable>(f: &F, a: &Expr, b: &Expr) -> bool {
-        Self::eval(f, a) || Self::eval(f, b)
+    fn in_ranges(ranges: &[RangeInclusive<u16>], port: u16) -> bool {
+        ranges.iter().any(|range| range.contains(&port))
     }
 
     #[inline]
-    fn and<F: Filterable>(f: &F, a: &Expr, b: &Expr) -> bool {
-        Self::eval(f, a) && Self::eval(f, b)
+    fn or<F: Filterable>(f: &F, a: &Expr, b: &Expr) -> Option<bool> {
+        Self::any(Self::walk_expr(f, a), || Self::walk_expr(f, b))
     }
 
     #[inline]
-    fn not<F: Filterable>(f: &F, a: &Expr) -> bool {
-        !Self::eval(f, a)
+    fn and<F: Filterable>(f: &F, a: &Expr, b: &Expr) -> Option<bool> {
+        match Self::walk_expr(f, a) {
+            Some(false) => Some(false),
+            Some(true) => Self::walk_expr(f, b),
+            None => Self::walk_expr(f, b).filter(|b| !*b),
+        }
+    }
+
+    /// Returns `a or b`, `b` is only evaluated if `a` is not true.
+    #[inline]
+    fn any(a: Option<bool>, b: impl FnOnce() -> Option<bool>) -> Option<bool> {
+        match a {
+            Some(true) => Some(true),
+            Some(false) => b(),
+            None => b().filter(|b| *b),
+        }
+    }
+
+    #[inline]
+    fn not<F: Filterable>(f: &F, a: &Expr) -> Option<bool> {
+        Self::walk_expr(f, a).map(|a| !a)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::{
+        borrow::Cow,
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
 
-    use crate::frontend::{IpVersion, Protocol};
+    use crate::{
+        frontend::{IpVersion, Protocol, SockType, TcpState},
+        Traffic,
+    };
 
     use super::*;
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     struct Packet {
         pid: u32,
         protocol: Protocol,
@@ -75,6 +244,9 @@ mod tests {
         remote_address: IpAddr,
         local_port: u16,
         remote_port: u16,
+        uid: u32,
+        gid: u32,
+        traffic: Option<Traffic>,
     }
 
     impl Filterable for Packet {
@@ -86,6 +258,21 @@ mod tests {
             Some(self.protocol)
         }
 
+        fn sock_type(&self) -> Option<SockType> {
+            Some(match self.protocol {
+                Protocol::Tcp => SockType::Stream,
+                Protocol::Udp => SockType::Dgram,
+            })
+        }
+
+        fn state(&self) -> Option<TcpState> {
+            (self.protocol == Protocol::Tcp).then_some(if self.remote_port == 443 {
+                TcpState::Established
+            } else {
+                TcpState::TimeWait
+            })
+        }
+
         fn ip_version(&self) -> IpVersion {
             self.ip_version
         }
@@ -105,6 +292,63 @@ mod tests {
         fn remote_port(&self) -> u16 {
             self.remote_port
         }
+
+        fn uid(&self) -> Option<u32> {
+            Some(self.uid)
+        }
+
+        fn user_name(&self) -> Option<&str> {
+            match self.uid {
+                0 => Some("root"),
+                1000 => Some("alice"),
+                _ => None,
+            }
+        }
+
+        fn gid(&self) -> Option<u32> {
+            Some(self.gid)
+        }
+
+        fn group_name(&self) -> Option<&str> {
+            (self.gid == 0).then_some("root")
+        }
+
+        fn comm(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(if self.pid == 213 {
+                "nginx"
+            } else {
+                "curl"
+            }))
+        }
+
+        fn exe(&self) -> Option<Arc<str>> {
+            (self.pid == 213).then(|| "/usr/sbin/nginx".into())
+        }
+
+        fn cmdline(&self) -> Option<Arc<str>> {
+            (self.pid == 213).then(|| "nginx: worker process".into())
+        }
+
+        fn host_name(&self, addr: IpAddr) -> Lookup<Arc<str>> {
+            match addr.to_string().as_str() {
+                "1.1.1.1" => Lookup::Found("one.one.one.one".into()),
+                "127.0.0.1" => Lookup::Found("localhost".into()),
+                "10.1.2.3" => Lookup::Pending,
+                _ => Lookup::NotFound,
+            }
+        }
+
+        fn host_addrs(&self, name: &str) -> Lookup<Arc<[IpAddr]>> {
+            match name {
+                "db-primary" => Lookup::Found(vec!["10.1.2.3".parse().unwrap()].into()),
+                "cache" => Lookup::Pending,
+                _ => Lookup::NotFound,
+            }
+        }
+
+        fn traffic(&self) -> Option<Traffic> {
+            self.traffic
+        }
     }
 
     #[test]
@@ -117,6 +361,9 @@ mod tests {
             remote_address: Ipv4Addr::new(1, 1, 1, 1).into(),
             local_port: 12382,
             remote_port: 443,
+            uid: 1000,
+            gid: 1000,
+            traffic: None,
         };
 
         let packet1 = Packet {
@@ -127,6 +374,9 @@ mod tests {
             remote_address: Ipv4Addr::new(1, 1, 1, 1).into(),
             local_port: 12382,
             remote_port: 8443,
+            uid: 0,
+            gid: 0,
+            traffic: None,
         };
 
         let interpretor =
@@ -135,5 +385,299 @@ mod tests {
 
         assert!(interpretor.filter(&packet0));
         assert!(!interpretor.filter(&packet1));
+
+        let interpretor =
+            Interpretor::parse("comm[ngin?] and exe[/usr/*] and cmdline =~ \"worker\"").unwrap();
+        assert!(interpretor.filter(&packet0));
+        let packet3 = Packet { pid: 1, ..packet0 };
+        assert!(!interpretor.filter(&packet3));
+        // Unknown executables are unknown, even negated.
+        assert!(!Interpretor::parse("exe[*]").unwrap().filter(&packet3));
+        let interpretor = Interpretor::parse("not exe[*]").unwrap();
+        assert!(!interpretor.filter(&packet3));
+        assert!(interpretor.may_match(&packet3));
+
+        let interpretor = Interpretor::parse("rport < 1024 and lport[1024-65535]").unwrap();
+        assert!(interpretor.filter(&packet0));
+        assert!(!interpretor.filter(&packet1));
+        let interpretor = Interpretor::parse("port[80, 8000-9000] or port != 12382").unwrap();
+        assert!(!interpretor.filter(&packet0));
+        assert!(interpretor.filter(&packet1));
+
+        let interpretor = Interpretor::parse("raddr[1.0.0.0/8, 2001:db8::/32]").unwrap();
+        assert!(interpretor.filter(&packet0));
+        assert!(!Interpretor::parse("addr[10.0.0.0/8]")
+            .unwrap()
+            .filter(&packet0));
+
+        // Dual stack sockets report IPv4-mapped addresses.
+        let packet2 = Packet {
+            local_address: "::ffff:127.0.0.1".parse().unwrap(),
+            remote_address: "::ffff:10.1.2.3".parse().unwrap(),
+            ..packet0
+        };
+        assert!(Interpretor::parse("laddr[127.0.0.1]")
+            .unwrap()
+            .filter(&packet2));
+        assert!(Interpretor::parse("raddr[10.0.0.0/8]")
+            .unwrap()
+            .filter(&packet2));
+        assert!(!Interpretor::parse("raddr[::/0]").unwrap().filter(&packet2));
+
+        let interpretor = Interpretor::parse("user[alice] or (uid[0] and group[root])").unwrap();
+        assert!(interpretor.filter(&packet0));
+        assert!(interpretor.filter(&packet1));
+
+        // Unknown names never match.
+        let interpretor = Interpretor::parse("user[bob] or gid[1000] and group[staff]").unwrap();
+        assert!(!interpretor.filter(&packet0));
+        assert!(!interpretor.filter(&packet1));
+
+        assert!(Interpretor::parse("loopback and remote public")
+            .unwrap()
+            .filter(&packet0));
+        assert!(!Interpretor::parse("remote private or local multicast")
+            .unwrap()
+            .filter(&packet0));
+        assert!(Interpretor::parse("remote private")
+            .unwrap()
+            .filter(&packet2));
+
+        let interpretor = Interpretor::parse("type[stream] and state[established]").unwrap();
+        assert!(interpretor.filter(&packet0));
+        assert!(!interpretor.filter(&packet1));
+        let udp = Packet {
+            protocol: Protocol::Udp,
+            ..packet0
+        };
+        assert!(!Interpretor::parse("state[established|time_wait]")
+            .unwrap()
+            .filter(&udp));
+        assert!(Interpretor::parse("type[raw|dgram]").unwrap().filter(&udp));
+
+        let interpretor = Interpretor::parse("rhost[*.one.one] and lhost[LOCALHOST]").unwrap();
+        assert!(interpretor.filter(&packet0));
+        assert!(!Interpretor::parse("host[*.amazonaws.com]")
+            .unwrap()
+            .may_match(&packet0));
+        // Only the remote host is matched by `host`.
+        assert!(!Interpretor::parse("host[localhost]")
+            .unwrap()
+            .may_match(&packet0));
+
+        // Host names resolve to IPv4-mapped addresses of dual stack sockets.
+        assert!(Interpretor::parse("rhost[db-primary]")
+            .unwrap()
+            .filter(&packet2));
+        // Pending lookups are unknown.
+        let interpretor = Interpretor::parse("rhost[cache]").unwrap();
+        assert!(!interpretor.filter(&packet0));
+        assert!(interpretor.may_match(&packet0));
+        let interpretor = Interpretor::parse("rhost[*.internal]").unwrap();
+        assert!(!interpretor.filter(&packet2));
+        assert!(interpretor.may_match(&packet2));
+        assert!(Interpretor::parse("rhost[*.internal] or tcp")
+            .unwrap()
+            .filter(&packet2));
+
+        // The traffic of a bare socket is unknown.
+        let interpretor = Interpretor::parse("rate > 1MiB and tx").unwrap();
+        assert!(interpretor.has_traffic());
+        assert!(!interpretor.filter(&packet0));
+        assert!(interpretor.may_match(&packet0));
+        let interpretor = Interpretor::parse("bytes > 100k and rport[8443]").unwrap();
+        assert!(!interpretor.may_match(&packet0));
+        assert!(interpretor.may_match(&packet1));
+        assert!(Interpretor::parse("not bytes > 0 or rport[443]")
+            .unwrap()
+            .filter(&packet0));
+        assert!(!Interpretor::parse("tcp").unwrap().has_traffic());
+
+        let traffic = Traffic {
+            rx_bytes: 1_500_000,
+            tx_bytes: 20_000,
+            rx_packets: 1000,
+            tx_packets: 20,
+            rx_rate: 1_048_576.0,
+            tx_rate: 100.0,
+        };
+        let busy = Packet {
+            traffic: Some(traffic),
+            ..packet0
+        };
+        let idle = Packet {
+            traffic: Some(Traffic::default()),
+            ..packet0
+        };
+        let interpretor = Interpretor::parse("rate > 1MiB and tx").unwrap();
+        assert!(interpretor.filter(&busy));
+        assert!(!interpretor.filter(&idle));
+        let interpretor = Interpretor::parse("rxrate > 1MiB/s or txbytes >= 20k").unwrap();
+        assert!(interpretor.filter(&busy));
+        assert!(!interpretor.may_match(&idle));
+        let interpretor = Interpretor::parse("packets < 1k and not rx").unwrap();
+        assert!(!interpretor.filter(&busy));
+        assert!(interpretor.filter(&idle));
+    }
+
+    #[test]
+    fn unknown_details() {
+        /// A socket only known by its addresses.
+        struct Bare;
+
+        impl Filterable for Bare {
+            fn pid(&self) -> u32 {
+                1
+            }
+
+            fn protocol(&self) -> Option<Protocol> {
+                Some(Protocol::Tcp)
+            }
+
+            fn ip_version(&self) -> IpVersion {
+                IpVersion::IpV4
+            }
+
+            fn local_address(&self) -> IpAddr {
+                Ipv4Addr::LOCALHOST.into()
+            }
+
+            fn remote_address(&self) -> IpAddr {
+                Ipv4Addr::new(1, 1, 1, 1).into()
+            }
+
+            fn local_port(&self) -> u16 {
+                12382
+            }
+
+            fn remote_port(&self) -> u16 {
+                443
+            }
+        }
+
+        for filter in [
+            "uid[0]",
+            "user[root]",
+            "comm[curl]",
+            "state[established]",
+            "rhost[one.one.one.one]",
+        ] {
+            let interpretor = Interpretor::parse(filter).unwrap();
+            assert_eq!(interpretor.eval(&Bare), Some(false), "{filter}");
+        }
+        let interpretor = Interpretor::parse("tcp and rate > 1k").unwrap();
+        assert_eq!(interpretor.eval(&Bare), None);
+
+        // The details of the process are unknown, not missing.
+        for filter in ["exe[/usr/bin/*]", "not exe[/usr/bin/*]", "not cmdline[*]"] {
+            let interpretor = Interpretor::parse(filter).unwrap();
+            assert_eq!(interpretor.eval(&Bare), None, "{filter}");
+            assert_eq!(interpretor.walk(&Bare), None, "{filter}");
+        }
+    }
+
+    /// A xorshift generator, to draw the same expressions on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_expr(rng: &mut Rng, depth: usize) -> Expr {
+        // Few predicates, to draw the same ones and their negations together.
+        const PREDICATES: &[&str] = &[
+            "tcp",
+            "ipv6",
+            "pid[213]",
+            "rport[443]",
+            "port >= 0",
+            "lport < 0",
+            "port[80, 8000-9000]",
+            "raddr[1.0.0.0/8]",
+            "remote private",
+            "user[alice]",
+            "comm[ngin?]",
+            "exe[/usr/*]",
+            "state[established]",
+            "rhost[*.one.one]",
+            "host[db-primary]",
+            "rhost[cache]",
+            "rhost[*.internal]",
+            "rate > 1MiB",
+            "tx",
+            "bytes > 100k",
+        ];
+
+        if depth == 0 || rng.below(4) == 0 {
+            return parser::filter(PREDICATES[rng.below(PREDICATES.len())]).unwrap();
+        }
+        match rng.below(3) {
+            0 => Expr::And(
+                Box::new(random_expr(rng, depth - 1)),
+                Box::new(random_expr(rng, depth - 1)),
+            ),
+            1 => Expr::Or(
+                Box::new(random_expr(rng, depth - 1)),
+                Box::new(random_expr(rng, depth - 1)),
+            ),
+            _ => Expr::Not(Box::new(random_expr(rng, depth - 1))),
+        }
+    }
+
+    #[test]
+    fn compiled_program_agrees_with_walker() {
+        let busy = Traffic {
+            rx_bytes: 1_500_000,
+            tx_bytes: 20_000,
+            rx_packets: 1000,
+            tx_packets: 20,
+            rx_rate: 2_000_000.0,
+            tx_rate: 100.0,
+        };
+
+        let mut packets = Vec::new();
+        for protocol in [Protocol::Tcp, Protocol::Udp] {
+            for remote_address in ["1.1.1.1", "10.1.2.3", "::ffff:10.1.2.3"] {
+                for traffic in [None, Some(Traffic::default()), Some(busy)] {
+                    for (pid, uid, remote_port) in [(213, 1000, 443), (1, 0, 8443)] {
+                        packets.push(Packet {
+                            pid,
+                            protocol,
+                            ip_version: IpVersion::IpV4,
+                            local_address: Ipv4Addr::new(127, 0, 0, 1).into(),
+                            remote_address: remote_address.parse().unwrap(),
+                            local_port: 12382,
+                            remote_port,
+                            uid,
+                            gid: uid,
+                            traffic,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut rng = Rng(0x5eed);
+        for _ in 0..2000 {
+            let interpretor = Interpretor::new(random_expr(&mut rng, 5));
+            for packet in &packets {
+                assert_eq!(
+                    interpretor.eval(packet),
+                    interpretor.walk(packet),
+                    "{:?} on {:?}",
+                    interpretor.ast,
+                    packet
+                );
+            }
+        }
     }
 }
@@ -1,16 +1,34 @@
-use std::net::IpAddr;
+use std::{borrow::Cow, net::IpAddr, sync::Arc};
 
+mod compiler;
+mod completion;
+mod error;
 mod frontend;
 mod interpretor;
 
+pub use completion::*;
+pub use error::*;
 pub use frontend::*;
 pub use interpretor::*;
 
+/// A socket tested by the filters.
+///
+/// Only the addresses of the socket are required, the predicates on the unknown details
+/// don't match.
 pub trait Filterable {
     fn pid(&self) -> u32;
 
     fn protocol(&self) -> Option<Protocol>;
 
+    fn sock_type(&self) -> Option<SockType> {
+        None
+    }
+
+    /// Returns the state of a TCP socket, `None` for the other protocols.
+    fn state(&self) -> Option<TcpState> {
+        None
+    }
+
     fn ip_version(&self) -> IpVersion;
 
     fn local_address(&self) -> IpAddr;
@@ -20,4 +38,79 @@ pub trait Filterable {
     fn local_port(&self) -> u16;
 
     fn remote_port(&self) -> u16;
+
+    fn uid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the name of the user, if known.
+    fn user_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn gid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the name of the group, if known.
+    fn group_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the command name of the process, if known.
+    fn comm(&self) -> Option<Cow<'_, str>> {
+        None
+    }
+
+    /// Returns the path of the executable of the process, if known.
+    ///
+    /// The predicates on the executable and the command line are unknown without them.
+    fn exe(&self) -> Option<Arc<str>> {
+        None
+    }
+
+    /// Returns the command line of the process, the arguments separated by spaces, if known.
+    fn cmdline(&self) -> Option<Arc<str>> {
+        None
+    }
+
+    /// Returns the name of the host at `addr`, from a reverse DNS lookup.
+    fn host_name(&self, _addr: IpAddr) -> Lookup<Arc<str>> {
+        Lookup::NotFound
+    }
+
+    /// Returns the addresses of the host `name`, from a forward DNS lookup.
+    fn host_addrs(&self, _name: &str) -> Lookup<Arc<[IpAddr]>> {
+        Lookup::NotFound
+    }
+
+    /// Returns the traffic aggregated over a time range, `None` for a bare socket.
+    ///
+    /// The traffic thresholds of a filter are unknown without it, see [Interpretor::may_match].
+    fn traffic(&self) -> Option<Traffic> {
+        None
+    }
+}
+
+/// The result of a lookup done in the background, e.g. a DNS lookup.
+///
+/// The predicates depending on a pending lookup are unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<T> {
+    Pending,
+    NotFound,
+    Found(T),
+}
+
+/// The traffic of a socket over a time range.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Traffic {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    /// Bytes received per second, 0 if the rate is unknown.
+    pub rx_rate: f64,
+    /// Bytes sent per second, 0 if the rate is unknown.
+    pub tx_rate: f64,
 }
@@ -0,0 +1,73 @@
+//! Measures the ingestion throughput of the store by threads and batch sizes.
+//!
+//! Run with `cargo bench -p ptraf --bench ingest`.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use ptraf::{clock::Timestamp, store::Store};
+use ptraf_common::{Channel, SockMsgEvent, SockType, TcpState, TASK_COMM_LEN};
+
+const MESSAGES: usize = 1_000_000;
+
+/// 256 connections of 64 processes, with messages of various sizes.
+fn messages() -> Vec<SockMsgEvent> {
+    (0..4096u32)
+        .map(|i| SockMsgEvent {
+            pid: i % 64,
+            uid: 1000,
+            gid: 1000,
+            comm: [0; TASK_COMM_LEN],
+            channel: if i % 3 == 0 { Channel::Rx } else { Channel::Tx },
+            sock_type: SockType::Stream,
+            local_addr: ptraf_common::IpAddr::v4(33),
+            local_port: (1024 + i as u16 % 256).to_be(),
+            remote_addr: ptraf_common::IpAddr::v4(i % 256),
+            remote_port: 443u16.to_be(),
+            ret: 100 + i as i32,
+            state: TcpState::Established,
+            ts: 0,
+        })
+        .collect()
+}
+
+/// Returns the millions of messages ingested per second.
+fn bench(stream: &[&SockMsgEvent], threads: usize, batch_size: usize) -> f64 {
+    let store = Store::new(Duration::from_millis(250), 16);
+    let ts = Timestamp::default();
+
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for batch in stream.chunks(batch_size) {
+                    store.batch_update(batch.iter().map(|msg| (ts, *msg)));
+                }
+            });
+        }
+    });
+
+    (threads * stream.len()) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+fn main() {
+    let messages = messages();
+    let stream: Vec<_> = messages.iter().cycle().take(MESSAGES).collect();
+
+    // More threads than cores still measure the contention of the preempted writers.
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    let mut threads = vec![1, 2, 4, 8, cores];
+    threads.sort_unstable();
+    threads.dedup();
+
+    for threads in threads {
+        for batch_size in [1, 64, 1024] {
+            println!(
+                "{threads:>3} threads, batch size {batch_size:>4}: {:>6.2}M events/s",
+                bench(&stream, threads, batch_size)
+            );
+        }
+    }
+}
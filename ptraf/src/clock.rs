@@ -33,14 +33,43 @@ impl From<Duration> for Timestamp {
 #[derive(Debug, Clone)]
 pub struct ClockNano {
     start: Instant,
+    /// `CLOCK_MONOTONIC` time at `start`.
+    monotonic_start: Duration,
     wall_time: SystemTime,
+    /// Timestamp of `start`.
+    origin: Duration,
 }
 
 impl ClockNano {
+    /// Returns a clock whose timestamps start at `past`, so that the times
+    /// up to `past` before its creation have a timestamp.
+    pub fn with_past(past: Duration) -> Self {
+        Self {
+            origin: past,
+            ..Self::default()
+        }
+    }
+
     /// Returns the wall time of the timestamp `ts` generated
     /// from this clock.
     pub fn wall_time(&self, ts: Timestamp) -> SystemTime {
-        self.wall_time.checked_add(ts.0).unwrap_or(self.wall_time)
+        if ts.0 >= self.origin {
+            self.wall_time.checked_add(ts.0 - self.origin)
+        } else {
+            self.wall_time.checked_sub(self.origin - ts.0)
+        }
+        .unwrap_or(self.wall_time)
+    }
+
+    /// Returns the timestamp of the wall time `time`, or `None` if it is
+    /// before the start of the clock.
+    pub fn timestamp(&self, time: SystemTime) -> Option<Timestamp> {
+        let ts = match time.duration_since(self.wall_time) {
+            Ok(elapsed) => self.origin.checked_add(elapsed)?,
+            Err(err) => self.origin.checked_sub(err.duration())?,
+        };
+
+        Some(Timestamp(ts))
     }
 }
 
@@ -48,22 +77,45 @@ impl Default for ClockNano {
     fn default() -> Self {
         Self {
             start: Instant::now(),
+            monotonic_start: monotonic_now(),
             wall_time: SystemTime::now(),
+            origin: Duration::ZERO,
         }
     }
 }
 
 impl ClockNano {
     pub fn now(&self) -> Timestamp {
-        Timestamp(self.start.elapsed())
+        Timestamp(self.origin + self.start.elapsed())
     }
+
+    /// Returns the timestamp of a time in nanoseconds of `CLOCK_MONOTONIC`,
+    /// like the timestamps of the kernel events (`bpf_ktime_get_ns`).
+    ///
+    /// Times before the start of the clock are mapped to its start.
+    pub fn ktime_timestamp(&self, ktime_ns: u64) -> Timestamp {
+        Timestamp(
+            (Duration::from_nanos(ktime_ns) + self.origin).saturating_sub(self.monotonic_start),
+        )
+    }
+}
+
+fn monotonic_now() -> Duration {
+    let mut tp = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `tp` is a valid timespec and CLOCK_MONOTONIC is always supported.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut tp) };
+
+    Duration::new(tp.tv_sec as u64, tp.tv_nsec as u32)
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use super::Timestamp;
+    use super::{monotonic_now, ClockNano, Timestamp};
 
     #[test]
     fn timestamp_trunc() {
@@ -71,4 +123,30 @@ mod tests {
         let ts = ts.trunc(Duration::from_secs(1000));
         assert_eq!(ts, Duration::from_secs(3000).into());
     }
+
+    #[test]
+    fn clock_ktime_timestamp() {
+        let clock = ClockNano::default();
+
+        let ts = clock.ktime_timestamp(monotonic_now().as_nanos() as u64);
+        assert!(ts <= clock.now());
+        assert!(clock.now().saturating_elapsed_since(&ts) < Duration::from_secs(1));
+
+        assert_eq!(clock.ktime_timestamp(0), Duration::ZERO.into());
+    }
+
+    #[test]
+    fn clock_with_past() {
+        let past = Duration::from_secs(3600);
+        let clock = ClockNano::with_past(past);
+        let origin = clock.wall_time(Duration::ZERO.into());
+
+        assert!(clock.now() >= past.into());
+        assert_eq!(clock.wall_time(past.into()), origin + past,);
+        assert_eq!(
+            clock.timestamp(origin + Duration::from_secs(10)),
+            Some(Duration::from_secs(10).into())
+        );
+        assert_eq!(clock.timestamp(origin - Duration::from_secs(1)), None);
+    }
 }
@@ -0,0 +1,197 @@
+//! Host names of the addresses and addresses of the host names.
+//!
+//! The lookups run on a few resolver threads, the callers never wait for them. When too many
+//! lookups are waiting, the next ones are started on a later call. They are cached for a few
+//! minutes, including the failed ones.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    io,
+    net::IpAddr,
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use ptraf_filter::Lookup;
+use tokio::sync::oneshot;
+
+use crate::{privileges, promise::Promise};
+
+/// Time after which a lookup is done again.
+const TTL: Duration = Duration::from_secs(300);
+
+/// Number of lookups cached, the oldest ones are evicted first.
+const CAPACITY: usize = 4096;
+
+/// Number of threads resolving the lookups.
+const RESOLVERS: usize = 4;
+
+/// Number of lookups waiting for a resolver.
+const QUEUE: usize = 256;
+
+type Cache<K, T> = Mutex<Option<HashMap<K, (Instant, Promise<Option<T>>)>>>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static HOST_NAMES: Cache<IpAddr, Arc<str>> = Mutex::new(None);
+static HOST_ADDRS: Cache<String, Arc<[IpAddr]>> = Mutex::new(None);
+
+static JOBS: Mutex<Option<SyncSender<Job>>> = Mutex::new(None);
+
+/// Returns the name of the host at `addr` (reverse DNS), the lookup is started if needed.
+///
+/// The unspecified and loopback addresses are not looked up.
+pub fn host_name(addr: IpAddr) -> Lookup<Arc<str>> {
+    if addr.is_unspecified() || addr.is_loopback() {
+        return Lookup::NotFound;
+    }
+
+    lookup(&HOST_NAMES, &addr, |addr| {
+        dns_lookup::lookup_addr(&addr)
+            .ok()
+            // The address is returned if it has no name.
+            .filter(|name| name.parse::<IpAddr>().is_err())
+            .map(Arc::from)
+    })
+}
+
+/// Returns the addresses of the host `name` (forward DNS), the lookup is started if needed.
+pub fn host_addrs(name: &str) -> Lookup<Arc<[IpAddr]>> {
+    lookup(&HOST_ADDRS, name, |name| {
+        dns_lookup::lookup_host(&name)
+            .ok()
+            .filter(|addrs| !addrs.is_empty())
+            .map(Arc::from)
+    })
+}
+
+fn lookup<K, Q, T>(cache: &Cache<K, T>, key: &Q, resolve: fn(K) -> Option<T>) -> Lookup<T>
+where
+    K: Borrow<Q> + Eq + Hash + Clone + Send + 'static,
+    Q: ToOwned<Owned = K> + Eq + Hash + ?Sized,
+    T: Clone + Send + 'static,
+{
+    let now = Instant::now();
+
+    let mut cache = cache.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::default);
+
+    if let Some((looked_up_at, promise)) = cache.get_mut(key) {
+        if now.duration_since(*looked_up_at) < TTL {
+            return match promise.value() {
+                None => Lookup::Pending,
+                Some(None) => Lookup::NotFound,
+                Some(Some(value)) => Lookup::Found(value.clone()),
+            };
+        }
+    }
+
+    let key = key.to_owned();
+    let resolving = key.clone();
+    if let Some(promise) = spawn(move || resolve(resolving)) {
+        if cache.len() >= CAPACITY {
+            evict(cache, now);
+        }
+        cache.insert(key, (now, promise));
+    }
+
+    Lookup::Pending
+}
+
+/// Queues the lookup for the resolvers, returns `None` if too many lookups are waiting.
+fn spawn<T: Send + 'static>(resolve: impl FnOnce() -> T + Send + 'static) -> Option<Promise<T>> {
+    let mut jobs = JOBS.lock().unwrap();
+    let jobs = match &mut *jobs {
+        Some(jobs) => jobs,
+        jobs @ None => jobs.insert(start_resolvers().ok()?),
+    };
+
+    let (tx, rx) = oneshot::channel();
+    jobs.try_send(Box::new(move || {
+        let _ = tx.send(resolve());
+    }))
+    .ok()?;
+
+    Some(Promise::Pending(rx))
+}
+
+fn start_resolvers() -> io::Result<SyncSender<Job>> {
+    let (jobs, received) = mpsc::sync_channel::<Job>(QUEUE);
+    let received = Arc::new(Mutex::new(received));
+
+    for _ in 0..RESOLVERS {
+        let received = Arc::clone(&received);
+        thread::Builder::new()
+            .name("ptraf-dns".to_string())
+            .spawn(move || {
+                privileges::drop_current_thread();
+
+                loop {
+                    // The lock is released before the lookup.
+                    let job = received.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                }
+            })?;
+    }
+
+    Ok(jobs)
+}
+
+/// Evicts the expired lookups, and the oldest ones if the cache is still full.
+fn evict<K, T>(cache: &mut HashMap<K, (Instant, T)>, now: Instant) {
+    cache.retain(|_, (looked_up_at, _)| now.duration_since(*looked_up_at) < TTL);
+
+    if cache.len() >= CAPACITY {
+        let mut looked_up_at: Vec<_> = cache.values().map(|(at, _)| *at).collect();
+        let (_, oldest, _) = looked_up_at.select_nth_unstable(CAPACITY / 4);
+        let oldest = *oldest;
+        cache.retain(|_, (looked_up_at, _)| *looked_up_at > oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn skip_local_addresses() {
+        for addr in [
+            IpAddr::from(Ipv4Addr::UNSPECIFIED),
+            IpAddr::from(Ipv4Addr::LOCALHOST),
+            IpAddr::from(Ipv6Addr::UNSPECIFIED),
+            IpAddr::from(Ipv6Addr::LOCALHOST),
+        ] {
+            assert_eq!(host_name(addr), Lookup::NotFound);
+        }
+    }
+
+    #[test]
+    fn evict_oldest() {
+        let now = Instant::now();
+
+        let mut cache: HashMap<u64, (Instant, ())> = (0..CAPACITY as u64)
+            .map(|i| (i, (now - Duration::from_millis(i), ())))
+            .collect();
+        evict(&mut cache, now);
+
+        // The last entries were looked up first.
+        assert!(cache.len() < CAPACITY);
+        assert!(cache.contains_key(&0));
+        assert!(!cache.contains_key(&(CAPACITY as u64 - 1)));
+
+        cache.insert(CAPACITY as u64, (now - TTL, ()));
+        evict(&mut cache, now);
+        assert!(!cache.contains_key(&(CAPACITY as u64)));
+    }
+}
@@ -0,0 +1,392 @@
+//! Kernel feature probing and startup diagnostics.
+//!
+//! The [Report] checks the running kernel and the privileges of the process
+//! against what the probe program requires, and tells which features will be
+//! degraded. It backs the `ptraf doctor` command and the checks run on startup.
+
+use std::{io, path::Path};
+
+use procfs::{
+    process::{LimitValue, Process},
+    sys::kernel::Version,
+};
+
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+/// Minimum locked memory recommended on kernels that account BPF maps on `RLIMIT_MEMLOCK`.
+const MIN_LOCKED_MEMORY: u64 = 64 * 1024 * 1024;
+
+const TRACEFS_ROOTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+const VMLINUX: &str = "/sys/kernel/btf/vmlinux";
+
+/// Outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// The feature is available.
+    Ok,
+    /// The check couldn't be completed.
+    Unknown,
+    /// ptraf runs with reduced functionality.
+    Degraded,
+    /// ptraf won't be able to run.
+    Fatal,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "  OK  ",
+            Self::Unknown => " ???? ",
+            Self::Degraded => " WARN ",
+            Self::Fatal => " FAIL ",
+        }
+    }
+}
+
+/// The result of checking one feature.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found.
+    pub detail: String,
+    /// What it implies and how to fix it, if the status is not ok.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn failed(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// A report of the kernel features and privileges required by ptraf.
+#[derive(Debug, Clone)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    /// Runs all the checks.
+    pub fn collect() -> Self {
+        let kernel = Version::current().ok();
+        let capeff = Process::myself()
+            .and_then(|proc| proc.status())
+            .map(|status| status.capeff)
+            .map_err(|err| err.to_string());
+        let locked_memory = Process::myself()
+            .and_then(|proc| proc.limits())
+            .map(|limits| limits.max_locked_memory.soft_limit)
+            .map_err(|err| err.to_string());
+        let tracefs = |path: &str| {
+            TRACEFS_ROOTS
+                .iter()
+                .any(|root| Path::new(root).join(path).exists())
+        };
+
+        Self {
+            checks: vec![
+                check_kernel_version(kernel),
+                check_btf(Path::new(VMLINUX).exists()),
+                check_capabilities(capeff),
+                check_locked_memory(kernel, locked_memory),
+                check_tracepoint(
+                    tracefs("events/sock/inet_sock_set_state"),
+                    tracefs("events"),
+                ),
+            ],
+        }
+    }
+
+    /// Tells whether one of the checks prevents ptraf from running.
+    pub fn is_fatal(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.status == Status::Fatal)
+    }
+
+    /// Returns the checks that are not ok.
+    pub fn problems(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|check| check.status != Status::Ok)
+    }
+
+    /// Writes the report in a human readable form.
+    pub fn write_to(&self, mut out: impl io::Write) -> io::Result<()> {
+        for check in &self.checks {
+            writeln!(
+                out,
+                "[{}] {}: {}",
+                check.status.label(),
+                check.name,
+                check.detail
+            )?;
+            if let Some(hint) = &check.hint {
+                writeln!(out, "         -> {hint}")?;
+            }
+        }
+
+        let summary = match self.checks.iter().map(|check| check.status).max() {
+            Some(Status::Fatal) => "ptraf can't run in this environment.",
+            Some(Status::Degraded) | Some(Status::Unknown) => {
+                "ptraf can run, some features are degraded."
+            }
+            _ => "ptraf can run with all features.",
+        };
+        writeln!(out, "\n{summary}")
+    }
+}
+
+fn check_kernel_version(kernel: Option<Version>) -> Check {
+    const NAME: &str = "kernel version";
+
+    match kernel {
+        None => Check::failed(
+            NAME,
+            Status::Unknown,
+            "couldn't read /proc/sys/kernel/osrelease",
+            "the kernel must be 5.5 or newer",
+        ),
+        Some(version) if version < Version::new(5, 5, 0) => Check::failed(
+            NAME,
+            Status::Fatal,
+            format_version(version),
+            "the kernel must be 5.5 or newer (bpf_probe_read_kernel)",
+        ),
+        Some(version) => Check::ok(NAME, format_version(version)),
+    }
+}
+
+fn check_btf(vmlinux: bool) -> Check {
+    const NAME: &str = "BTF";
+
+    if vmlinux {
+        Check::ok(NAME, VMLINUX)
+    } else {
+        Check::failed(
+            NAME,
+            Status::Degraded,
+            format!("{VMLINUX} not found"),
+            "fexit programs unavailable, falling back to kprobes (higher overhead); \
+             enable CONFIG_DEBUG_INFO_BTF",
+        )
+    }
+}
+
+/// `capeff` is the effective capabilities of the process.
+fn check_capabilities(capeff: Result<u64, String>) -> Check {
+    const NAME: &str = "capabilities";
+
+    let capeff = match capeff {
+        Ok(capeff) => capeff,
+        Err(err) => {
+            return Check::failed(
+                NAME,
+                Status::Unknown,
+                format!("couldn't read /proc/self/status: {err}"),
+                "ptraf requires CAP_BPF and CAP_PERFMON, or CAP_SYS_ADMIN",
+            )
+        }
+    };
+
+    let has = |cap: u32| capeff & (1 << cap) != 0;
+
+    if has(CAP_SYS_ADMIN) {
+        return Check::ok(NAME, "CAP_SYS_ADMIN");
+    }
+
+    let missing: Vec<_> = [(CAP_BPF, "CAP_BPF"), (CAP_PERFMON, "CAP_PERFMON")]
+        .into_iter()
+        .filter(|(cap, _)| !has(*cap))
+        .map(|(_, name)| name)
+        .collect();
+
+    if missing.is_empty() {
+        Check::ok(NAME, "CAP_BPF, CAP_PERFMON")
+    } else {
+        Check::failed(
+            NAME,
+            Status::Fatal,
+            format!("missing {}", missing.join(", ")),
+            "run as root (sudo -E) or grant the capabilities with \
+             `setcap cap_bpf,cap_perfmon+ep`",
+        )
+    }
+}
+
+/// `limit` is the soft limit of the locked memory of the process.
+fn check_locked_memory(kernel: Option<Version>, limit: Result<LimitValue, String>) -> Check {
+    const NAME: &str = "locked memory";
+
+    // Since 5.11, BPF memory is accounted on the memory cgroup instead of RLIMIT_MEMLOCK.
+    if matches!(kernel, Some(version) if version >= Version::new(5, 11, 0)) {
+        return Check::ok(NAME, "accounted on memory cgroup");
+    }
+
+    let limit = match limit {
+        Ok(limit) => limit,
+        Err(err) => {
+            return Check::failed(
+                NAME,
+                Status::Unknown,
+                format!("couldn't read /proc/self/limits: {err}"),
+                "BPF maps might fail to load, raise the limit with `ulimit -l`",
+            )
+        }
+    };
+
+    match limit {
+        LimitValue::Unlimited => Check::ok(NAME, "unlimited"),
+        LimitValue::Value(value) if value >= MIN_LOCKED_MEMORY => {
+            Check::ok(NAME, format!("{value} bytes"))
+        }
+        // The maps of a small dataset may still fit.
+        LimitValue::Value(value) => Check::failed(
+            NAME,
+            Status::Degraded,
+            format!("{value} bytes"),
+            "BPF maps might fail to load, raise the limit with `ulimit -l unlimited`",
+        ),
+    }
+}
+
+/// `found` tells whether the tracepoint is in tracefs, `tracefs` whether tracefs is readable.
+fn check_tracepoint(found: bool, tracefs: bool) -> Check {
+    const NAME: &str = "tracepoint sock:inet_sock_set_state";
+
+    if found {
+        Check::ok(NAME, "available")
+    } else if tracefs {
+        Check::failed(
+            NAME,
+            Status::Degraded,
+            "not found",
+            "socket state changes won't be tracked, the kernel must be 4.16 or newer",
+        )
+    } else {
+        Check::failed(
+            NAME,
+            Status::Unknown,
+            "tracefs not mounted or not readable",
+            "socket state changes won't be tracked if the tracepoint is missing; \
+             mount tracefs on /sys/kernel/tracing",
+        )
+    }
+}
+
+fn format_version(version: Version) -> String {
+    format!("{}.{}.{}", version.major, version.minor, version.patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(check: Check) -> Status {
+        check.status
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(status(check_kernel_version(None)), Status::Unknown);
+        assert_eq!(
+            status(check_kernel_version(Some(Version::new(5, 4, 0)))),
+            Status::Fatal
+        );
+        assert_eq!(
+            status(check_kernel_version(Some(Version::new(5, 5, 0)))),
+            Status::Ok
+        );
+
+        assert_eq!(status(check_btf(true)), Status::Ok);
+        assert_eq!(status(check_btf(false)), Status::Degraded);
+
+        assert_eq!(
+            status(check_capabilities(Err("denied".into()))),
+            Status::Unknown
+        );
+        assert_eq!(
+            status(check_capabilities(Ok(1 << CAP_SYS_ADMIN))),
+            Status::Ok
+        );
+        assert_eq!(
+            status(check_capabilities(Ok(1 << CAP_BPF | 1 << CAP_PERFMON))),
+            Status::Ok
+        );
+        let check = check_capabilities(Ok(1 << CAP_BPF));
+        assert_eq!(check.status, Status::Fatal);
+        assert_eq!(check.detail, "missing CAP_PERFMON");
+
+        let old = Some(Version::new(5, 10, 0));
+        assert_eq!(
+            status(check_locked_memory(
+                Some(Version::new(5, 11, 0)),
+                Err("denied".into())
+            )),
+            Status::Ok
+        );
+        assert_eq!(
+            status(check_locked_memory(old, Err("denied".into()))),
+            Status::Unknown
+        );
+        assert_eq!(
+            status(check_locked_memory(old, Ok(LimitValue::Unlimited))),
+            Status::Ok
+        );
+        assert_eq!(
+            status(check_locked_memory(
+                old,
+                Ok(LimitValue::Value(MIN_LOCKED_MEMORY))
+            )),
+            Status::Ok
+        );
+        assert_eq!(
+            status(check_locked_memory(old, Ok(LimitValue::Value(65536)))),
+            Status::Degraded
+        );
+
+        assert_eq!(status(check_tracepoint(true, true)), Status::Ok);
+        assert_eq!(status(check_tracepoint(false, true)), Status::Degraded);
+        assert_eq!(status(check_tracepoint(false, false)), Status::Unknown);
+    }
+
+    #[test]
+    fn fatal_report() {
+        let report = Report {
+            checks: vec![
+                check_btf(false),
+                check_locked_memory(Some(Version::new(5, 4, 0)), Ok(LimitValue::Value(0))),
+            ],
+        };
+        assert!(!report.is_fatal());
+        assert_eq!(report.problems().count(), 2);
+
+        let report = Report {
+            checks: vec![check_capabilities(Ok(0))],
+        };
+        assert!(report.is_fatal());
+    }
+}
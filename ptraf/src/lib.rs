@@ -0,0 +1,12 @@
+//! Tracking of the socket traffic of the processes, shared by the ptraf binary and its benches.
+
+pub mod clock;
+mod dns;
+pub mod doctor;
+pub mod privileges;
+pub mod probe;
+mod processes;
+mod promise;
+pub mod store;
+pub mod ui;
+mod users;
@@ -1,25 +1,25 @@
-use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+use std::{io, num::NonZeroUsize, path::PathBuf, sync::Arc, time::Duration};
 
-use clap::Parser;
-use log::info;
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+use log::{info, warn};
 use tokio::signal;
 
-mod clock;
-mod probe;
-mod promise;
-mod store;
-mod ui;
-
-use self::{
+use ptraf::{
     clock::ClockNano,
+    doctor::Report,
+    privileges::{self, Credentials},
     probe::ProbeProgram,
-    store::Store,
+    store::{Dimension, DiskStore, Limits, Rollup, Store},
     ui::{run_ui, App},
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Number of seconds of history to store.
     /// Defaults to 30s.
     #[arg(short, long, default_value_t = 30u64)]
@@ -36,24 +36,160 @@ struct Args {
     /// Duration of a unit of storage in milliseconds. min: 10ms.
     #[arg(short, long, default_value_t = 250u64)]
     interval_ms: u64,
+
+    /// Coarser tier of history kept after the backlog, as `<resolution>:<retention>`.
+    /// The expired data of a tier is merged into the next one.
+    #[arg(long = "rollup", value_parser = parse_rollup, default_values = ["5s:1h", "1m:24h"])]
+    rollups: Vec<Rollup>,
+
+    /// Directory where the history is persisted, it must be writable by `--user`.
+    /// The history is only kept in memory if not set.
+    #[arg(long)]
+    history_dir: Option<PathBuf>,
+
+    /// Duration of the history kept in `--history-dir`.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "7d")]
+    history_retention: Duration,
+
+    /// Maximum number of sockets tracked in a unit of storage, the traffic of
+    /// the other sockets is only accounted by process.
+    #[arg(long, default_value_t = 65536)]
+    max_sockets_per_interval: usize,
+
+    /// Maximum number of sockets tracked over the whole history in memory.
+    #[arg(long, default_value_t = 1_000_000)]
+    max_sockets: usize,
+
+    /// Dimensions of the traffic indexed in addition to the processes, sockets and remote IPs,
+    /// among local-port, remote-port, process-name, uid and sock-type. The traffic of the other
+    /// dimensions is computed from the sockets when needed.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values = ["local-port", "remote-port", "process-name", "uid", "sock-type"]
+    )]
+    dimensions: Vec<Dimension>,
+
+    /// Account traffic on unix domain sockets.
+    #[arg(long)]
+    unix: bool,
+
+    /// Switch to this user once the BPF program is attached, only retaining
+    /// the capabilities required to read the details of the processes.
+    #[arg(long)]
+    user: Option<String>,
+}
+
+fn parse_rollup(value: &str) -> Result<Rollup, String> {
+    let (resolution, retention) = value
+        .split_once(':')
+        .ok_or("expected <resolution>:<retention>, e.g. 5s:1h")?;
+
+    let parse = |duration: &str| {
+        humantime::parse_duration(duration).map_err(|err| format!("{duration}: {err}"))
+    };
+
+    Ok(Rollup {
+        resolution: parse(resolution)?,
+        retention: parse(retention)?,
+    })
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Checks the kernel features and privileges required by ptraf
+    /// and reports which features will be degraded.
+    Doctor,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
 
     let args = Args::parse();
 
-    let clock = ClockNano::default();
+    let credentials = args
+        .user
+        .as_deref()
+        .map(Credentials::from_user_name)
+        .transpose()?;
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+
+    // Credentials are per thread, the runtime threads drop the privileges as soon as they start.
+    // They are kept on the main thread that loads the program and opens the perf buffers.
+    if let Some(credentials) = credentials.clone() {
+        privileges::set_thread_credentials(credentials);
+        runtime.on_thread_start(privileges::drop_current_thread);
+    }
+
+    runtime.build()?.block_on(run(args, credentials))
+}
+
+async fn run(args: Args, credentials: Option<Credentials>) -> Result<(), anyhow::Error> {
+    let report = Report::collect();
+
+    if let Some(Command::Doctor) = args.command {
+        report.write_to(io::stdout())?;
+        if report.is_fatal() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if report.is_fatal() {
+        report.write_to(io::stderr())?;
+        anyhow::bail!("ptraf can't run in this environment, see the report above");
+    }
+
+    for check in report.problems() {
+        warn!(
+            "{}: {} ({})",
+            check.name,
+            check.detail,
+            check.hint.as_deref().unwrap_or_default()
+        );
+    }
 
     let segment_interval = Duration::from_millis(args.interval_ms.max(10));
     let segment_count = (args.backlog_secs * 1000 / (args.interval_ms.max(10))).max(1) as usize;
 
-    let store = Store::new(segment_interval, segment_count);
+    let mut store = Store::with_rollups(segment_interval, segment_count, &args.rollups)?
+        .with_limits(Limits {
+            sockets_per_segment: args.max_sockets_per_interval,
+            sockets: args.max_sockets,
+        })
+        .with_dimensions(&args.dimensions);
+
+    let clock = if let Some(dir) = &args.history_dir {
+        // The persisted history must have timestamps.
+        let clock = ClockNano::with_past(args.history_retention);
+        let disk = DiskStore::open(dir, args.history_retention, clock.clone())
+            .with_context(|| format!("failed to open the history in {}", dir.display()))?;
+        store = store.with_disk(disk);
+        clock
+    } else {
+        ClockNano::default()
+    };
+
     let app = Arc::new(App::new(clock, store));
 
-    let program = ProbeProgram::load()?;
-    info!("BPF program loaded");
+    let mut program = ProbeProgram::load().map_err(|err| {
+        let _ = report.write_to(io::stderr());
+        err.context("failed to load the BPF program, see the report above")
+    })?;
+    info!("BPF program loaded, attach mode: {}", program.attach_mode());
+
+    if args.unix {
+        let app = Arc::clone(&app);
+        program.unix_events(move |events, cpu_id| {
+            record_lost_events(&app, cpu_id, events.lost());
+            app.store().batch_update_unix(
+                events.map(|event| (app.clock().ktime_timestamp(event.ts), event)),
+            );
+        })?;
+        info!("unix domain sockets accounting enabled");
+    }
 
     let ui_handle = {
         let app = Arc::clone(&app);
@@ -63,15 +199,24 @@ async fn main() -> Result<(), anyhow::Error> {
         ))
     };
 
-    let mut join_set = program
-        .events(args.msg_buffer_capacity, move |events, _cpu_id| {
-            let ts = app.clock().now();
-
-            app.store().batch_update(ts, events);
+    let mut join_set = {
+        let app = Arc::clone(&app);
+        program.events(args.msg_buffer_capacity, move |events, cpu_id| {
+            record_lost_events(&app, cpu_id, events.lost());
+            app.store()
+                .batch_update(events.map(|event| (app.clock().ktime_timestamp(event.ts), event)));
         })
-        .await?;
+    }
+    .await?;
 
-    tokio::select! {
+    if let Some(credentials) = credentials {
+        credentials
+            .apply_to_current_thread()
+            .context("failed to drop privileges")?;
+        info!("privileges dropped, running as {}", credentials.name());
+    }
+
+    let res = tokio::select! {
         _ = signal::ctrl_c() => {
             info!("Exiting...");
             join_set.abort_all();
@@ -80,5 +225,17 @@ async fn main() -> Result<(), anyhow::Error> {
         },
         res = join_set.join_next() => res.ok_or_else(|| anyhow::anyhow!("BPF task exited"))??,
         ui_res = ui_handle => { ui_res? },
+    };
+
+    app.store().flush();
+
+    res
+}
+
+/// Records the events lost by the perf buffer of `cpu_id` since its last read.
+fn record_lost_events(app: &App, cpu_id: u32, lost: usize) {
+    if lost > 0 {
+        app.store()
+            .record_lost_events(app.clock().now(), cpu_id, lost as u64);
     }
 }
@@ -0,0 +1,188 @@
+//! Dropping privileges once the BPF program is attached.
+//!
+//! Loading the program and opening the perf buffers requires root (or `CAP_BPF` and
+//! `CAP_PERFMON`), the rest of ptraf doesn't. [Credentials] switches to an unprivileged
+//! user and only retains the capabilities needed to read the details of other users'
+//! processes from procfs.
+//!
+//! Linux credentials are per thread: the switch must be applied on every thread of the
+//! process, see [Credentials::apply_to_current_thread]. The threads started by ptraf call
+//! [drop_current_thread] first, whichever thread starts them.
+
+use std::{io, sync::Mutex};
+
+use anyhow::Context as _;
+use caps::{CapSet, Capability, CapsHashSet};
+use log::error;
+use nix::unistd::User;
+
+/// Capabilities retained after switching user.
+///
+/// * `CAP_SYS_PTRACE` to read `/proc/<pid>/exe` and the fd links of other users' processes.
+/// * `CAP_DAC_READ_SEARCH` to list `/proc/<pid>/fd` of other users' processes.
+const RETAINED_CAPS: [Capability; 2] =
+    [Capability::CAP_SYS_PTRACE, Capability::CAP_DAC_READ_SEARCH];
+
+/// Credentials of the threads, see [drop_current_thread].
+static THREAD_CREDENTIALS: Mutex<Option<Credentials>> = Mutex::new(None);
+
+/// The unprivileged user to switch to.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    name: String,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+impl Credentials {
+    /// Looks up the user `name` in the user database.
+    pub fn from_user_name(name: &str) -> Result<Self, anyhow::Error> {
+        let user = User::from_name(name)
+            .with_context(|| format!("failed to look up user {name}"))?
+            .ok_or_else(|| anyhow::anyhow!("unknown user {name}"))?;
+
+        Ok(Self {
+            name: user.name,
+            uid: user.uid.as_raw(),
+            gid: user.gid.as_raw(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Switches the calling thread to the user and drops all capabilities but [RETAINED_CAPS].
+    ///
+    /// This bypasses the libc wrappers that would switch the user of all the threads at once,
+    /// because the capabilities can only be retained on the calling thread.
+    ///
+    /// The threads inherit the credentials of the thread starting them, this is a no-op on the
+    /// threads started once the privileges are dropped, e.g. the blocking threads of the runtime.
+    pub fn apply_to_current_thread(&self) -> Result<(), anyhow::Error> {
+        let retained: CapsHashSet = RETAINED_CAPS.into_iter().collect();
+
+        if self.is_current_thread()? && caps::read(None, CapSet::Permitted)?.is_subset(&retained) {
+            return Ok(());
+        }
+
+        for cap in caps::all().difference(&retained) {
+            if caps::has_cap(None, CapSet::Bounding, *cap).unwrap_or(false) {
+                caps::drop(None, CapSet::Bounding, *cap)
+                    .with_context(|| format!("failed to drop {cap} from the bounding set"))?;
+            }
+        }
+
+        // Keep the permitted capabilities across the change of user.
+        caps::securebits::set_keepcaps(true)?;
+
+        // SAFETY: raw syscalls on the calling thread credentials with valid arguments.
+        unsafe {
+            check_syscall(libc::syscall(libc::SYS_setgroups, 1, &self.gid)).context("setgroups")?;
+            check_syscall(libc::syscall(
+                libc::SYS_setresgid,
+                self.gid,
+                self.gid,
+                self.gid,
+            ))
+            .context("setresgid")?;
+            check_syscall(libc::syscall(
+                libc::SYS_setresuid,
+                self.uid,
+                self.uid,
+                self.uid,
+            ))
+            .context("setresuid")?;
+        }
+
+        caps::securebits::set_keepcaps(false)?;
+
+        caps::set(None, CapSet::Permitted, &retained)?;
+        caps::set(None, CapSet::Effective, &retained)?;
+        caps::clear(None, CapSet::Inheritable)?;
+        caps::clear(None, CapSet::Ambient)?;
+
+        Ok(())
+    }
+
+    /// Tells whether the calling thread already runs as the user.
+    fn is_current_thread(&self) -> io::Result<bool> {
+        let (mut ruid, mut euid, mut suid) = (0, 0, 0);
+        let (mut rgid, mut egid, mut sgid) = (0, 0, 0);
+
+        // SAFETY: the syscalls only write to the given pointers, they return the credentials
+        // of the calling thread.
+        unsafe {
+            check_syscall(libc::getresuid(&mut ruid, &mut euid, &mut suid).into())?;
+            check_syscall(libc::getresgid(&mut rgid, &mut egid, &mut sgid).into())?;
+        }
+
+        Ok([ruid, euid, suid] == [self.uid; 3] && [rgid, egid, sgid] == [self.gid; 3])
+    }
+}
+
+/// Sets the credentials the threads switch to in [drop_current_thread].
+pub fn set_thread_credentials(credentials: Credentials) {
+    *THREAD_CREDENTIALS.lock().unwrap() = Some(credentials);
+}
+
+/// Switches the calling thread to the credentials set with [set_thread_credentials], if any.
+///
+/// It exits the process if the switch fails, rather than running a privileged thread.
+pub fn drop_current_thread() {
+    let credentials = THREAD_CREDENTIALS.lock().unwrap().clone();
+
+    if let Some(credentials) = credentials {
+        if let Err(err) = credentials.apply_to_current_thread() {
+            error!("failed to drop privileges: {err:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check_syscall(ret: libc::c_long) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "switching user requires root"]
+    fn blocking_task_after_drop() {
+        let credentials = Credentials::from_user_name("nobody").unwrap();
+
+        // The credentials are per thread, the privileges of the test harness are kept.
+        std::thread::spawn(move || {
+            credentials.apply_to_current_thread().unwrap();
+
+            // The result of the switch on the runtime threads, a failing hook would only kill
+            // the thread.
+            let (results, switched) = std::sync::mpsc::channel();
+            let runtime = {
+                let credentials = credentials.clone();
+                tokio::runtime::Builder::new_current_thread()
+                    .on_thread_start(move || {
+                        let result = credentials.apply_to_current_thread();
+                        results.send(result.map_err(|err| err.to_string())).unwrap();
+                    })
+                    .build()
+                    .unwrap()
+            };
+
+            // The blocking thread is started by the unprivileged thread.
+            let uid = runtime
+                .block_on(runtime.spawn_blocking(nix::unistd::getuid))
+                .unwrap();
+            assert_eq!(switched.recv().unwrap(), Ok(()));
+            assert_eq!(uid.as_raw(), credentials.uid);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -3,7 +3,8 @@
 //! The `ProbeProgram` struct contains the program itself, and provides a method for launching a task for
 //! each CPU to read events from the kernel and pass them to a provided function.
 //!
-//! The `EventIter` struct is an iterator over references to `SockMsgEvent` structs.
+//! The `EventIter` struct is an iterator over references to `SockMsgEvent` structs, or
+//! `UnixMsgEvent` structs when `AF_UNIX` accounting is enabled.
 //!
 //! # Example
 //!
@@ -11,7 +12,6 @@
 //! use std::num::NonZeroUsize;
 //!
 //! use ptraf::probe::{ProbeProgram, EventIter};
-//! use ptraf_common::types::SockMsgEvent;
 //! use tokio::task::JoinSet;
 //!
 //! # #[tokio::main]
@@ -39,22 +39,46 @@
 //! ```
 
 use std::iter::FusedIterator;
+use std::marker::PhantomData;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use aya::maps::perf::AsyncPerfEventArray;
-use aya::programs::{KProbe, ProgramError, TracePoint};
+use aya::maps::{Array, MapRefMut};
+use aya::programs::{FExit, KProbe, ProgramError, TracePoint};
 use aya::util::online_cpus;
-use aya::{include_bytes_aligned, Bpf};
+use aya::{include_bytes_aligned, Bpf, Btf};
 use aya_log::BpfLogger;
 use bytes::BytesMut;
-use log::{trace, warn};
-use ptraf_common::types::SockMsgEvent;
+use log::{info, trace, warn};
+use ptraf_common::types::{Setting, SockMsgEvent, UnixMsgEvent};
 use tokio::task::JoinSet;
 
+type UnixEventsFn = dyn Fn(EventIter<'_, UnixMsgEvent>, u32) + Send + Sync;
+
+/// How the send and receive probes are attached to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachMode {
+    /// fexit programs through BPF trampolines, requires BTF.
+    FExit,
+    /// kprobe and kretprobe pairs.
+    KProbe,
+}
+
+impl std::fmt::Display for AttachMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::FExit => "fexit",
+            Self::KProbe => "kprobe",
+        })
+    }
+}
+
 /// The probing eBPF program.
 pub struct ProbeProgram {
     bpf: Bpf,
+    attach_mode: AttachMode,
+    unix_events: Option<Arc<UnixEventsFn>>,
 }
 
 fn run_inet_sock_set_state(bpf: &mut Bpf) -> Result<(), ProgramError> {
@@ -64,8 +88,55 @@ fn run_inet_sock_set_state(bpf: &mut Bpf) -> Result<(), ProgramError> {
     Ok(())
 }
 
+/// Attaches the fexit programs, both arguments and return value are available
+/// in a single program.
+fn attach_fexit(bpf: &mut Bpf) -> Result<(), anyhow::Error> {
+    let btf = Btf::from_sys_fs()?;
+
+    for (program, function) in [
+        ("sendmsg_fexit", "sock_sendmsg"),
+        ("recvmsg_fexit", "sock_recvmsg"),
+    ] {
+        let fexit: &mut FExit = bpf.program_mut(program).unwrap().try_into()?;
+        fexit.load(function, &btf)?;
+        fexit.attach()?;
+    }
+
+    Ok(())
+}
+
+/// Detaches the fexit programs that might have been attached before a failure.
+fn detach_fexit(bpf: &mut Bpf) {
+    for program in ["sendmsg_fexit", "recvmsg_fexit"] {
+        if let Some(Ok(fexit)) = bpf.program_mut(program).map(<&mut FExit>::try_from) {
+            let _ = fexit.unload();
+        }
+    }
+}
+
+/// Attaches the kprobes, the socket is kept in a map between the probe and the return probe.
+fn attach_kprobes(bpf: &mut Bpf) -> Result<(), anyhow::Error> {
+    let probe: &mut KProbe = bpf.program_mut("msg").unwrap().try_into()?;
+    probe.load()?;
+    probe.attach("sock_sendmsg", 0)?;
+    probe.attach("sock_recvmsg", 0)?;
+
+    let ret_probe: &mut KProbe = bpf.program_mut("sendmsg_ret").unwrap().try_into()?;
+    ret_probe.load()?;
+    ret_probe.attach("sock_sendmsg", 0)?;
+
+    let ret_probe: &mut KProbe = bpf.program_mut("recvmsg_ret").unwrap().try_into()?;
+    ret_probe.load()?;
+    ret_probe.attach("sock_recvmsg", 0)?;
+
+    Ok(())
+}
+
 impl ProbeProgram {
     /// Loads the program into the kernel and attaches different probes.
+    ///
+    /// fexit programs are used when the kernel supports them, otherwise falls back
+    /// to kprobes. See [attach_mode](ProbeProgram::attach_mode).
     pub fn load() -> Result<Self, anyhow::Error> {
         trace!("loading bpf program");
 
@@ -85,29 +156,52 @@ impl ProbeProgram {
 
         trace!("initialize BPF programs");
 
-        let probe: &mut KProbe = bpf.program_mut("msg").unwrap().try_into()?;
-        probe.load()?;
-        probe.attach("sock_sendmsg", 0)?;
-        probe.attach("sock_recvmsg", 0)?;
-
-        let ret_probe: &mut KProbe = bpf.program_mut("sendmsg_ret").unwrap().try_into()?;
-        ret_probe.load()?;
-        ret_probe.attach("sock_sendmsg", 0)?;
-
-        let ret_probe: &mut KProbe = bpf.program_mut("recvmsg_ret").unwrap().try_into()?;
-        ret_probe.load()?;
-        ret_probe.attach("sock_recvmsg", 0)?;
+        let attach_mode = match attach_fexit(&mut bpf) {
+            Ok(()) => AttachMode::FExit,
+            Err(error) => {
+                info!("fexit not available, falling back to kprobes: {}", error);
+                detach_fexit(&mut bpf);
+                attach_kprobes(&mut bpf)?;
+                AttachMode::KProbe
+            }
+        };
 
         if let Err(error) = run_inet_sock_set_state(&mut bpf) {
             warn!(
-                "couldn't load sock:inet_sock_set_state tracepoint: {}",
+                "couldn't load sock:inet_sock_set_state tracepoint, \
+                 socket state changes won't be tracked (run `ptraf doctor`): {}",
                 error
             );
         }
 
         trace!("probe program loaded");
 
-        Ok(Self { bpf })
+        Ok(Self {
+            bpf,
+            attach_mode,
+            unix_events: None,
+        })
+    }
+
+    /// Returns how the send and receive probes are attached.
+    pub fn attach_mode(&self) -> AttachMode {
+        self.attach_mode
+    }
+
+    /// Enables `AF_UNIX` traffic accounting.
+    ///
+    /// Once [events](ProbeProgram::events) is called, `f` is called with batches of
+    /// [UnixMsgEvent] and the ID of the CPU that produced the events.
+    pub fn unix_events<F>(&mut self, f: F) -> Result<(), anyhow::Error>
+    where
+        F: Fn(EventIter<'_, UnixMsgEvent>, u32) + Send + Sync + 'static,
+    {
+        let mut settings = Array::<_, u32>::try_from(self.bpf.map_mut("SETTINGS")?)?;
+        settings.set(Setting::UnixSockets as u32, 1, 0)?;
+
+        self.unix_events = Some(Arc::new(f));
+
+        Ok(())
     }
 
     /// Consumes `self` and launches one task per CPU, each of which reads events from
@@ -119,6 +213,8 @@ impl ProbeProgram {
     /// * `buffer_size`: Size of the, per task, buffer for reading events.
     /// * `f`: The function that will be called with an `EventIter` and the ID of the CPU that produced the events.
     ///
+    /// `AF_UNIX` events are passed to the function registered with [unix_events](ProbeProgram::unix_events), if any.
+    ///
     /// # Returns
     ///
     /// A `Result` that either contains a `JoinSet` that can wait for all tasks to complete or an `anyhow::Error`
@@ -132,49 +228,26 @@ impl ProbeProgram {
         F: Fn(EventIter<'_>, u32) + Send + Sync + 'static,
     {
         let mut join_set = JoinSet::new();
-        let f = Arc::new(f);
 
         trace!("creating async perf event array");
 
         // Create an `AsyncPerfEventArray` for reading events.
-        let mut perf_array = AsyncPerfEventArray::try_from(self.bpf.map_mut("EVENTS")?)?;
+        let perf_array = AsyncPerfEventArray::try_from(self.bpf.map_mut("EVENTS")?)?;
+        let unix_perf_array = if self.unix_events.is_some() {
+            Some(AsyncPerfEventArray::try_from(
+                self.bpf.map_mut("UNIX_EVENTS")?,
+            )?)
+        } else {
+            None
+        };
 
         // Create an Arc of the bpf program so that each task retains it.
         let bpf = Arc::new(self.bpf);
 
-        trace!("spawning per cpu tasks");
-
-        // Iterate over each online CPU and spawn a task for each.
-        for cpu_id in online_cpus()? {
-            // Open a separate perf buffer for each CPU.
-            let mut buf = perf_array.open(cpu_id, Some(4096))?;
-            let f = Arc::clone(&f);
-            let bpf = Arc::clone(&bpf);
-
-            // Process each perf buffer in a separate task.
-            join_set.spawn(async move {
-                let _bpf = bpf;
-                let f = &*f;
-                // Create a buffer to store events for the task.
-                let mut buffers = (0..buffer_size.into())
-                    .map(|_| BytesMut::with_capacity(std::mem::size_of::<SockMsgEvent>()))
-                    .collect::<Vec<_>>();
-
-                trace!("waiting for events cpu={}", cpu_id);
-
-                loop {
-                    // Wait for events.
-                    let events = buf.read_events(buffers.as_mut_slice()).await?;
-                    let event_buf = EventIter::new(&buffers[0..events.read]);
-                    trace!(
-                        "run events callback cpu={} read={} lost={}",
-                        cpu_id,
-                        events.read,
-                        events.lost
-                    );
-                    f(event_buf, cpu_id);
-                }
-            });
+        spawn_readers(&mut join_set, &bpf, perf_array, buffer_size, Arc::new(f))?;
+
+        if let (Some(perf_array), Some(f)) = (unix_perf_array, self.unix_events) {
+            spawn_readers(&mut join_set, &bpf, perf_array, buffer_size, f)?;
         }
 
         // Return a join set to wait for all tasks to complete.
@@ -182,28 +255,92 @@ impl ProbeProgram {
     }
 }
 
-/// An iterator over [SockMsgEvent] references.
-pub struct EventIter<'a> {
+/// Launches one task per CPU reading events of type `T` from `perf_array`
+/// and passing them in a batch through `f`.
+fn spawn_readers<T, F>(
+    join_set: &mut JoinSet<Result<(), anyhow::Error>>,
+    bpf: &Arc<Bpf>,
+    mut perf_array: AsyncPerfEventArray<MapRefMut>,
+    buffer_size: NonZeroUsize,
+    f: Arc<F>,
+) -> Result<(), anyhow::Error>
+where
+    T: 'static,
+    F: Fn(EventIter<'_, T>, u32) + Send + Sync + ?Sized + 'static,
+{
+    trace!("spawning per cpu tasks");
+
+    // Iterate over each online CPU and spawn a task for each.
+    for cpu_id in online_cpus()? {
+        // Open a separate perf buffer for each CPU.
+        let mut buf = perf_array.open(cpu_id, Some(4096))?;
+        let f = Arc::clone(&f);
+        let bpf = Arc::clone(bpf);
+
+        // Process each perf buffer in a separate task.
+        join_set.spawn(async move {
+            let _bpf = bpf;
+            let f = &*f;
+            // Create a buffer to store events for the task.
+            let mut buffers = (0..buffer_size.into())
+                .map(|_| BytesMut::with_capacity(std::mem::size_of::<T>()))
+                .collect::<Vec<_>>();
+
+            trace!("waiting for events cpu={}", cpu_id);
+
+            loop {
+                // Wait for events.
+                let events = buf.read_events(buffers.as_mut_slice()).await?;
+                let event_buf = EventIter::new(&buffers[0..events.read], events.lost);
+                trace!(
+                    "run events callback cpu={} read={} lost={}",
+                    cpu_id,
+                    events.read,
+                    events.lost
+                );
+                f(event_buf, cpu_id);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// An iterator over event references, [SockMsgEvent] by default.
+pub struct EventIter<'a, T = SockMsgEvent> {
     buf: &'a [BytesMut],
     cur: usize,
+    lost: usize,
+    _marker: PhantomData<&'a T>,
 }
 
-impl<'a> EventIter<'a> {
-    fn new(buf: &'a [BytesMut]) -> Self {
-        Self { cur: 0, buf }
+impl<'a, T> EventIter<'a, T> {
+    fn new(buf: &'a [BytesMut], lost: usize) -> Self {
+        Self {
+            cur: 0,
+            buf,
+            lost,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of events the perf buffer dropped before this batch,
+    /// because it was full.
+    pub fn lost(&self) -> usize {
+        self.lost
     }
 }
 
-impl<'a> Iterator for EventIter<'a> {
-    type Item = &'a SockMsgEvent;
+impl<'a, T> Iterator for EventIter<'a, T> {
+    type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.cur >= self.buf.len() {
             return None;
         }
 
-        // SAFETY: This EventIter is always created from buffers that contains [SockMsgEvent].
-        let msg: &SockMsgEvent = unsafe { &*(self.buf[self.cur].as_ptr() as *const SockMsgEvent) };
+        // SAFETY: This EventIter is always created from buffers read from the perf array of `T`.
+        let msg: &T = unsafe { &*(self.buf[self.cur].as_ptr() as *const T) };
         self.cur += 1;
 
         Some(msg)
@@ -219,5 +356,5 @@ impl<'a> Iterator for EventIter<'a> {
     }
 }
 
-impl ExactSizeIterator for EventIter<'_> {}
-impl FusedIterator for EventIter<'_> {}
+impl<T> ExactSizeIterator for EventIter<'_, T> {}
+impl<T> FusedIterator for EventIter<'_, T> {}
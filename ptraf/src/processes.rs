@@ -0,0 +1,114 @@
+//! Details of the processes read from procfs.
+//!
+//! The details are cached for a few seconds per pid, the filters look them up for every
+//! socket on every refresh of the display.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use procfs::process::Process;
+
+/// Time after which the details of a process are read again, pids are recycled and
+/// processes may exec.
+const TTL: Duration = Duration::from_secs(5);
+
+/// Number of processes cached before the expired ones are evicted, then the oldest ones.
+const CAPACITY: usize = 4096;
+
+type Cache = HashMap<u32, (Instant, Arc<ProcessInfo>)>;
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+#[derive(Debug, Default)]
+pub struct ProcessInfo {
+    /// Path of the executable.
+    pub exe: Option<Arc<str>>,
+    /// Arguments separated by spaces.
+    pub cmdline: Option<Arc<str>>,
+}
+
+impl ProcessInfo {
+    fn from_procfs(pid: u32) -> Self {
+        let process = match Process::new(pid as i32) {
+            Ok(process) => process,
+            Err(_) => return Self::default(),
+        };
+
+        Self {
+            exe: process
+                .exe()
+                .ok()
+                .and_then(|exe| exe.to_str().map(Arc::from)),
+            cmdline: process.cmdline().ok().map(|args| args.join(" ").into()),
+        }
+    }
+
+    /// Returns the file name of the executable.
+    pub fn name(&self) -> Option<&str> {
+        self.exe.as_deref().and_then(|exe| exe.rsplit('/').next())
+    }
+}
+
+/// Returns the details of the process `pid`, empty if it has exited or can't be read.
+pub fn info(pid: u32) -> Arc<ProcessInfo> {
+    let now = Instant::now();
+
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::default);
+
+    match cache.get(&pid) {
+        Some((read_at, info)) if now.duration_since(*read_at) < TTL => Arc::clone(info),
+        _ => {
+            if cache.len() >= CAPACITY {
+                evict(cache, now);
+            }
+
+            let info = Arc::new(ProcessInfo::from_procfs(pid));
+            cache.insert(pid, (now, Arc::clone(&info)));
+            info
+        }
+    }
+}
+
+/// Evicts the expired processes, or the oldest quarter of the cache if none is.
+fn evict(cache: &mut Cache, now: Instant) {
+    cache.retain(|_, (read_at, _)| now.duration_since(*read_at) < TTL);
+
+    if cache.len() >= CAPACITY {
+        let mut read_at: Vec<_> = cache.values().map(|(read_at, _)| *read_at).collect();
+        let (_, oldest, _) = read_at.select_nth_unstable(CAPACITY / 4);
+        let oldest = *oldest;
+        cache.retain(|_, (read_at, _)| *read_at > oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_oldest() {
+        let now = Instant::now();
+        let info = Arc::new(ProcessInfo::default());
+
+        let mut cache: Cache = (0..CAPACITY as u32)
+            .map(|pid| {
+                let read_at = now - Duration::from_millis(u64::from(pid));
+                (pid, (read_at, Arc::clone(&info)))
+            })
+            .collect();
+        evict(&mut cache, now);
+
+        // The last pids were read first.
+        assert!(cache.len() < CAPACITY);
+        assert!(cache.contains_key(&0));
+        assert!(!cache.contains_key(&(CAPACITY as u32 - 1)));
+
+        cache.insert(CAPACITY as u32, (now - TTL, info));
+        evict(&mut cache, now);
+        assert!(!cache.contains_key(&(CAPACITY as u32)));
+    }
+}
@@ -1,21 +1,38 @@
 use std::{
-    collections::VecDeque,
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt,
     hash::Hash,
     net::{IpAddr, SocketAddr},
     ops::{AddAssign, Deref},
+    str::FromStr,
     sync::{
-        atomic::{AtomicU64, Ordering},
-        RwLock, RwLockReadGuard,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard,
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use dashmap::{DashMap, DashSet};
+use dashmap::DashMap;
 use fxhash::FxBuildHasher;
-use ptraf_common::{Channel, SockMsgEvent, SockType};
-use ptraf_filter::Filterable;
+use ptraf_common::{Channel, SockMsgEvent, SockType, TcpState, UnixMsgEvent, TASK_COMM_LEN};
+use ptraf_filter::{Filterable, Lookup};
 
-use crate::clock::Timestamp;
+use log::warn;
+
+use crate::{clock::Timestamp, dns, processes, users};
+
+pub use self::{
+    disk::DiskStore,
+    histogram::SizeHistogram,
+    query::{GroupBy, GroupKey, Query},
+};
+
+mod disk;
+mod histogram;
+mod query;
+mod topk;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Interest {
@@ -23,18 +40,102 @@ pub enum Interest {
     RemoteSocket(SocketAddr),
     LocalSocket(SocketAddr),
     Pid(u32),
+    LocalPort(u16),
+    RemotePort(u16),
+    ProcessName(Comm),
+    Uid(u32),
+    SockType(SockType),
     All,
 }
 
-impl Interest {
-    pub fn interests_from_msg(msg: &SockMsgEvent) -> [Interest; 5] {
-        [
-            Interest::Pid(msg.pid),
-            Interest::LocalSocket(msg.local_sock_addr()),
-            Interest::RemoteSocket(msg.remote_sock_addr()),
-            Interest::RemoteIp(msg.remote_addr.into()),
-            Interest::All,
-        ]
+/// Optional dimensions of the index of the segments, see [Store::with_dimensions].
+///
+/// The pids, sockets and remote IPs are always indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    LocalPort,
+    RemotePort,
+    ProcessName,
+    Uid,
+    SockType,
+}
+
+impl Dimension {
+    pub const ALL: [Dimension; 5] = [
+        Self::LocalPort,
+        Self::RemotePort,
+        Self::ProcessName,
+        Self::Uid,
+        Self::SockType,
+    ];
+
+    /// Tells whether the `interest` is in this dimension.
+    fn contains(self, interest: &Interest) -> bool {
+        matches!(
+            (self, interest),
+            (Self::LocalPort, Interest::LocalPort(_))
+                | (Self::RemotePort, Interest::RemotePort(_))
+                | (Self::ProcessName, Interest::ProcessName(_))
+                | (Self::Uid, Interest::Uid(_))
+                | (Self::SockType, Interest::SockType(_))
+        )
+    }
+}
+
+impl FromStr for Dimension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "local-port" => Self::LocalPort,
+            "remote-port" => Self::RemotePort,
+            "process-name" => Self::ProcessName,
+            "uid" => Self::Uid,
+            "sock-type" => Self::SockType,
+            _ => {
+                return Err(format!(
+                    "unknown dimension {s}, expected one of local-port, remote-port, \
+                     process-name, uid, sock-type"
+                ))
+            }
+        })
+    }
+}
+
+/// Command name of a process, as reported by the kernel.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Comm([u8; TASK_COMM_LEN]);
+
+impl Comm {
+    /// Returns the command name, truncated to [TASK_COMM_LEN] - 1 bytes like the kernel does.
+    pub fn new(name: &[u8]) -> Self {
+        let mut comm = [0; TASK_COMM_LEN];
+        let len = name.len().min(TASK_COMM_LEN - 1);
+        comm[..len].copy_from_slice(&name[..len]);
+        Self(comm)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|c| *c == 0).unwrap_or(TASK_COMM_LEN);
+        &self.0[..len]
+    }
+}
+
+impl From<&str> for Comm {
+    fn from(name: &str) -> Self {
+        Self::new(name.as_bytes())
+    }
+}
+
+impl fmt::Display for Comm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        String::from_utf8_lossy(self.as_bytes()).fmt(f)
+    }
+}
+
+impl fmt::Debug for Comm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self.as_bytes()))
     }
 }
 
@@ -68,21 +169,36 @@ pub struct Stat {
     pub rx_packet_count: u64,
     pub tx: u64,
     pub tx_packet_count: u64,
+    /// Distribution of the message sizes in both directions.
+    pub sizes: SizeHistogram,
 }
 
 impl Stat {
-    #[cfg(test)]
     pub fn total(&self) -> u64 {
         self.rx + self.tx
     }
 
-    pub fn merge(&mut self, other: &Self) {
-        *self = Self {
-            rx: self.rx + other.rx,
-            rx_packet_count: self.rx_packet_count + other.rx_packet_count,
-            tx: self.tx + other.tx,
-            tx_packet_count: self.tx_packet_count + other.tx_packet_count,
+    /// Accounts a message of `len` bytes.
+    fn increment(&mut self, channel: Channel, len: u64) {
+        match channel {
+            Channel::Rx => {
+                self.rx += len;
+                self.rx_packet_count += 1;
+            }
+            Channel::Tx => {
+                self.tx += len;
+                self.tx_packet_count += 1;
+            }
         }
+        self.sizes.record(len, 1);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.rx += other.rx;
+        self.rx_packet_count += other.rx_packet_count;
+        self.tx += other.tx;
+        self.tx_packet_count += other.tx_packet_count;
+        self.sizes.merge(&other.sizes);
     }
 }
 
@@ -105,6 +221,7 @@ impl From<&'_ Metrics> for Stat {
             rx_packet_count: m.rx.count(),
             tx: m.tx.size(),
             tx_packet_count: m.tx.count(),
+            sizes: m.sizes.load(),
         }
     }
 }
@@ -113,6 +230,7 @@ impl From<&'_ Metrics> for Stat {
 struct Metrics {
     rx: Traffic,
     tx: Traffic,
+    sizes: histogram::AtomicSizeHistogram,
 }
 
 impl Metrics {
@@ -124,18 +242,125 @@ impl Metrics {
         }
     }
 
+    /// Accounts a message of `len` bytes.
+    ///
+    /// The aggregated traffic is added with its distribution, see [Metrics::add].
     #[inline]
-    fn increment(&self, channel: Channel, val: u64, count: u64) {
-        self.with_channel(channel).increment(val, count)
+    fn increment(&self, channel: Channel, len: u64) {
+        self.with_channel(channel).increment(len, 1);
+        self.sizes.record(len, 1);
+    }
+
+    fn merge(&self, other: &Metrics) {
+        self.add(&other.into());
+    }
+
+    fn add(&self, stat: &Stat) {
+        // Flows are mostly one-way in a batch.
+        if stat.rx_packet_count > 0 {
+            self.rx.increment(stat.rx, stat.rx_packet_count);
+        }
+        if stat.tx_packet_count > 0 {
+            self.tx.increment(stat.tx, stat.tx_packet_count);
+        }
+        self.sizes.merge(&stat.sizes);
+    }
+}
+
+/// A connection, the traffic of a batch is aggregated by flow before updating the segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Flow {
+    pid: u32,
+    uid: u32,
+    gid: u32,
+    comm: Comm,
+    local: SocketAddr,
+    remote: SocketAddr,
+    sock_type: SockType,
+}
+
+impl Flow {
+    fn socket(&self, state: TcpState) -> Socket {
+        Socket {
+            pid: self.pid,
+            uid: self.uid,
+            gid: self.gid,
+            comm: self.comm,
+            local: self.local,
+            remote: self.remote,
+            sock_type: self.sock_type,
+            state,
+        }
+    }
+
+    fn interest(&self, dimension: Dimension) -> Interest {
+        match dimension {
+            Dimension::LocalPort => Interest::LocalPort(self.local.port()),
+            Dimension::RemotePort => Interest::RemotePort(self.remote.port()),
+            Dimension::ProcessName => Interest::ProcessName(self.comm),
+            Dimension::Uid => Interest::Uid(self.uid),
+            Dimension::SockType => Interest::SockType(self.sock_type),
+        }
+    }
+
+    fn interests(&self) -> [Interest; 5] {
+        [
+            Interest::Pid(self.pid),
+            Interest::LocalSocket(self.local),
+            Interest::RemoteSocket(self.remote),
+            Interest::RemoteIp(self.remote.ip()),
+            Interest::All,
+        ]
+    }
+}
+
+impl From<&SockMsgEvent> for Flow {
+    fn from(msg: &SockMsgEvent) -> Self {
+        Self {
+            pid: msg.pid,
+            uid: msg.uid,
+            gid: msg.gid,
+            comm: Comm::new(msg.comm()),
+            local: msg.local_sock_addr(),
+            remote: msg.remote_sock_addr(),
+            sock_type: msg.sock_type,
+        }
+    }
+}
+
+/// Traffic of a flow, and the state of its socket after the last message.
+#[derive(Debug, Default)]
+struct FlowStat {
+    stat: Stat,
+    state: TcpState,
+}
+
+/// Traffic aggregated by flow.
+type Flows = HashMap<Flow, FlowStat, FxBuildHasher>;
+
+/// Traffic of the flows of a batch aggregated by interest.
+type Interests = HashMap<Interest, Stat, FxBuildHasher>;
+
+fn aggregate(flows: &mut Flows, msg: &SockMsgEvent) {
+    if let Ok(len) = msg.packet_size() {
+        let flow_stat = flows.entry(msg.into()).or_default();
+        flow_stat.stat.increment(msg.channel, len.into());
+        flow_stat.state = msg.state;
     }
 }
 
 #[derive(Copy, Clone, Eq, Debug)]
 pub struct Socket {
     pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Command name of the process when the socket was first seen.
+    pub comm: Comm,
     pub local: SocketAddr,
     pub remote: SocketAddr,
     pub sock_type: SockType,
+    /// State of a stream socket after its last message in the segment.
+    pub state: TcpState,
 }
 
 impl Socket {
@@ -143,8 +368,13 @@ impl Socket {
         match interest {
             Interest::RemoteIp(ip) => ip == self.remote.ip(),
             Interest::RemoteSocket(sock) => sock == self.remote,
-            Interest::LocalSocket(sock) => sock == self.remote,
+            Interest::LocalSocket(sock) => sock == self.local,
             Interest::Pid(pid) => pid == self.pid,
+            Interest::LocalPort(port) => port == self.local.port(),
+            Interest::RemotePort(port) => port == self.remote.port(),
+            Interest::ProcessName(comm) => comm == self.comm,
+            Interest::Uid(uid) => uid == self.uid,
+            Interest::SockType(sock_type) => sock_type == self.sock_type,
             Interest::All => true,
         }
     }
@@ -152,12 +382,7 @@ impl Socket {
 
 impl From<&SockMsgEvent> for Socket {
     fn from(msg: &SockMsgEvent) -> Self {
-        Self {
-            pid: msg.pid,
-            local: msg.local_sock_addr(),
-            remote: msg.remote_sock_addr(),
-            sock_type: msg.sock_type,
-        }
+        Flow::from(msg).socket(msg.state)
     }
 }
 
@@ -186,6 +411,37 @@ impl Filterable for Socket {
         }
     }
 
+    fn sock_type(&self) -> Option<ptraf_filter::SockType> {
+        match self.sock_type {
+            SockType::Stream => ptraf_filter::SockType::Stream.into(),
+            SockType::Dgram => ptraf_filter::SockType::Dgram.into(),
+            SockType::Raw => ptraf_filter::SockType::Raw.into(),
+            SockType::Rdm => ptraf_filter::SockType::Rdm.into(),
+            SockType::Seqpacket => ptraf_filter::SockType::Seqpacket.into(),
+            SockType::Dccp => ptraf_filter::SockType::Dccp.into(),
+            SockType::Packet => ptraf_filter::SockType::Packet.into(),
+            _ => None,
+        }
+    }
+
+    fn state(&self) -> Option<ptraf_filter::TcpState> {
+        match self.state {
+            TcpState::Established => ptraf_filter::TcpState::Established.into(),
+            TcpState::SynSent => ptraf_filter::TcpState::SynSent.into(),
+            TcpState::SynRecv => ptraf_filter::TcpState::SynRecv.into(),
+            TcpState::FinWait1 => ptraf_filter::TcpState::FinWait1.into(),
+            TcpState::FinWait2 => ptraf_filter::TcpState::FinWait2.into(),
+            TcpState::TimeWait => ptraf_filter::TcpState::TimeWait.into(),
+            TcpState::Close => ptraf_filter::TcpState::Close.into(),
+            TcpState::CloseWait => ptraf_filter::TcpState::CloseWait.into(),
+            TcpState::LastAck => ptraf_filter::TcpState::LastAck.into(),
+            TcpState::Listen => ptraf_filter::TcpState::Listen.into(),
+            TcpState::Closing => ptraf_filter::TcpState::Closing.into(),
+            TcpState::NewSynRecv => ptraf_filter::TcpState::NewSynRecv.into(),
+            TcpState::Unknown => None,
+        }
+    }
+
     fn ip_version(&self) -> ptraf_filter::IpVersion {
         if self.local.is_ipv4() {
             ptraf_filter::IpVersion::IpV4
@@ -209,38 +465,293 @@ impl Filterable for Socket {
     fn remote_port(&self) -> u16 {
         self.remote.port()
     }
+
+    fn uid(&self) -> Option<u32> {
+        Some(self.uid)
+    }
+
+    fn user_name(&self) -> Option<&str> {
+        users::user_name(self.uid)
+    }
+
+    fn gid(&self) -> Option<u32> {
+        Some(self.gid)
+    }
+
+    fn group_name(&self) -> Option<&str> {
+        users::group_name(self.gid)
+    }
+
+    fn comm(&self) -> Option<Cow<'_, str>> {
+        Some(String::from_utf8_lossy(self.comm.as_bytes())).filter(|comm| !comm.is_empty())
+    }
+
+    fn exe(&self) -> Option<Arc<str>> {
+        processes::info(self.pid).exe.clone()
+    }
+
+    fn cmdline(&self) -> Option<Arc<str>> {
+        processes::info(self.pid).cmdline.clone()
+    }
+
+    fn host_name(&self, addr: IpAddr) -> Lookup<Arc<str>> {
+        dns::host_name(addr)
+    }
+
+    fn host_addrs(&self, name: &str) -> Lookup<Arc<[IpAddr]>> {
+        dns::host_addrs(name)
+    }
+}
+
+/// Path or abstract name of a unix socket.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnixPath(Box<[u8]>);
+
+impl UnixPath {
+    /// Tells whether the path is in the abstract namespace.
+    pub fn is_abstract(&self) -> bool {
+        self.0.first() == Some(&0)
+    }
+}
+
+impl std::fmt::Display for UnixPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            f.write_str("(unnamed)")
+        } else if self.is_abstract() {
+            write!(f, "@{}", String::from_utf8_lossy(&self.0[1..]))
+        } else {
+            f.write_str(&String::from_utf8_lossy(&self.0))
+        }
+    }
+}
+
+impl std::fmt::Debug for UnixPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+/// A unix socket keyed by its path and the processes at both ends.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct UnixSocket {
+    pub pid: u32,
+    pub peer_pid: u32,
+    pub path: UnixPath,
+    pub sock_type: SockType,
+}
+
+impl From<&UnixMsgEvent> for UnixSocket {
+    fn from(msg: &UnixMsgEvent) -> Self {
+        Self {
+            pid: msg.pid,
+            peer_pid: msg.peer_pid,
+            path: UnixPath(msg.path().into()),
+            sock_type: msg.sock_type,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct Segment {
     index: DashMap<Interest, Metrics, FxBuildHasher>,
-    socks: DashSet<Socket, FxBuildHasher>,
+    /// Sockets by local address.
+    socks: DashMap<SocketAddr, Socket, FxBuildHasher>,
+    unix_socks: DashMap<UnixSocket, Metrics, FxBuildHasher>,
+    /// Number of events lost by the perf buffers, by CPU.
+    lost_events: DashMap<u32, u64, FxBuildHasher>,
+    top_talkers: Mutex<topk::TopTalkers>,
+    /// Traffic of the sockets beyond the limits of the store, by pid.
+    overflow: DashMap<u32, Metrics, FxBuildHasher>,
+    /// Traffic of the unix sockets beyond the limits of the store, by pid.
+    unix_overflow: DashMap<u32, Metrics, FxBuildHasher>,
+    /// Number of messages accounted in the overflow buckets.
+    overflowed: AtomicU64,
 }
 
 impl Segment {
-    pub fn batch_update<'a>(&self, messages: impl IntoIterator<Item = &'a SockMsgEvent>) {
+    /// Drains the traffic of the `flows` into the segment, indexed by the `dimensions` and
+    /// within the `limits`, `sockets` counts the sockets of all the segments.
+    ///
+    /// The traffic of the sockets that can't be tracked is accounted in the overflow bucket of
+    /// their process, and still in the totals of the process and of the dimensions.
+    ///
+    /// The flows are aggregated by interest first, the index and the top talkers are updated
+    /// once per interest of the batch.
+    fn merge_flows(
+        &self,
+        flows: &mut Flows,
+        dimensions: &[Dimension],
+        limits: &Limits,
+        sockets: &AtomicUsize,
+    ) {
+        // The interests of a single flow are distinct, there is nothing to aggregate.
+        if flows.len() == 1 {
+            let mut top_talkers = self.top_talkers();
+            self.drain_flows(flows, dimensions, limits, sockets, |interest, stat| {
+                self.add(interest, stat);
+                top_talkers.insert(interest, stat.total());
+            });
+            return;
+        }
+
+        thread_local! {
+            static INTERESTS: RefCell<Interests> = RefCell::default();
+        }
+
+        INTERESTS.with(|interests| {
+            let interests = &mut *interests.borrow_mut();
+            self.drain_flows(flows, dimensions, limits, sockets, |interest, stat| {
+                *interests.entry(interest).or_default() += stat;
+            });
+
+            for (interest, stat) in interests.iter() {
+                self.add(*interest, stat);
+            }
+
+            // Updated once the index is, so concurrent batches only contend on the segment maps.
+            let mut top_talkers = self.top_talkers();
+            for (interest, stat) in interests.drain() {
+                top_talkers.insert(interest, stat.total());
+            }
+        });
+    }
+
+    /// Adds the traffic of `interest` to the index.
+    fn add(&self, interest: Interest, stat: &Stat) {
+        // Most interests are already indexed, their metrics are atomic.
+        match self.index.get(&interest) {
+            Some(metrics) => metrics.add(stat),
+            None => self.index.entry(interest).or_default().add(stat),
+        }
+    }
+
+    /// Drains the `flows` into the sockets of the segment, and passes their traffic by
+    /// interest to `add`.
+    fn drain_flows(
+        &self,
+        flows: &mut Flows,
+        dimensions: &[Dimension],
+        limits: &Limits,
+        sockets: &AtomicUsize,
+        mut add: impl FnMut(Interest, &Stat),
+    ) {
+        for (flow, FlowStat { stat, state }) in flows.drain() {
+            let socket = flow.socket(state);
+            // The other details of a tracked socket are kept, the readers always find it.
+            let updated = self
+                .socks
+                .get_mut(&socket.local)
+                .map(|mut tracked| tracked.state = state)
+                .is_some();
+            let tracked = updated
+                || (self.socks.len() < limits.sockets_per_segment
+                    && sockets.load(Ordering::Relaxed) < limits.sockets
+                    && {
+                        if self.socks.insert(socket.local, socket).is_none() {
+                            sockets.fetch_add(1, Ordering::Relaxed);
+                        }
+                        true
+                    });
+
+            let mut add = |interest: Interest| add(interest, &stat);
+
+            dimensions
+                .iter()
+                .for_each(|dimension| add(flow.interest(*dimension)));
+
+            if tracked {
+                flow.interests().into_iter().for_each(&mut add);
+            } else {
+                add(Interest::Pid(flow.pid));
+                add(Interest::All);
+                self.overflow.entry(flow.pid).or_default().add(&stat);
+                self.overflowed.fetch_add(
+                    stat.rx_packet_count + stat.tx_packet_count,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    /// Accounts the `AF_UNIX` messages, the traffic of the sockets beyond
+    /// [Limits::sockets_per_segment] is accounted in the unix overflow bucket of their process.
+    pub fn batch_update_unix<'a>(
+        &self,
+        messages: impl IntoIterator<Item = &'a UnixMsgEvent>,
+        limits: &Limits,
+    ) {
         for msg in messages {
-            if let Ok(len) = msg.packet_size() {
-                let len = len.into();
-
-                self.socks.insert(msg.into());
-
-                for interest in Interest::interests_from_msg(msg) {
-                    self.index
-                        .entry(interest)
-                        .and_modify(|stat| {
-                            stat.increment(msg.channel, len, 1);
-                        })
-                        .or_insert_with(|| {
-                            let stat = Metrics::default();
-                            stat.increment(msg.channel, len, 1);
-                            stat
-                        });
-                }
+            let len = match msg.packet_size() {
+                Ok(len) => len,
+                Err(_) => continue,
+            };
+
+            let socket = UnixSocket::from(msg);
+            if self.unix_socks.contains_key(&socket)
+                || self.unix_socks.len() < limits.sockets_per_segment
+            {
+                self.unix_socks
+                    .entry(socket)
+                    .or_default()
+                    .increment(msg.channel, len.into());
+            } else {
+                self.unix_overflow
+                    .entry(msg.pid)
+                    .or_default()
+                    .increment(msg.channel, len.into());
+                self.overflowed.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
 
+    /// Merges the data of `other` into this segment.
+    fn merge(&self, other: Segment) {
+        for (interest, metrics) in other.index {
+            self.index.entry(interest).or_default().merge(&metrics);
+        }
+        for (local, socket) in other.socks {
+            self.socks.entry(local).or_insert(socket);
+        }
+        for (socket, metrics) in other.unix_socks {
+            self.unix_socks.entry(socket).or_default().merge(&metrics);
+        }
+        for (cpu_id, count) in other.lost_events {
+            self.record_lost_events(cpu_id, count);
+        }
+        for (pid, metrics) in other.overflow {
+            self.overflow.entry(pid).or_default().merge(&metrics);
+        }
+        for (pid, metrics) in other.unix_overflow {
+            self.unix_overflow.entry(pid).or_default().merge(&metrics);
+        }
+        self.overflowed
+            .fetch_add(other.overflowed.into_inner(), Ordering::Relaxed);
+        self.top_talkers()
+            .merge(&other.top_talkers.into_inner().unwrap());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.index.is_empty()
+            && self.unix_socks.is_empty()
+            && self.unix_overflow.is_empty()
+            && self.lost_events.is_empty()
+    }
+
+    pub fn record_lost_events(&self, cpu_id: u32, count: u64) {
+        *self.lost_events.entry(cpu_id).or_default() += count;
+    }
+
+    /// Returns the number of events lost on all the CPUs.
+    pub fn lost_events(&self) -> u64 {
+        self.lost_events.iter().map(|entry| *entry.value()).sum()
+    }
+
+    /// Returns the number of messages accounted in the overflow buckets.
+    pub fn overflowed(&self) -> u64 {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
     #[cfg(test)]
     pub fn total(&self, channel: Option<Channel>) -> u64 {
         self.stat_by_interest(&Interest::All)
@@ -263,68 +774,192 @@ impl Segment {
         self.index.get(interest).map(|m| (&*m).into())
     }
 
+    /// Returns the socket bound to the `local` address.
+    pub fn socket(&self, local: &SocketAddr) -> Option<Socket> {
+        self.socks.get(local).map(|socket| *socket)
+    }
+
+    fn top_talkers(&self) -> MutexGuard<'_, topk::TopTalkers> {
+        self.top_talkers.lock().unwrap()
+    }
+
     pub fn for_each_socket(&self, mut f: impl FnMut(&Socket)) {
-        self.socks.iter().for_each(|sock| f(sock.deref()));
+        self.socks.iter().for_each(|entry| f(entry.value()));
+    }
+
+    /// Calls `f` with the traffic of the sockets beyond the limits of each process.
+    pub fn for_each_overflow(&self, mut f: impl FnMut(u32, Stat)) {
+        self.overflow
+            .iter()
+            .for_each(|entry| f(*entry.key(), entry.value().into()));
+    }
+
+    pub fn for_each_unix_socket(&self, mut f: impl FnMut(&UnixSocket, Stat)) {
+        self.unix_socks
+            .iter()
+            .for_each(|entry| f(entry.key(), entry.value().into()));
+    }
+
+    /// Calls `f` with the traffic of the unix sockets beyond the limits of each process.
+    pub fn for_each_unix_overflow(&self, mut f: impl FnMut(u32, Stat)) {
+        self.unix_overflow
+            .iter()
+            .for_each(|entry| f(*entry.key(), entry.value().into()));
     }
 }
 
-struct WriteTimeSegment<'a>(RwLockReadGuard<'a, VecDeque<TimeSegment>>);
+struct WriteTimeSegment<'a> {
+    guard: RwLockReadGuard<'a, VecDeque<TimeSegment>>,
+    index: usize,
+}
 
 impl<'a> Deref for WriteTimeSegment<'a> {
     type Target = TimeSegment;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        self.0.back().unwrap()
+        &self.guard[self.index]
     }
 }
 
 /// A reader for a sequence of time segments.
 ///
 /// This struct provides a read-only view of a sequence of `TimeSegment` objects in the storage of a `Store`.
+/// The segments of coarser tiers come first, all the segments are in chronological order.
 ///
 /// Note that creating a `TimeSegmentsView` requires acquiring a read lock on the storage of the `Store`.
 /// This means that while a `TimeSegmentsView` exists, new time segments cannot be added to the storage,
 /// but existing time segments can still be updated.
-pub struct TimeSegmentsView<'a>(RwLockReadGuard<'a, VecDeque<TimeSegment>>);
+pub struct TimeSegmentsView<'a> {
+    /// Partitions read from the disk.
+    archive: Vec<disk::Partition>,
+    /// Tiers from the finest to the coarsest.
+    tiers: Vec<RwLockReadGuard<'a, VecDeque<TimeSegment>>>,
+}
+
+impl<'a> TimeSegmentsView<'a> {
+    fn new(tiers: Vec<RwLockReadGuard<'a, VecDeque<TimeSegment>>>) -> Self {
+        Self {
+            archive: Vec::new(),
+            tiers,
+        }
+    }
 
-impl TimeSegmentsView<'_> {
     /// Returns the number of time segments in the reader.
     #[allow(unused)]
     #[inline]
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.iter().count()
     }
 
     /// Returns `true` if the reader contains no time segments.
     #[allow(unused)]
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.iter().next().is_none()
     }
 
     /// Returns a reference to the first time segment in the reader, or `None` if the reader is empty.
     #[inline]
     pub fn oldest(&self) -> Option<&TimeSegment> {
-        self.0.front()
+        self.iter().next()
     }
 
     /// Returns a reference to the last time segment in the reader, or `None` if the reader is empty.
     #[allow(unused)]
     #[inline]
     pub fn newest(&self) -> Option<&TimeSegment> {
-        self.0.back()
+        self.iter().next_back()
     }
 
     /// Returns an iterator over the time segments in the reader.
     #[inline]
-    pub fn iter(
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TimeSegment> + '_ {
+        // The disk also has the segments of the coarsest tier that are complete.
+        let memory_oldest = self
+            .tiers
+            .iter()
+            .rev()
+            .find_map(|tier| tier.front())
+            .map(|time_segment| time_segment.ts);
+
+        self.archive
+            .iter()
+            .flat_map(|partition| partition.iter().map(|time_segment| &**time_segment))
+            .filter(move |time_segment| {
+                !matches!(memory_oldest, Some(oldest) if time_segment.ts >= oldest)
+            })
+            // Tiers are stored from the finest to the coarsest.
+            .chain(self.tiers.iter().rev().flat_map(|tier| tier.iter()))
+    }
+}
+
+/// A coarser tier of the history: segments expiring from the finer tier are merged
+/// into segments of `resolution`, which are kept for `retention`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rollup {
+    pub resolution: Duration,
+    pub retention: Duration,
+}
+
+/// Caps on the number of sockets tracked in memory, see [Store::with_limits].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of sockets in a segment of the finest tier.
+    pub sockets_per_segment: usize,
+    /// Maximum number of sockets over all the segments in memory.
+    pub sockets: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            sockets_per_segment: usize::MAX,
+            sockets: usize::MAX,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tier {
+    window: Duration,
+    capacity: usize,
+    segments: RwLock<VecDeque<TimeSegment>>,
+}
+
+impl Tier {
+    fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            segments: RwLock::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn retention(&self) -> Duration {
+        self.window * self.capacity as u32
+    }
+
+    /// Creates the missing segments up to `ts` and passes the expired ones to `expire`.
+    fn extend_to(
         &self,
-    ) -> impl Iterator<Item = &TimeSegment>
-           + ExactSizeIterator<Item = &TimeSegment>
-           + DoubleEndedIterator<Item = &TimeSegment>
-           + '_ {
-        self.0.iter()
+        dequeue: &mut VecDeque<TimeSegment>,
+        ts: Timestamp,
+        mut expire: impl FnMut(TimeSegment),
+    ) {
+        while !matches!(dequeue.back(), Some(newest) if newest.ts >= ts) {
+            let segment_ts = dequeue.back().map_or(ts, |newest| newest.ts + self.window);
+
+            if dequeue.len() >= self.capacity {
+                if let Some(expired) = dequeue.pop_front() {
+                    expire(expired);
+                }
+            }
+            dequeue.push_back(TimeSegment {
+                ts: segment_ts,
+                segment: Segment::default(),
+            });
+        }
     }
 }
 
@@ -333,11 +968,20 @@ impl TimeSegmentsView<'_> {
 /// The Store struct maintains a list of TimeSegment instances, with each segment representing
 /// a fixed time interval or window. The store aggregates the data within each segment and
 /// provides an overall view of the data over the entire time period covered by the store.
+///
+/// The history can be extended with coarser tiers (see [Rollup]): the segments expiring from
+/// a tier are merged into the segments of the next one.
 #[derive(Debug)]
 pub struct Store {
-    window: Duration,
-    capacity: usize,
-    segments: RwLock<VecDeque<TimeSegment>>,
+    /// Tiers from the finest to the coarsest, the first one receives the updates.
+    tiers: Vec<Tier>,
+    /// Where the completed segments of the coarsest tier are persisted.
+    disk: Option<DiskStore>,
+    /// Optional dimensions of the index.
+    dimensions: Vec<Dimension>,
+    limits: Limits,
+    /// Number of sockets tracked by the segments in memory.
+    sockets: AtomicUsize,
 }
 
 impl Store {
@@ -348,92 +992,391 @@ impl Store {
     /// * `window`: A Duration representing the time interval between each segment in the store.
     /// * `capacity`: An usize representing the maximum number of segments that the store can hold.
     pub fn new(window: Duration, capacity: usize) -> Self {
-        let deque = VecDeque::with_capacity(capacity);
-
         Self {
-            window,
-            capacity,
-            segments: RwLock::new(deque),
+            tiers: vec![Tier::new(window, capacity)],
+            disk: None,
+            dimensions: Dimension::ALL.to_vec(),
+            limits: Limits::default(),
+            sockets: AtomicUsize::new(0),
         }
     }
 
-    pub fn window(&self) -> Duration {
-        self.window
+    /// Returns a store keeping `capacity` segments of `window`, then the expired segments
+    /// rolled up in the coarser tiers `rollups`.
+    ///
+    /// The resolution of each rollup must be a multiple of the resolution of the previous tier.
+    pub fn with_rollups(
+        window: Duration,
+        capacity: usize,
+        rollups: &[Rollup],
+    ) -> Result<Self, anyhow::Error> {
+        let mut store = Self::new(window, capacity);
+
+        for rollup in rollups {
+            let previous = store.tiers.last().map_or(window, |tier| tier.window);
+
+            anyhow::ensure!(
+                rollup.resolution > previous
+                    && rollup.resolution.as_nanos() % previous.as_nanos() == 0,
+                "the rollup resolution {:?} must be a multiple of {:?}",
+                rollup.resolution,
+                previous
+            );
+
+            let capacity = (rollup.retention.as_nanos() / rollup.resolution.as_nanos()).max(1);
+            store
+                .tiers
+                .push(Tier::new(rollup.resolution, capacity as usize));
+        }
+
+        Ok(store)
     }
 
-    pub fn max_capacity(&self) -> usize {
-        self.capacity
+    /// Persists the history in `disk`.
+    ///
+    /// The segments of the coarsest tier are handed to the writer thread of the disk as soon as
+    /// they are complete, see [flush](Self::flush) for the most recent ones. The views read the
+    /// segments older than the history in memory from the disk.
+    pub fn with_disk(mut self, disk: DiskStore) -> Self {
+        self.disk = Some(disk);
+        self
     }
 
-    /// Update the store from the messages.
+    /// Caps the number of sockets tracked in memory.
     ///
-    /// The `ts` parameter represents the timestamp of the update and must be from the same monolithic clock.
+    /// Beyond the limits, the traffic of the new sockets is aggregated by process, see
+    /// [Segment::for_each_overflow] and [overflowed](Self::overflowed).
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only indexes the traffic by the `dimensions` in addition to the pids, sockets and
+    /// remote IPs, all of them are indexed by default.
+    ///
+    /// Each dimension costs an entry per distinct value in every segment. The interests of
+    /// the other dimensions are computed from the sockets by the queries.
+    pub fn with_dimensions(mut self, dimensions: &[Dimension]) -> Self {
+        self.dimensions = dimensions.to_vec();
+        self
+    }
+
+    /// Tells whether the traffic of the `interest` is indexed by the segments.
+    pub fn is_indexed(&self, interest: &Interest) -> bool {
+        match interest {
+            Interest::RemoteIp(_)
+            | Interest::RemoteSocket(_)
+            | Interest::LocalSocket(_)
+            | Interest::Pid(_)
+            | Interest::All => true,
+            _ => self
+                .dimensions
+                .iter()
+                .any(|dimension| dimension.contains(interest)),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.tiers[0].window
+    }
+
+    pub fn max_capacity(&self) -> usize {
+        self.tiers[0].capacity
+    }
+
+    /// Update the store from the messages.
+    ///
+    /// Each message is accounted in the segment of its timestamp, which must be from the same
+    /// monolithic clock. Messages older than the history are dropped.
+    ///
+    /// The messages are first aggregated by flow in a buffer local to the calling thread, so
+    /// the shared segment is only updated once per flow of the batch.
     pub fn batch_update<'a>(
         &self,
-        ts: Timestamp,
-        messages: impl IntoIterator<Item = &'a SockMsgEvent>,
+        messages: impl IntoIterator<Item = (Timestamp, &'a SockMsgEvent)>,
     ) {
-        let time_segment = self.write_segment(ts);
-        time_segment.segment.batch_update(messages);
+        thread_local! {
+            static FLOWS: RefCell<Flows> = RefCell::default();
+        }
+
+        FLOWS.with(|flows| {
+            let flows = &mut *flows.borrow_mut();
+            let mut current = None;
+
+            for (ts, msg) in messages {
+                let ts = ts.trunc(self.window());
+
+                // Messages of a batch are mostly in the same segment.
+                if current != Some(ts) {
+                    if let Some(current) = current {
+                        self.merge_flows(current, flows);
+                    }
+                    current = Some(ts);
+                }
+
+                aggregate(flows, msg);
+            }
+
+            if let Some(current) = current {
+                self.merge_flows(current, flows);
+            }
+        });
+    }
+
+    /// Drains the `flows` into the segment of `ts`, or drops them if `ts` is older than
+    /// the history.
+    fn merge_flows(&self, ts: Timestamp, flows: &mut Flows) {
+        match self.write_segment(ts) {
+            Some(time_segment) => time_segment.segment.merge_flows(
+                flows,
+                &self.dimensions,
+                &self.limits,
+                &self.sockets,
+            ),
+            None => flows.clear(),
+        }
     }
 
-    /// Returns a `TimeSegmentsView` that provides a read-only view of the time segments in the store.
+    /// Update the store from the `AF_UNIX` messages.
+    ///
+    /// Each message is accounted in the segment of its timestamp, which must be from the same
+    /// monolithic clock. Messages older than the history are dropped.
+    pub fn batch_update_unix<'a>(
+        &self,
+        messages: impl IntoIterator<Item = (Timestamp, &'a UnixMsgEvent)>,
+    ) {
+        self.route(messages, |segment, msg| {
+            segment.batch_update_unix(std::iter::once(msg), &self.limits)
+        });
+    }
+
+    /// Passes each message to `f` with the segment of its timestamp.
+    fn route<'a, T: 'a>(
+        &self,
+        messages: impl IntoIterator<Item = (Timestamp, &'a T)>,
+        f: impl Fn(&Segment, &'a T),
+    ) {
+        let mut current: Option<WriteTimeSegment<'_>> = None;
+
+        for (ts, msg) in messages {
+            let ts = ts.trunc(self.window());
+
+            // Messages of a batch are mostly in the same segment, keep it until the time changes.
+            if !matches!(&current, Some(time_segment) if time_segment.ts == ts) {
+                // Release the lock before creating segments.
+                drop(current.take());
+                current = self.write_segment(ts);
+            }
+
+            if let Some(time_segment) = &current {
+                f(&time_segment.segment, msg);
+            }
+        }
+    }
+
+    /// Records the events lost by the perf buffer of the CPU `cpu_id`.
+    ///
+    /// The traffic of the segment at `ts` is under-reported when it has lost events.
+    pub fn record_lost_events(&self, ts: Timestamp, cpu_id: u32, count: u64) {
+        if let Some(time_segment) = self.write_segment(ts) {
+            time_segment.segment.record_lost_events(cpu_id, count);
+        }
+    }
+
+    /// Returns the number of events lost over the history in memory.
+    pub fn lost_events(&self) -> u64 {
+        self.sum_segments(Segment::lost_events)
+    }
+
+    /// Returns the number of messages accounted in the overflow buckets over the history
+    /// in memory, see [with_limits](Self::with_limits).
+    pub fn overflowed(&self) -> u64 {
+        self.sum_segments(Segment::overflowed)
+    }
+
+    fn sum_segments(&self, f: impl Fn(&Segment) -> u64) -> u64 {
+        self.tiers
+            .iter()
+            .map(|tier| {
+                tier.segments
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|time_segment| f(&time_segment.segment))
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    /// Returns a `TimeSegmentsView` that provides a read-only view of the time segments
+    /// of the finest tier of the store.
     ///
     /// The `TimeSegmentsView` holds a read lock over the storage in the store,
     /// which prevents new time segments from being added while the reader is active.
     pub fn segments_view(&self) -> TimeSegmentsView<'_> {
-        TimeSegmentsView(self.segments.read().unwrap())
+        TimeSegmentsView::new(vec![self.tiers[0].segments.read().unwrap()])
     }
 
-    pub fn oldest_timestamp(&self, ts: Timestamp) -> Timestamp {
-        self.write_segment(ts).ts
+    /// Returns a `TimeSegmentsView` over the finest tiers that cover the last `duration`,
+    /// including the segments on disk if the history in memory is not enough.
+    ///
+    /// See [segments_view](Self::segments_view).
+    pub fn segments_view_covering(&self, duration: Duration) -> TimeSegmentsView<'_> {
+        let mut covered = Duration::ZERO;
+        let covering = self.tiers.iter().position(|tier| {
+            covered = covered.saturating_add(tier.retention());
+            covered >= duration
+        });
+
+        // The disk is read before locking the tiers, the updates never wait for it.
+        let archive = match (&self.disk, covering) {
+            (Some(disk), None) => {
+                let since = SystemTime::now()
+                    .checked_sub(duration)
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+
+                disk.partitions_since(since).unwrap_or_else(|err| {
+                    warn!("failed to read the history from disk: {err}");
+                    Vec::new()
+                })
+            }
+            _ => Vec::new(),
+        };
+
+        // Locks are always acquired from the finest to the coarsest tier.
+        let tiers = covering.map_or(self.tiers.len(), |covering| covering + 1);
+        let mut view = TimeSegmentsView::new(
+            self.tiers[..tiers]
+                .iter()
+                .map(|tier| tier.segments.read().unwrap())
+                .collect(),
+        );
+        view.archive = archive;
+        view
     }
 
-    fn write_segment(&self, ts: Timestamp) -> WriteTimeSegment<'_> {
-        let ts = ts.trunc(self.window);
+    /// Persists the segments not yet on disk and waits for them to be written, to be called
+    /// before exiting.
+    pub fn flush(&self) {
+        let disk = match &self.disk {
+            Some(disk) => disk,
+            None => return,
+        };
 
-        {
-            // fast path: the current segment is not outdated.
+        // From the oldest to the newest, the complete segments of the coarsest tier
+        // are already on disk.
+        for (level, tier) in self.tiers.iter().enumerate().rev() {
+            let guard = tier.segments.read().unwrap();
 
-            let read_guard = self.segments.read().unwrap();
-            let dequeue = &*read_guard;
+            if level + 1 == self.tiers.len() {
+                guard
+                    .back()
+                    .into_iter()
+                    .for_each(|time_segment| self.persist(time_segment));
+            } else {
+                guard
+                    .iter()
+                    .for_each(|time_segment| self.persist(time_segment));
+            }
+        }
 
-            if let Some(segment) = dequeue.back() {
-                if segment.ts == ts {
-                    return WriteTimeSegment(read_guard);
-                }
+        if let Err(err) = disk.sync() {
+            warn!("failed to persist the history: {err}");
+        }
+    }
+
+    fn persist(&self, time_segment: &TimeSegment) {
+        if let Some(disk) = &self.disk {
+            if time_segment.segment.is_empty() {
+                return;
             }
-        };
 
-        let mut write_guard = self.segments.write().unwrap();
-        let dequeue = &mut *write_guard;
+            if let Err(err) = disk.append(time_segment) {
+                warn!("failed to persist the history: {err}");
+            }
+        }
+    }
 
-        loop {
-            // slow path: create missing segments.
+    pub fn oldest_timestamp(&self, ts: Timestamp) -> Timestamp {
+        self.write_segment(ts)
+            .map_or_else(|| ts.trunc(self.window()), |time_segment| time_segment.ts)
+    }
 
-            let segment_ts = if let Some(segment) = dequeue.back() {
-                // Happends if an other thread creates the new segments
-                // after we released the read lock and before we grab the write
-                // lock.
+    /// Returns the segment of `ts` in the finest tier, creating the missing segments up to `ts`
+    /// if needed, or `None` if `ts` is older than this tier.
+    fn write_segment(&self, ts: Timestamp) -> Option<WriteTimeSegment<'_>> {
+        let tier = &self.tiers[0];
+        let ts = ts.trunc(tier.window);
 
-                if segment.ts >= ts {
-                    // Ignoring the race condition on purpose because it's harmless.
-                    drop(write_guard);
-                    return WriteTimeSegment(self.segments.read().unwrap());
+        loop {
+            {
+                // fast path: the segment exists.
+
+                let read_guard = tier.segments.read().unwrap();
+                let dequeue = &*read_guard;
+
+                if let Some(newest) = dequeue.back() {
+                    if newest.ts >= ts {
+                        // Segments are contiguous.
+                        let offset = (newest.ts.0 - ts.0).as_nanos() / tier.window.as_nanos();
+                        let index = usize::try_from(offset)
+                            .ok()
+                            .and_then(|offset| (dequeue.len() - 1).checked_sub(offset))?;
+
+                        return Some(WriteTimeSegment {
+                            guard: read_guard,
+                            index,
+                        });
+                    }
                 }
-                segment.ts + self.window
-            } else {
-                ts
             };
 
-            if dequeue.len() >= self.capacity {
-                dequeue.pop_front();
+            let mut write_guard = tier.segments.write().unwrap();
+
+            // slow path: create missing segments.
+            // An other thread may have created them after we released the read lock
+            // and before we grabbed the write lock, in which case there is nothing to do.
+            self.extend_tier(0, &mut write_guard, ts);
+        }
+    }
+
+    /// Creates the missing segments of the tier `level` up to `ts`, rolling up the expired ones.
+    fn extend_tier(&self, level: usize, dequeue: &mut VecDeque<TimeSegment>, ts: Timestamp) {
+        // The newest segment of the coarsest tier is complete once a newer one is created.
+        if level + 1 == self.tiers.len() {
+            if let Some(newest) = dequeue.back().filter(|newest| newest.ts < ts) {
+                self.persist(newest);
+            }
+        }
+
+        self.tiers[level].extend_to(dequeue, ts, |expired| self.roll_up(level + 1, expired));
+    }
+
+    /// Merges the segment expired from the tier `level - 1` into the tier `level`.
+    ///
+    /// Called with the lock of the finer tier held.
+    fn roll_up(&self, level: usize, time_segment: TimeSegment) {
+        // The sockets of the expired segment that are not already in the coarser one.
+        let mut dropped_sockets = time_segment.segment.socks.len();
+
+        if let Some(tier) = self.tiers.get(level) {
+            if !time_segment.segment.is_empty() {
+                let ts = time_segment.ts.trunc(tier.window);
+
+                let mut write_guard = tier.segments.write().unwrap();
+                self.extend_tier(level, &mut write_guard, ts);
+
+                // Segments expire in chronological order, `ts` is the newest segment of the tier.
+                if let Some(newest) = write_guard.back() {
+                    debug_assert_eq!(newest.ts, ts);
+                    let sockets = newest.segment.socks.len();
+                    newest.segment.merge(time_segment.segment);
+                    dropped_sockets -= newest.segment.socks.len() - sockets;
+                }
             }
-            dequeue.push_back(TimeSegment {
-                ts: segment_ts,
-                segment: Segment::default(),
-            });
         }
+
+        self.sockets.fetch_sub(dropped_sockets, Ordering::Relaxed);
     }
 }
 
@@ -450,6 +1393,32 @@ mod tests {
 
     use super::*;
 
+    /// A message of `ret` bytes sent by the process `pid` from the port `local_port`, to the
+    /// IPv4 address `remote`, the other fields are overridden with the struct update syntax.
+    pub(super) fn msg(
+        pid: u32,
+        local_port: u16,
+        remote: u32,
+        remote_port: u16,
+        ret: i32,
+    ) -> SockMsgEvent {
+        SockMsgEvent {
+            pid,
+            uid: 1000,
+            gid: 1000,
+            comm: [0; TASK_COMM_LEN],
+            channel: Channel::Tx,
+            sock_type: SockType::Stream,
+            local_addr: ptraf_common::IpAddr::v4(33),
+            local_port: local_port.to_be(),
+            remote_addr: ptraf_common::IpAddr::v4(remote),
+            remote_port: remote_port.to_be(),
+            ret,
+            state: TcpState::Established,
+            ts: 0,
+        }
+    }
+
     #[test]
     fn store_batch_update_simple() {
         let window = Duration::from_millis(100);
@@ -457,53 +1426,20 @@ mod tests {
         let clock = ClockNano::default();
         let ts = clock.now();
 
-        let messages = vec![
+        let messages = [
+            msg(1, 31, 32, 80, 10),
             SockMsgEvent {
-                pid: 1,
-                channel: Channel::Tx,
-                sock_type: SockType::Stream,
-                local_addr: ptraf_common::IpAddr::v4(33),
-                local_port: 31u16.to_be(),
-                remote_addr: ptraf_common::IpAddr::v4(32),
-                remote_port: 80u16.to_be(),
-                ret: 10,
-            },
-            SockMsgEvent {
-                pid: 1,
                 channel: Channel::Rx,
-                sock_type: SockType::Stream,
-                local_addr: ptraf_common::IpAddr::v4(33),
-                local_port: 31u16.to_be(),
-                remote_addr: ptraf_common::IpAddr::v4(32),
-                remote_port: 80u16.to_be(),
-                ret: 11,
-            },
-            SockMsgEvent {
-                pid: 2,
-                channel: Channel::Tx,
-                sock_type: SockType::Stream,
-                local_addr: ptraf_common::IpAddr::v4(33),
-                local_port: 32u16.to_be(),
-                remote_addr: ptraf_common::IpAddr::v4(35),
-                remote_port: 443u16.to_be(),
-                ret: 12,
-            },
-            SockMsgEvent {
-                pid: 3,
-                channel: Channel::Tx,
-                sock_type: SockType::Stream,
-                local_addr: ptraf_common::IpAddr::v4(33),
-                local_port: 33u16.to_be(),
-                remote_addr: ptraf_common::IpAddr::v4(32),
-                remote_port: 443u16.to_be(),
-                ret: 13,
+                ..msg(1, 31, 32, 80, 11)
             },
+            msg(2, 32, 35, 443, 12),
+            msg(3, 33, 32, 443, 13),
         ];
 
-        store.batch_update(ts, &messages);
-        store.batch_update(ts, &messages);
-        store.batch_update(ts, &messages);
-        store.batch_update(ts, &messages);
+        store.batch_update(messages.iter().map(|msg| (ts, msg)));
+        store.batch_update(messages.iter().map(|msg| (ts, msg)));
+        store.batch_update(messages.iter().map(|msg| (ts, msg)));
+        store.batch_update(messages.iter().map(|msg| (ts, msg)));
 
         let view = store.segments_view();
         assert_eq!(1, view.len());
@@ -539,26 +1475,453 @@ mod tests {
     }
 
     #[test]
-    fn store_create_segments() {
-        let messages = vec![SockMsgEvent {
+    fn store_size_distribution() {
+        let store = Store::new(Duration::from_millis(100), 16);
+        let ts = ClockNano::default().now();
+
+        // The messages of a flow are aggregated in a batch, each one is still a sample.
+        let mut messages = vec![msg(1, 31, 32, 80, 10); 9];
+        messages.push(msg(1, 31, 32, 80, 10_000));
+        store.batch_update(messages.iter().map(|msg| (ts, msg)));
+
+        let view = store.segments_view();
+        let sizes = view
+            .oldest()
+            .and_then(|time_segment| time_segment.segment.stat_by_interest(&Interest::Pid(1)))
+            .unwrap()
+            .sizes;
+        assert_eq!(9, sizes.buckets()[SizeHistogram::bucket(10)]);
+        assert_eq!(1, sizes.buckets()[SizeHistogram::bucket(10_000)]);
+        assert_eq!(10, sizes.count());
+    }
+
+    #[test]
+    fn store_batch_update_unix() {
+        let mut path = [0u8; ptraf_common::UNIX_PATH_MAX];
+        path[..16].copy_from_slice(b"/run/docker.sock");
+        let mut abstract_path = [0u8; ptraf_common::UNIX_PATH_MAX];
+        abstract_path[1..5].copy_from_slice(b"dbus");
+
+        let msg = |pid, path, path_len, channel, ret| UnixMsgEvent {
+            sock_type: SockType::Stream,
+            path,
+            path_len,
+            ret,
+            pid,
+            peer_pid: 42,
+            channel,
+            ts: 0,
+        };
+
+        let messages = [
+            msg(1, path, 16, Channel::Tx, 10),
+            msg(1, path, 16, Channel::Rx, 11),
+            msg(2, path, 16, Channel::Tx, 12),
+            msg(2, abstract_path, 5, Channel::Tx, 13),
+            msg(2, abstract_path, 5, Channel::Tx, -1),
+        ];
+
+        let store = Store::new(Duration::from_millis(100), 16);
+        store.batch_update_unix(messages.iter().map(|msg| (Duration::ZERO.into(), msg)));
+
+        let view = store.segments_view();
+        let mut socks = Vec::new();
+        view.oldest()
+            .unwrap()
+            .segment
+            .for_each_unix_socket(|sock, stat| {
+                socks.push((sock.pid, sock.path.to_string(), stat.rx, stat.tx))
+            });
+        socks.sort();
+
+        assert_eq!(
+            socks,
+            vec![
+                (1, "/run/docker.sock".to_string(), 11, 10),
+                (2, "/run/docker.sock".to_string(), 0, 12),
+                (2, "@dbus".to_string(), 0, 13),
+            ]
+        );
+
+        // Beyond the limits, the traffic is accounted by process.
+        let store = Store::new(Duration::from_millis(100), 16).with_limits(Limits {
+            sockets_per_segment: 2,
+            sockets: usize::MAX,
+        });
+        store.batch_update_unix(messages.iter().map(|msg| (Duration::ZERO.into(), msg)));
+
+        let view = store.segments_view();
+        let segment = &view.oldest().unwrap().segment;
+        let mut socks = 0;
+        segment.for_each_unix_socket(|_, _| socks += 1);
+        let mut overflow = Vec::new();
+        segment.for_each_unix_overflow(|pid, stat| overflow.push((pid, stat.tx)));
+        assert_eq!(2, socks);
+        assert_eq!(vec![(2, 13)], overflow);
+        assert_eq!(1, store.overflowed());
+    }
+
+    #[test]
+    fn store_batch_update_routes_by_timestamp() {
+        let msg = msg(1, 31, 32, 80, 10);
+
+        let window = Duration::from_millis(100);
+        let store = Store::new(window, 4);
+
+        store.batch_update([(Duration::from_millis(10).into(), &msg)]);
+        store.batch_update([(Duration::from_millis(310).into(), &msg)]);
+
+        // A late batch spans past segments, the newest one and a new one.
+        store.batch_update(
+            [50, 150, 160, 320, 410].map(|ms| (Duration::from_millis(ms).into(), &msg)),
+        );
+
+        // The first segment expired, messages older than the history are dropped.
+        store.batch_update([(Duration::from_millis(20).into(), &msg)]);
+
+        let view = store.segments_view();
+
+        let times: Vec<_> = view
+            .iter()
+            .map(|TimeSegment { ts, segment }| (*ts, segment.total_packet_count()))
+            .collect();
+
+        assert_eq!(
+            times,
+            vec![
+                (Duration::from_millis(100).into(), 2),
+                (Duration::from_millis(200).into(), 0),
+                (Duration::from_millis(300).into(), 2),
+                (Duration::from_millis(400).into(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn store_roll_up_expired_segments() {
+        let msg = |ret| msg(1, 31, 32, 80, ret);
+
+        let rollups = [
+            Rollup {
+                resolution: Duration::from_millis(200),
+                retention: Duration::from_millis(400),
+            },
+            Rollup {
+                resolution: Duration::from_millis(800),
+                retention: Duration::from_secs(10),
+            },
+        ];
+        let store = Store::with_rollups(Duration::from_millis(100), 2, &rollups).unwrap();
+
+        for ms in (0..1400).step_by(100) {
+            store.batch_update([(Duration::from_millis(ms).into(), &msg(ms as i32))]);
+        }
+
+        let view = store.segments_view_covering(Duration::from_secs(60));
+
+        let times: Vec<_> = view
+            .iter()
+            .map(|TimeSegment { ts, segment }| (ts.0.as_millis(), segment.total(None)))
+            .collect();
+
+        assert_eq!(
+            times,
+            vec![
+                (0, 100 + 200 + 300 + 400 + 500 + 600 + 700),
+                (800, 800 + 900),
+                (1000, 1000 + 1100),
+                (1200, 1200),
+                (1300, 1300),
+            ]
+        );
+
+        // The view only locks the tiers required to cover the duration.
+        assert_eq!(
+            2,
+            store
+                .segments_view_covering(Duration::from_millis(200))
+                .len()
+        );
+        assert_eq!(
+            4,
+            store
+                .segments_view_covering(Duration::from_millis(300))
+                .len()
+        );
+        assert_eq!(2, store.segments_view().len());
+
+        assert!(Store::with_rollups(
+            Duration::from_millis(100),
+            2,
+            &[Rollup {
+                resolution: Duration::from_millis(150),
+                retention: Duration::from_secs(1),
+            }],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn store_read_history_from_disk() {
+        let dir = std::env::temp_dir().join(format!("ptraf-store-history-{}", std::process::id()));
+        let clock = ClockNano::with_past(Duration::from_secs(3600));
+        let window = Duration::from_millis(100);
+
+        let open = || {
+            let disk = DiskStore::open(&dir, Duration::from_secs(3600), clock.clone()).unwrap();
+            Store::new(window, 2).with_disk(disk)
+        };
+
+        let msg = msg(1, 31, 32, 80, 10);
+
+        let ts = clock.now().trunc(window);
+
+        {
+            let store = open();
+            for i in 0..4 {
+                store.batch_update([(ts + window * i, &msg)]);
+            }
+            // The complete segments are already on disk, flush the newest one.
+            store.flush();
+        }
+
+        let store = open();
+        store.batch_update([(ts + window * 10, &msg)]);
+
+        let times: Vec<_> = store
+            .segments_view_covering(Duration::from_secs(60))
+            .iter()
+            .map(|TimeSegment { ts, segment }| (*ts, segment.total_packet_count()))
+            .collect();
+
+        assert_eq!(
+            times,
+            vec![
+                (ts, 1),
+                (ts + window, 1),
+                (ts + window * 2, 1),
+                (ts + window * 3, 1),
+                (ts + window * 10, 1),
+            ]
+        );
+
+        // The history in memory is enough.
+        assert_eq!(1, store.segments_view_covering(window).len());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn store_record_lost_events() {
+        let store = Store::new(Duration::from_millis(100), 2);
+
+        store.record_lost_events(Duration::from_millis(10).into(), 0, 3);
+        store.record_lost_events(Duration::from_millis(20).into(), 1, 4);
+        store.record_lost_events(Duration::from_millis(30).into(), 0, 5);
+        assert_eq!(12, store.lost_events());
+
+        store.record_lost_events(Duration::from_millis(110).into(), 1, 1);
+        assert_eq!(13, store.lost_events());
+
+        {
+            let view = store.segments_view();
+            let lost: Vec<_> = view.iter().map(|ts| ts.segment.lost_events()).collect();
+            assert_eq!(lost, vec![12, 1]);
+        }
+
+        // The lost events expire with their segment.
+        store.record_lost_events(Duration::from_millis(210).into(), 0, 0);
+        assert_eq!(1, store.lost_events());
+    }
+
+    #[test]
+    fn store_socket_state() {
+        let store = Store::new(Duration::from_millis(100), 2);
+
+        let msg = |pid: u32, state: TcpState| SockMsgEvent {
+            state,
+            ..msg(pid, 4242, 32, 80, 10)
+        };
+        let states = || {
+            let mut states = Vec::new();
+            for time_segment in store.segments_view().iter() {
+                time_segment
+                    .segment
+                    .for_each_socket(|socket| states.push((socket.pid, socket.state)));
+            }
+            states
+        };
+
+        // The last state of a batch.
+        let ts = Timestamp::from(Duration::from_millis(0));
+        store.batch_update([
+            (ts, &msg(1, TcpState::Established)),
+            (ts, &msg(1, TcpState::FinWait1)),
+        ]);
+        assert_eq!(states(), vec![(1, TcpState::FinWait1)]);
+
+        // State changes may be reported in the context of another process.
+        store.batch_update([(ts, &msg(2, TcpState::TimeWait))]);
+        assert_eq!(states(), vec![(1, TcpState::TimeWait)]);
+
+        // Each segment has the state of its last message.
+        let ts = Timestamp::from(Duration::from_millis(100));
+        store.batch_update([(ts, &msg(1, TcpState::Close))]);
+        assert_eq!(
+            states(),
+            vec![(1, TcpState::TimeWait), (1, TcpState::Close)]
+        );
+    }
+
+    #[test]
+    fn store_limits() {
+        let store = Store::new(Duration::from_millis(100), 2).with_limits(Limits {
+            sockets_per_segment: 2,
+            sockets: 3,
+        });
+
+        let msg = |local_port: u16| msg(1, local_port, 32, 80, 10);
+        let update = |ms: u64, ports: &[u16]| {
+            let messages: Vec<_> = ports.iter().map(|port| msg(*port)).collect();
+            let ts = Timestamp::from(Duration::from_millis(ms));
+            store.batch_update(messages.iter().map(|msg| (ts, msg)));
+        };
+
+        // The per-segment limit.
+        update(0, &[1, 2, 3, 1]);
+        // The global limit.
+        update(100, &[1, 2]);
+
+        assert_eq!(3, store.sockets.load(Ordering::Relaxed));
+        assert_eq!(2, store.overflowed());
+
+        {
+            let view = store.segments_view();
+            let segments: Vec<_> = view
+                .iter()
+                .map(|time_segment| {
+                    let segment = &time_segment.segment;
+
+                    let mut socks = 0;
+                    segment.for_each_socket(|_| socks += 1);
+                    let mut overflow = Vec::new();
+                    segment.for_each_overflow(|pid, stat| overflow.push((pid, stat.tx)));
+
+                    let total = segment
+                        .stat_by_interest(&Interest::Pid(1))
+                        .unwrap_or_default()
+                        .tx;
+                    (socks, overflow, total)
+                })
+                .collect();
+            assert_eq!(
+                segments,
+                vec![(2, vec![(1, 10)], 40), (1, vec![(1, 10)], 20)]
+            );
+        }
+
+        // The sockets of the expired segment are released.
+        update(200, &[4, 5]);
+        assert_eq!(3, store.sockets.load(Ordering::Relaxed));
+        assert_eq!(1, store.overflowed());
+    }
+
+    #[test]
+    fn socket_match_interest() {
+        let socket = Socket {
             pid: 1,
-            channel: Channel::Tx,
+            uid: 1000,
+            gid: 1000,
+            comm: Comm::default(),
+            local: "10.0.0.1:4000".parse().unwrap(),
+            remote: "10.0.0.2:443".parse().unwrap(),
             sock_type: SockType::Stream,
-            local_addr: ptraf_common::IpAddr::v4(33),
-            local_port: 31,
-            remote_addr: ptraf_common::IpAddr::v4(32),
-            remote_port: 80,
-            ret: 10,
-        }];
+            state: TcpState::Established,
+        };
+
+        assert!(socket.match_interest(Interest::LocalSocket(socket.local)));
+        assert!(!socket.match_interest(Interest::LocalSocket(socket.remote)));
+        assert!(socket.match_interest(Interest::RemoteSocket(socket.remote)));
+        assert!(!socket.match_interest(Interest::RemoteSocket(socket.local)));
+    }
+
+    #[test]
+    fn store_dimensions() {
+        let msg = |uid: u32, comm: &str, remote_port: u16| SockMsgEvent {
+            uid,
+            gid: uid,
+            comm: Comm::from(comm).0,
+            channel: Channel::Rx,
+            ..msg(uid, uid as u16, 32, remote_port, 10)
+        };
+        let messages = [
+            msg(1000, "psql", 5432),
+            msg(1000, "psql", 5432),
+            msg(1001, "curl", 443),
+        ];
+
+        for dimensions in [&Dimension::ALL[..], &[Dimension::RemotePort]] {
+            let store = Store::new(Duration::from_millis(100), 2).with_dimensions(dimensions);
+            store.batch_update(messages.iter().map(|msg| (Timestamp::default(), msg)));
+
+            let lookup = |interest| {
+                let view = store.segments_view();
+                view.newest()
+                    .and_then(|time_segment| time_segment.segment.stat_by_interest(&interest))
+                    .map(|stat| stat.rx)
+            };
+            let query = |interest| {
+                store
+                    .query(
+                        &Query::new(Timestamp::default()..Duration::MAX.into()).interest(interest),
+                    )
+                    .total
+                    .rx
+            };
+
+            assert_eq!(Some(20), lookup(Interest::RemotePort(5432)));
+            for (interest, expected) in [
+                (Interest::RemotePort(443), 10),
+                (Interest::LocalPort(1000), 20),
+                (Interest::ProcessName("psql".into()), 20),
+                (Interest::Uid(1001), 10),
+                (Interest::SockType(SockType::Stream), 30),
+                (Interest::SockType(SockType::Dgram), 0),
+            ] {
+                let indexed = store.is_indexed(&interest);
+                assert_eq!(
+                    indexed,
+                    dimensions.len() > 1 || interest == Interest::RemotePort(443)
+                );
+                assert_eq!(
+                    lookup(interest),
+                    Some(expected).filter(|expected| indexed && *expected > 0)
+                );
+                // Computed from the sockets if the interest is not indexed.
+                assert_eq!(expected, query(interest));
+            }
+        }
+    }
+
+    #[test]
+    fn store_create_segments() {
+        let messages = [msg(1, 31, 32, 80, 10)];
 
         let window = Duration::from_millis(100);
         let store = Store::new(window, 16);
+        let update = |ms| {
+            store.batch_update(
+                messages
+                    .iter()
+                    .map(|msg| (Duration::from_millis(ms).into(), msg)),
+            )
+        };
 
-        store.batch_update(Duration::from_millis(10).into(), &messages); // 0
-        store.batch_update(Duration::from_millis(20).into(), &messages); // 0
-        store.batch_update(Duration::from_millis(100).into(), &messages); // 1
-        store.batch_update(Duration::from_millis(101).into(), &messages); // 1
-        store.batch_update(Duration::from_millis(401).into(), &messages); // 4
+        update(10); // 0
+        update(20); // 0
+        update(100); // 1
+        update(101); // 1
+        update(401); // 4
 
         let view = store.segments_view();
 
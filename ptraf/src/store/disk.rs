@@ -0,0 +1,807 @@
+//! On-disk history.
+//!
+//! Segments are appended to files partitioned by hour of wall time, named after the unix time
+//! of the start of their partition (`<secs>.seg`). Each record starts with a marker and its
+//! length, the records truncated by a crash or corrupt are skipped on read. Partitions older
+//! than the retention are deleted.
+//!
+//! The records are written by a dedicated thread, the updates of the store never wait for
+//! the disk.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use ptraf_common::SockType;
+
+use crate::{clock::ClockNano, privileges};
+
+use super::{
+    histogram::SIZE_BUCKETS, Comm, Interest, Metrics, Segment, SizeHistogram, Socket, TimeSegment,
+    UnixPath, UnixSocket,
+};
+
+const PARTITION: Duration = Duration::from_secs(3600);
+const EXTENSION: &str = "seg";
+const FORMAT_VERSION: u8 = 1;
+
+/// Start of a record, searched for after a truncated or corrupt record.
+const RECORD_MARKER: [u8; 4] = *b"PTRF";
+
+/// Segments of a partition, in chronological order.
+pub(super) type Partition = Arc<Vec<Arc<TimeSegment>>>;
+
+/// Append-only store of the segments in a directory.
+#[derive(Debug)]
+pub struct DiskStore {
+    dir: PathBuf,
+    retention: Duration,
+    clock: ClockNano,
+    /// Commands of the writer thread, started on the first append so that it runs with the
+    /// credentials of the threads updating the store.
+    writer: Mutex<Option<mpsc::Sender<Command>>>,
+    cache: Arc<Cache>,
+}
+
+/// Partitions already read, the writer appends its records to the cached partitions.
+#[derive(Debug, Default)]
+struct Cache {
+    partitions: RwLock<BTreeMap<u64, Partition>>,
+    /// Number of records written, a partition read while it changes isn't cached.
+    writes: AtomicU64,
+}
+
+#[derive(Debug)]
+enum Command {
+    /// Appends the encoded segment to the partition.
+    Append { partition: u64, record: Vec<u8> },
+    /// Replies once the previous records are written.
+    Sync(mpsc::Sender<()>),
+}
+
+impl DiskStore {
+    /// Opens the store in `dir`, creating the directory if needed, and deletes the partitions
+    /// older than `retention`.
+    ///
+    /// The segments read from the disk are mapped on `clock`, the segments older than the start
+    /// of the clock are ignored (see [ClockNano::with_past]).
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        retention: Duration,
+        clock: ClockNano,
+    ) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        enforce_retention(&dir, retention)?;
+
+        Ok(Self {
+            dir,
+            retention,
+            clock,
+            writer: Mutex::new(None),
+            cache: Arc::default(),
+        })
+    }
+
+    /// Encodes the segment and passes it to the writer thread, to be appended to its partition.
+    pub(super) fn append(&self, time_segment: &TimeSegment) -> io::Result<()> {
+        let wall_time = self.clock.wall_time(time_segment.ts);
+        let partition = partition_of(wall_time);
+
+        let mut record = Vec::with_capacity(4096);
+        encode_segment(&mut record, wall_time, &time_segment.segment);
+
+        self.send(Command::Append { partition, record })
+    }
+
+    /// Waits until the segments appended are written.
+    pub(super) fn sync(&self) -> io::Result<()> {
+        let (done, synced) = mpsc::channel();
+        self.send(Command::Sync(done))?;
+        synced.recv().map_err(|_| writer_stopped())
+    }
+
+    fn send(&self, command: Command) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+
+        let commands = match &mut *writer {
+            Some(commands) => commands,
+            None => {
+                let (commands, received) = mpsc::channel();
+                let worker = Writer {
+                    dir: self.dir.clone(),
+                    retention: self.retention,
+                    clock: self.clock.clone(),
+                    file: None,
+                    cache: Arc::clone(&self.cache),
+                };
+                thread::Builder::new()
+                    .name("ptraf-history".to_string())
+                    .spawn(move || {
+                        privileges::drop_current_thread();
+                        worker.run(received)
+                    })?;
+                writer.insert(commands)
+            }
+        };
+
+        commands.send(command).map_err(|_| writer_stopped())
+    }
+
+    /// Returns the partitions of the segments since `since`, from the oldest to the newest.
+    pub(super) fn partitions_since(&self, since: SystemTime) -> io::Result<Vec<Partition>> {
+        let retained = partition_of(retention_start(self.retention));
+        let oldest = partition_of(since).max(retained);
+        let current = partition_of(SystemTime::now());
+
+        let mut partitions = Vec::new();
+
+        for partition in oldest..=current {
+            if let Some(cached) = self.cache.partitions.read().unwrap().get(&partition) {
+                partitions.push(Arc::clone(cached));
+                continue;
+            }
+
+            let writes = self.cache.writes.load(Ordering::Acquire);
+            let segments = match self.read_partition(partition) {
+                Ok(segments) => Arc::new(segments.into_iter().map(Arc::new).collect()),
+                // The missing partitions are cached too, until the writer creates them.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Partition::default(),
+                Err(err) => return Err(err),
+            };
+
+            {
+                let mut cache = self.cache.partitions.write().unwrap();
+                if self.cache.writes.load(Ordering::Acquire) == writes {
+                    cache.retain(|partition, _| *partition >= retained);
+                    cache.insert(partition, Arc::clone(&segments));
+                }
+            }
+
+            partitions.push(segments);
+        }
+
+        Ok(partitions)
+    }
+
+    fn read_partition(&self, partition: u64) -> io::Result<Vec<TimeSegment>> {
+        let content = fs::read(self.partition_path(partition))?;
+        let mut buf = content.as_slice();
+
+        let mut segments = Vec::new();
+
+        while let Some(start) = buf
+            .windows(RECORD_MARKER.len())
+            .position(|marker| marker == RECORD_MARKER)
+        {
+            buf = &buf[start + RECORD_MARKER.len()..];
+
+            let mut record = buf;
+            let (wall_time, segment) = match decode_segment(&mut record) {
+                Ok(decoded) => decoded,
+                // The length of a truncated record may cover the records written after it,
+                // the next record is searched for from its start.
+                Err(_) => continue,
+            };
+            buf = record;
+
+            if let Some(ts) = self.clock.timestamp(wall_time) {
+                segments.push(TimeSegment { ts, segment });
+            }
+        }
+
+        segments.sort_by_key(|time_segment| time_segment.ts);
+
+        Ok(segments)
+    }
+
+    fn partition_path(&self, partition: u64) -> PathBuf {
+        partition_path(&self.dir, partition)
+    }
+}
+
+/// The thread appending the records to the partitions.
+struct Writer {
+    dir: PathBuf,
+    retention: Duration,
+    clock: ClockNano,
+    /// Partition being written and its file.
+    file: Option<(u64, File)>,
+    cache: Arc<Cache>,
+}
+
+impl Writer {
+    fn run(mut self, commands: mpsc::Receiver<Command>) {
+        for command in commands {
+            match command {
+                Command::Append { partition, record } => {
+                    if let Err(err) = self.append(partition, &record) {
+                        warn!("failed to persist the history: {err}");
+                    }
+                }
+                Command::Sync(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn append(&mut self, partition: u64, record: &[u8]) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some((current, file)) if *current == partition => file,
+            file => {
+                // A new partition is started once in a while, a good time to delete the old ones.
+                enforce_retention(&self.dir, self.retention)?;
+
+                let opened = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(partition_path(&self.dir, partition))?;
+                &mut file.insert((partition, opened)).1
+            }
+        };
+
+        // The record is written under the lock: a reader caching the partition either sees
+        // the new count, or has read the file before the record and gets it appended.
+        let mut partitions = self.cache.partitions.write().unwrap();
+        file.write_all(record)?;
+        self.cache.writes.fetch_add(1, Ordering::AcqRel);
+
+        if let Some(cached) = partitions.get_mut(&partition) {
+            match self.decode(record) {
+                Some(time_segment) => {
+                    let segments = Arc::make_mut(cached);
+                    let at = segments.partition_point(|cached| cached.ts <= time_segment.ts);
+                    segments.insert(at, Arc::new(time_segment));
+                }
+                // Older than the clock, read again from the file otherwise.
+                None => {
+                    partitions.remove(&partition);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the record just written, as read from its partition.
+    fn decode(&self, record: &[u8]) -> Option<TimeSegment> {
+        let (wall_time, segment) = decode_segment(&mut &record[RECORD_MARKER.len()..]).ok()?;
+        let ts = self.clock.timestamp(wall_time)?;
+        Some(TimeSegment { ts, segment })
+    }
+}
+
+/// Deletes the partitions of `dir` older than `retention`.
+fn enforce_retention(dir: &Path, retention: Duration) -> io::Result<()> {
+    let oldest = partition_of(retention_start(retention));
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if let Some(partition) = parse_partition_path(&path) {
+            if partition < oldest {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the wall time of the oldest segment retained.
+fn retention_start(retention: Duration) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(retention)
+        .unwrap_or(UNIX_EPOCH)
+}
+
+fn partition_path(dir: &Path, partition: u64) -> PathBuf {
+    dir.join(format!("{}.{EXTENSION}", partition * PARTITION.as_secs()))
+}
+
+fn writer_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the history writer stopped")
+}
+
+fn partition_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / PARTITION.as_secs()
+}
+
+fn parse_partition_path(path: &Path) -> Option<u64> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+
+    let secs: u64 = path.file_stem()?.to_str()?.parse().ok()?;
+    Some(secs / PARTITION.as_secs())
+}
+
+fn encode_segment(buf: &mut Vec<u8>, wall_time: SystemTime, segment: &Segment) {
+    buf.extend_from_slice(&RECORD_MARKER);
+
+    let start = buf.len();
+    // Length of the record, written once encoded.
+    buf.extend_from_slice(&0u32.to_le_bytes());
+
+    buf.push(FORMAT_VERSION);
+    let wall_time = wall_time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    buf.extend_from_slice(&(wall_time.as_nanos() as u64).to_le_bytes());
+
+    buf.extend_from_slice(&(segment.index.len() as u32).to_le_bytes());
+    for entry in segment.index.iter() {
+        encode_interest(buf, entry.key());
+        encode_metrics(buf, entry.value());
+    }
+
+    buf.extend_from_slice(&(segment.socks.len() as u32).to_le_bytes());
+    for socket in segment.socks.iter().map(|entry| *entry.value()) {
+        buf.extend_from_slice(&socket.pid.to_le_bytes());
+        encode_socket_addr(buf, &socket.local);
+        encode_socket_addr(buf, &socket.remote);
+        buf.extend_from_slice(&(socket.sock_type as u16).to_le_bytes());
+        buf.extend_from_slice(&socket.uid.to_le_bytes());
+        buf.extend_from_slice(&socket.gid.to_le_bytes());
+        encode_comm(buf, &socket.comm);
+        buf.push(socket.state as u8);
+    }
+
+    buf.extend_from_slice(&(segment.unix_socks.len() as u32).to_le_bytes());
+    for entry in segment.unix_socks.iter() {
+        let socket = entry.key();
+        buf.extend_from_slice(&socket.pid.to_le_bytes());
+        buf.extend_from_slice(&socket.peer_pid.to_le_bytes());
+        buf.push(socket.path.0.len() as u8);
+        buf.extend_from_slice(&socket.path.0);
+        buf.extend_from_slice(&(socket.sock_type as u16).to_le_bytes());
+        encode_metrics(buf, entry.value());
+    }
+
+    buf.extend_from_slice(&(segment.lost_events.len() as u32).to_le_bytes());
+    for entry in segment.lost_events.iter() {
+        buf.extend_from_slice(&entry.key().to_le_bytes());
+        buf.extend_from_slice(&entry.value().to_le_bytes());
+    }
+
+    buf.extend_from_slice(&(segment.overflow.len() as u32).to_le_bytes());
+    for entry in segment.overflow.iter() {
+        buf.extend_from_slice(&entry.key().to_le_bytes());
+        encode_metrics(buf, entry.value());
+    }
+    buf.extend_from_slice(&(segment.unix_overflow.len() as u32).to_le_bytes());
+    for entry in segment.unix_overflow.iter() {
+        buf.extend_from_slice(&entry.key().to_le_bytes());
+        encode_metrics(buf, entry.value());
+    }
+    buf.extend_from_slice(&segment.overflowed().to_le_bytes());
+
+    let len = (buf.len() - start - 4) as u32;
+    buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+fn decode_segment(buf: &mut &[u8]) -> io::Result<(SystemTime, Segment)> {
+    let len = u32::from_le_bytes(take(buf)?) as usize;
+    if buf.len() < len {
+        return Err(invalid_data("truncated record"));
+    }
+    let (mut record, rest) = buf.split_at(len);
+    *buf = rest;
+    let buf = &mut record;
+
+    let [version] = take(buf)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data("unknown format version"));
+    }
+
+    let wall_time = UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(take(buf)?));
+
+    let segment = Segment::default();
+
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let interest = decode_interest(buf)?;
+        let metrics = decode_metrics(buf)?;
+        segment
+            .top_talkers()
+            .insert(interest, metrics.rx.size() + metrics.tx.size());
+        segment.index.insert(interest, metrics);
+    }
+
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let pid = u32::from_le_bytes(take(buf)?);
+        let local = decode_socket_addr(buf)?;
+        let remote = decode_socket_addr(buf)?;
+        let sock_type = u16::from_le_bytes(take(buf)?).into();
+        let uid = u32::from_le_bytes(take(buf)?);
+        let gid = u32::from_le_bytes(take(buf)?);
+        let comm = decode_comm(buf)?;
+        let [state] = take(buf)?;
+
+        segment.socks.insert(
+            local,
+            Socket {
+                pid,
+                uid,
+                gid,
+                comm,
+                local,
+                remote,
+                sock_type,
+                state: state.into(),
+            },
+        );
+    }
+
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let pid = u32::from_le_bytes(take(buf)?);
+        let peer_pid = u32::from_le_bytes(take(buf)?);
+        let [path_len] = take(buf)?;
+        let path = take_slice(buf, path_len.into())?;
+        let sock_type: SockType = u16::from_le_bytes(take(buf)?).into();
+
+        segment.unix_socks.insert(
+            UnixSocket {
+                pid,
+                peer_pid,
+                path: UnixPath(path.into()),
+                sock_type,
+            },
+            decode_metrics(buf)?,
+        );
+    }
+
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let cpu_id = u32::from_le_bytes(take(buf)?);
+        let count = u64::from_le_bytes(take(buf)?);
+        segment.record_lost_events(cpu_id, count);
+    }
+
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let pid = u32::from_le_bytes(take(buf)?);
+        segment.overflow.insert(pid, decode_metrics(buf)?);
+    }
+    for _ in 0..u32::from_le_bytes(take(buf)?) {
+        let pid = u32::from_le_bytes(take(buf)?);
+        segment.unix_overflow.insert(pid, decode_metrics(buf)?);
+    }
+    segment
+        .overflowed
+        .store(u64::from_le_bytes(take(buf)?), Ordering::Relaxed);
+
+    if !buf.is_empty() {
+        return Err(invalid_data("trailing bytes"));
+    }
+
+    Ok((wall_time, segment))
+}
+
+fn encode_interest(buf: &mut Vec<u8>, interest: &Interest) {
+    match interest {
+        Interest::RemoteIp(ip) => {
+            buf.push(0);
+            encode_ip(buf, ip);
+        }
+        Interest::RemoteSocket(addr) => {
+            buf.push(1);
+            encode_socket_addr(buf, addr);
+        }
+        Interest::LocalSocket(addr) => {
+            buf.push(2);
+            encode_socket_addr(buf, addr);
+        }
+        Interest::Pid(pid) => {
+            buf.push(3);
+            buf.extend_from_slice(&pid.to_le_bytes());
+        }
+        Interest::All => buf.push(4),
+        Interest::LocalPort(port) => {
+            buf.push(5);
+            buf.extend_from_slice(&port.to_le_bytes());
+        }
+        Interest::RemotePort(port) => {
+            buf.push(6);
+            buf.extend_from_slice(&port.to_le_bytes());
+        }
+        Interest::ProcessName(comm) => {
+            buf.push(7);
+            encode_comm(buf, comm);
+        }
+        Interest::Uid(uid) => {
+            buf.push(8);
+            buf.extend_from_slice(&uid.to_le_bytes());
+        }
+        Interest::SockType(sock_type) => {
+            buf.push(9);
+            buf.extend_from_slice(&(*sock_type as u16).to_le_bytes());
+        }
+    }
+}
+
+fn decode_interest(buf: &mut &[u8]) -> io::Result<Interest> {
+    let [tag] = take(buf)?;
+
+    Ok(match tag {
+        0 => Interest::RemoteIp(decode_ip(buf)?),
+        1 => Interest::RemoteSocket(decode_socket_addr(buf)?),
+        2 => Interest::LocalSocket(decode_socket_addr(buf)?),
+        3 => Interest::Pid(u32::from_le_bytes(take(buf)?)),
+        4 => Interest::All,
+        5 => Interest::LocalPort(u16::from_le_bytes(take(buf)?)),
+        6 => Interest::RemotePort(u16::from_le_bytes(take(buf)?)),
+        7 => Interest::ProcessName(decode_comm(buf)?),
+        8 => Interest::Uid(u32::from_le_bytes(take(buf)?)),
+        9 => Interest::SockType(u16::from_le_bytes(take(buf)?).into()),
+        _ => return Err(invalid_data("unknown interest")),
+    })
+}
+
+fn encode_metrics(buf: &mut Vec<u8>, metrics: &Metrics) {
+    for val in [
+        metrics.rx.size(),
+        metrics.rx.count(),
+        metrics.tx.size(),
+        metrics.tx.count(),
+    ] {
+        buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // The non-empty buckets of the histogram, after a bitmap of them.
+    let sizes = metrics.sizes.load();
+    let bitmap = sizes
+        .buckets()
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .fold(0u16, |bitmap, (bucket, _)| bitmap | 1 << bucket);
+    buf.extend_from_slice(&bitmap.to_le_bytes());
+    for count in sizes.buckets().iter().filter(|count| **count > 0) {
+        buf.extend_from_slice(&count.to_le_bytes());
+    }
+}
+
+fn decode_metrics(buf: &mut &[u8]) -> io::Result<Metrics> {
+    let metrics = Metrics::default();
+    metrics.rx.increment(
+        u64::from_le_bytes(take(buf)?),
+        u64::from_le_bytes(take(buf)?),
+    );
+    metrics.tx.increment(
+        u64::from_le_bytes(take(buf)?),
+        u64::from_le_bytes(take(buf)?),
+    );
+
+    let bitmap = u16::from_le_bytes(take(buf)?);
+    let mut sizes = SizeHistogram::default();
+    for bucket in (0..SIZE_BUCKETS).filter(|bucket| bitmap & 1 << bucket != 0) {
+        let (lower, _) = SizeHistogram::bounds(bucket);
+        sizes.record(lower, u32::from_le_bytes(take(buf)?));
+    }
+    metrics.sizes.merge(&sizes);
+
+    Ok(metrics)
+}
+
+fn encode_comm(buf: &mut Vec<u8>, comm: &Comm) {
+    buf.push(comm.as_bytes().len() as u8);
+    buf.extend_from_slice(comm.as_bytes());
+}
+
+fn decode_comm(buf: &mut &[u8]) -> io::Result<Comm> {
+    let [len] = take(buf)?;
+    Ok(Comm::new(take_slice(buf, len.into())?))
+}
+
+fn encode_socket_addr(buf: &mut Vec<u8>, addr: &SocketAddr) {
+    encode_ip(buf, &addr.ip());
+    buf.extend_from_slice(&addr.port().to_le_bytes());
+}
+
+fn decode_socket_addr(buf: &mut &[u8]) -> io::Result<SocketAddr> {
+    let ip = decode_ip(buf)?;
+    Ok(SocketAddr::new(ip, u16::from_le_bytes(take(buf)?)))
+}
+
+fn encode_ip(buf: &mut Vec<u8>, ip: &IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+}
+
+fn decode_ip(buf: &mut &[u8]) -> io::Result<IpAddr> {
+    let [version] = take(buf)?;
+
+    match version {
+        4 => Ok(Ipv4Addr::from(take::<4>(buf)?).into()),
+        6 => Ok(Ipv6Addr::from(take::<16>(buf)?).into()),
+        _ => Err(invalid_data("unknown ip version")),
+    }
+}
+
+fn take<const N: usize>(buf: &mut &[u8]) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(take_slice(buf, N)?);
+    Ok(bytes)
+}
+
+fn take_slice<'a>(buf: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(invalid_data("truncated record"));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes)
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use ptraf_common::{Channel, TcpState};
+
+    use super::*;
+
+    #[test]
+    fn disk_store_append_and_read() {
+        let dir = std::env::temp_dir().join(format!("ptraf-disk-store-{}", std::process::id()));
+        let clock = ClockNano::with_past(Duration::from_secs(7200));
+
+        let segment = Segment::default();
+        let socket = Socket {
+            pid: 1,
+            uid: 1000,
+            gid: 100,
+            comm: "curl".into(),
+            local: "10.0.0.1:4242".parse().unwrap(),
+            remote: "[2001:db8::1]:443".parse().unwrap(),
+            sock_type: SockType::Stream,
+            state: TcpState::CloseWait,
+        };
+        segment.socks.insert(socket.local, socket);
+        {
+            let metrics = segment
+                .index
+                .entry(Interest::LocalSocket(socket.local))
+                .or_default();
+            metrics.increment(Channel::Tx, 50);
+            metrics.increment(Channel::Tx, 50);
+        }
+        segment
+            .unix_socks
+            .entry(UnixSocket {
+                pid: 2,
+                peer_pid: 3,
+                path: UnixPath(b"/run/docker.sock".to_vec().into()),
+                sock_type: SockType::Stream,
+            })
+            .or_default()
+            .increment(Channel::Rx, 10);
+        segment.record_lost_events(0, 5);
+        {
+            let metrics = segment.overflow.entry(4).or_default();
+            (0..3).for_each(|_| metrics.increment(Channel::Rx, 10));
+        }
+        segment
+            .unix_overflow
+            .entry(5)
+            .or_default()
+            .increment(Channel::Tx, 20);
+        segment.overflowed.store(4, Ordering::Relaxed);
+
+        let now = clock.now();
+        let store = DiskStore::open(&dir, Duration::from_secs(3600), clock.clone()).unwrap();
+        store.append(&TimeSegment { ts: now, segment }).unwrap();
+        store.sync().unwrap();
+
+        // A truncated record and a corrupt one are skipped.
+        let path = store.partition_path(partition_of(clock.wall_time(now)));
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&RECORD_MARKER).unwrap();
+        file.write_all(&[42, 0, 0, 0, FORMAT_VERSION]).unwrap();
+        file.write_all(&RECORD_MARKER).unwrap();
+        file.write_all(&[1, 0, 0, 0, FORMAT_VERSION + 1]).unwrap();
+
+        let since = clock.wall_time(now) - Duration::from_secs(60);
+        let count = |partitions: Vec<Partition>| partitions.iter().map(|p| p.len()).sum::<usize>();
+        assert_eq!(1, count(store.partitions_since(since).unwrap()));
+
+        // The segments written are appended to the partition cached.
+        let later = TimeSegment {
+            ts: now + Duration::from_millis(1),
+            segment: Segment::default(),
+        };
+        later.segment.record_lost_events(1, 7);
+        store.append(&later).unwrap();
+        store.sync().unwrap();
+
+        let partitions = store.partitions_since(since).unwrap();
+        let segments: Vec<_> = partitions.iter().flat_map(|p| p.iter()).collect();
+        let read = store
+            .read_partition(partition_of(clock.wall_time(now)))
+            .unwrap();
+        assert_eq!(
+            read.iter()
+                .map(|time_segment| time_segment.ts)
+                .collect::<Vec<_>>(),
+            segments
+                .iter()
+                .map(|time_segment| time_segment.ts)
+                .collect::<Vec<_>>()
+        );
+
+        let cached = store.partitions_since(since).unwrap();
+        assert!(partitions
+            .iter()
+            .zip(&cached)
+            .all(|(partition, cached)| Arc::ptr_eq(partition, cached)));
+
+        assert_eq!(2, segments.len());
+        assert_eq!(later.ts, segments[1].ts);
+        assert_eq!(7, segments[1].segment.lost_events());
+        let time_segment = segments[0];
+        // Wall times are stored with a nanosecond precision.
+        assert_eq!(now, time_segment.ts);
+
+        let mut socks = Vec::new();
+        time_segment
+            .segment
+            .for_each_socket(|socket| socks.push(*socket));
+        assert_eq!(vec![socket], socks);
+        assert_eq!(
+            (1000, 100, "curl".into(), TcpState::CloseWait),
+            (socks[0].uid, socks[0].gid, socks[0].comm, socks[0].state)
+        );
+        let stat = time_segment
+            .segment
+            .stat_by_interest(&Interest::LocalSocket(socket.local))
+            .unwrap();
+        assert_eq!((100, 2), (stat.tx, stat.tx_packet_count));
+        assert_eq!(2, stat.sizes.buckets()[SizeHistogram::bucket(50)]);
+
+        let mut unix_socks = Vec::new();
+        time_segment
+            .segment
+            .for_each_unix_socket(|socket, stat| unix_socks.push((socket.to_owned(), stat.rx)));
+        assert_eq!(1, unix_socks.len());
+        assert_eq!("/run/docker.sock", unix_socks[0].0.path.to_string());
+        assert_eq!(10, unix_socks[0].1);
+
+        assert_eq!(5, time_segment.segment.lost_events());
+
+        let mut overflow = Vec::new();
+        time_segment
+            .segment
+            .for_each_overflow(|pid, stat| overflow.push((pid, stat.rx, stat.rx_packet_count)));
+        assert_eq!(vec![(4, 30, 3)], overflow);
+        let mut unix_overflow = Vec::new();
+        time_segment
+            .segment
+            .for_each_unix_overflow(|pid, stat| unix_overflow.push((pid, stat.tx)));
+        assert_eq!(vec![(5, 20)], unix_overflow);
+        assert_eq!(4, time_segment.segment.overflowed());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
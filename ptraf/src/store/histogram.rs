@@ -0,0 +1,156 @@
+//! Distribution of the message sizes.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Number of buckets: under 64 B, one per power of two up to 1 MiB, then 1 MiB and more.
+pub const SIZE_BUCKETS: usize = 16;
+
+/// Log2 of the upper bound of the first bucket.
+const FIRST_BUCKET_LOG2: u32 = 6;
+
+/// Log-bucketed histogram of the message sizes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SizeHistogram {
+    buckets: [u32; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    /// Returns the bucket of the messages of `size` bytes.
+    pub fn bucket(size: u64) -> usize {
+        if size < 1 << FIRST_BUCKET_LOG2 {
+            0
+        } else {
+            let log2 = u64::BITS - 1 - size.leading_zeros();
+            ((log2 + 1 - FIRST_BUCKET_LOG2) as usize).min(SIZE_BUCKETS - 1)
+        }
+    }
+
+    /// Returns the lower and upper bounds of the sizes of `bucket`, the last one is unbounded.
+    pub fn bounds(bucket: usize) -> (u64, Option<u64>) {
+        let lower = match bucket {
+            0 => 0,
+            _ => 1 << (bucket as u32 - 1 + FIRST_BUCKET_LOG2),
+        };
+        let upper = (bucket + 1 < SIZE_BUCKETS).then(|| 1 << (bucket as u32 + FIRST_BUCKET_LOG2));
+        (lower, upper)
+    }
+
+    pub fn record(&mut self, size: u64, count: u32) {
+        let bucket = &mut self.buckets[Self::bucket(size)];
+        *bucket = bucket.saturating_add(count);
+    }
+
+    /// Returns the number of messages of each bucket.
+    pub fn buckets(&self) -> &[u32; SIZE_BUCKETS] {
+        &self.buckets
+    }
+
+    /// Returns the number of messages.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|count| u64::from(*count)).sum()
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket = bucket.saturating_add(count);
+        }
+    }
+
+    /// Returns the bucket of the `quantile` (between 0 and 1) of the messages, or `None`
+    /// if there are no messages.
+    pub fn quantile(&self, quantile: f64) -> Option<usize> {
+        let rank = ((quantile * self.count() as f64).ceil() as u64).max(1);
+
+        let mut seen = 0;
+        self.buckets.iter().position(|count| {
+            seen += u64::from(*count);
+            seen >= rank
+        })
+    }
+}
+
+/// A [SizeHistogram] updated concurrently.
+#[derive(Debug, Default)]
+pub(super) struct AtomicSizeHistogram {
+    buckets: [AtomicU32; SIZE_BUCKETS],
+}
+
+impl AtomicSizeHistogram {
+    pub(super) fn record(&self, size: u64, count: u64) {
+        saturating_add(
+            &self.buckets[SizeHistogram::bucket(size)],
+            u32::try_from(count).unwrap_or(u32::MAX),
+        );
+    }
+
+    pub(super) fn merge(&self, other: &SizeHistogram) {
+        for (bucket, count) in self.buckets.iter().zip(other.buckets) {
+            // Most histograms only have a few buckets in use.
+            if count > 0 {
+                saturating_add(bucket, count);
+            }
+        }
+    }
+
+    pub(super) fn load(&self) -> SizeHistogram {
+        let mut histogram = SizeHistogram::default();
+        for (count, bucket) in histogram.buckets.iter_mut().zip(&self.buckets) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        histogram
+    }
+}
+
+/// Adds `count` to the `bucket`, saturating like [SizeHistogram::merge].
+fn saturating_add(bucket: &AtomicU32, count: u32) {
+    // The closure always returns `Some`, the update can't fail.
+    let _ = bucket.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bucket| {
+        Some(bucket.saturating_add(count))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_histogram() {
+        assert_eq!(0, SizeHistogram::bucket(0));
+        assert_eq!(0, SizeHistogram::bucket(63));
+        assert_eq!(1, SizeHistogram::bucket(64));
+        assert_eq!(1, SizeHistogram::bucket(127));
+        assert_eq!(14, SizeHistogram::bucket((1 << 20) - 1));
+        assert_eq!(15, SizeHistogram::bucket(1 << 20));
+        assert_eq!(15, SizeHistogram::bucket(u64::MAX));
+
+        assert_eq!((0, Some(64)), SizeHistogram::bounds(0));
+        assert_eq!((64, Some(128)), SizeHistogram::bounds(1));
+        assert_eq!((1 << 20, None), SizeHistogram::bounds(15));
+        for size in [0, 1, 64, 100, 4096, 1 << 19, 1 << 21] {
+            let (lower, upper) = SizeHistogram::bounds(SizeHistogram::bucket(size));
+            assert!(lower <= size && upper.map(|upper| size < upper).unwrap_or(true));
+        }
+
+        let mut histogram = SizeHistogram::default();
+        assert_eq!(None, histogram.quantile(0.5));
+
+        // Many small RPCs and a few large transfers.
+        histogram.record(60, 90);
+        histogram.record(1 << 20, 10);
+        assert_eq!(Some(0), histogram.quantile(0.5));
+        assert_eq!(Some(0), histogram.quantile(0.9));
+        assert_eq!(Some(15), histogram.quantile(0.99));
+
+        let atomic = AtomicSizeHistogram::default();
+        atomic.record(100, 3);
+        atomic.merge(&histogram);
+        histogram.record(100, 3);
+        assert_eq!(histogram, atomic.load());
+        assert_eq!(103, histogram.count());
+
+        // The buckets saturate.
+        atomic.record(100, u64::MAX);
+        atomic.merge(&histogram);
+        assert_eq!(u32::MAX, atomic.load().buckets()[1]);
+    }
+}
@@ -0,0 +1,799 @@
+//! Aggregation of the traffic over a time range.
+
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    ops::Range,
+    sync::Arc,
+    time::Duration,
+};
+
+use fxhash::FxBuildHasher;
+use ptraf_filter::{
+    Filterable, Interpretor, IpVersion, Lookup, Protocol, SockType, TcpState, Traffic,
+};
+
+use crate::clock::Timestamp;
+
+use super::{
+    topk::{TopK, TOP_TALKERS_CAPACITY},
+    Interest, Socket, Stat, Store, TimeSegment,
+};
+
+/// Dimension the rows of a query are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Pid,
+    ProcessName,
+    RemoteIp,
+    RemotePort,
+    Uid,
+    /// Local socket address.
+    Socket,
+}
+
+/// Key of a row of a query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GroupKey {
+    Pid(u32),
+    ProcessName(String),
+    RemoteIp(IpAddr),
+    RemotePort(u16),
+    Uid(u32),
+    Socket(Socket),
+}
+
+/// A query over the traffic of the sockets in a time range.
+///
+/// The traffic is aggregated in rows if the query is grouped (see [group_by](Self::group_by))
+/// and/or in a time series with one data point per segment (see [series](Self::series)).
+pub struct Query<'a> {
+    range: Range<Timestamp>,
+    interest: Interest,
+    interpretor: Option<&'a Interpretor>,
+    group_by: Option<GroupBy>,
+    process_name: Option<&'a dyn Fn(u32) -> String>,
+    rate_window: Option<Duration>,
+    limit: Option<usize>,
+    series: bool,
+    traffic_thresholds: Option<TrafficThresholds<'a>>,
+}
+
+/// How the traffic thresholds of the filter are evaluated.
+#[derive(Clone, Copy)]
+enum TrafficThresholds<'a> {
+    /// Before the traffic is aggregated, the sockets which may match are accepted.
+    Unknown,
+    /// The sockets matching the filter with their traffic, see [Store::traffic_matches].
+    Matches(&'a HashSet<Socket, FxBuildHasher>),
+}
+
+impl<'a> Query<'a> {
+    /// Returns a query over the segments whose timestamp is in `range`.
+    pub fn new(range: Range<Timestamp>) -> Self {
+        Self {
+            range,
+            interest: Interest::All,
+            interpretor: None,
+            group_by: None,
+            process_name: None,
+            rate_window: None,
+            limit: None,
+            series: false,
+            traffic_thresholds: None,
+        }
+    }
+
+    /// Only accounts the sockets matching `interest`.
+    ///
+    /// Without filter nor grouping, the traffic of an indexed interest is looked up in the
+    /// segments, see [Store::with_dimensions].
+    pub fn interest(mut self, interest: Interest) -> Self {
+        self.interest = interest;
+        self
+    }
+
+    /// Only accounts the sockets matching the filter.
+    ///
+    /// The traffic thresholds are evaluated against the traffic of each socket over the range,
+    /// and the rate over the rate window, or over the range without rate window.
+    pub fn filter(mut self, interpretor: Option<&'a Interpretor>) -> Self {
+        self.interpretor = interpretor;
+        self
+    }
+
+    pub fn group_by(mut self, group_by: GroupBy) -> Self {
+        self.group_by = Some(group_by);
+        self
+    }
+
+    /// Resolves the process names of [GroupBy::ProcessName], the pid is used otherwise.
+    pub fn process_name(mut self, process_name: &'a dyn Fn(u32) -> String) -> Self {
+        self.process_name = Some(process_name);
+        self
+    }
+
+    /// Also aggregates the traffic of the rows over the last `window` of the range,
+    /// see [QueryRow::rate_stat].
+    pub fn rate_window(mut self, window: Duration) -> Self {
+        self.rate_window = Some(window);
+        self
+    }
+
+    /// Only returns the `limit` rows with the most traffic.
+    ///
+    /// Without filter nor interest, the heaviest pids, remote IPs and sockets are found from the
+    /// top talkers of each segment instead of scanning all the sockets. The top talkers don't
+    /// know which keys match a filter or an interest, those queries scan all the sockets.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Also returns the time series of the traffic.
+    pub fn series(mut self) -> Self {
+        self.series = true;
+        self
+    }
+
+    fn accepts(&self, socket: &Socket) -> bool {
+        socket.match_interest(self.interest)
+            && match (self.interpretor, self.traffic_thresholds) {
+                (None, _) => true,
+                (Some(interpretor), None) => interpretor.filter(socket),
+                (Some(interpretor), Some(TrafficThresholds::Unknown)) => {
+                    interpretor.may_match(socket)
+                }
+                (Some(_), Some(TrafficThresholds::Matches(sockets))) => sockets.contains(socket),
+            }
+    }
+
+    fn with_traffic_thresholds<'b>(&self, traffic_thresholds: TrafficThresholds<'b>) -> Query<'b>
+    where
+        'a: 'b,
+    {
+        Query {
+            range: self.range.clone(),
+            interest: self.interest,
+            interpretor: self.interpretor,
+            group_by: self.group_by,
+            process_name: self.process_name,
+            rate_window: self.rate_window,
+            limit: self.limit,
+            series: self.series,
+            traffic_thresholds: Some(traffic_thresholds),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryRow {
+    pub key: GroupKey,
+    /// Traffic over the range.
+    pub stat: Stat,
+    /// Traffic over the rate window, see [QueryResult::rate_range].
+    pub rate_stat: Stat,
+    /// Timestamp of the newest segment with traffic.
+    pub last_activity: Timestamp,
+}
+
+#[derive(Debug, Default)]
+pub struct QueryResult {
+    /// Rows of a grouped query, from the heaviest if the query is limited,
+    /// in no particular order otherwise.
+    pub rows: Vec<QueryRow>,
+    /// The traffic of each segment in chronological order, if requested.
+    pub series: Vec<(Timestamp, Stat)>,
+    /// The traffic over the range.
+    pub total: Stat,
+    /// Timestamps of the oldest and the newest segments in the rate window.
+    pub rate_range: Option<Range<Timestamp>>,
+    /// Number of events lost by the perf buffers over the range, its traffic is under-reported
+    /// if any.
+    pub lost_events: u64,
+}
+
+impl Store {
+    /// Runs the query over the history.
+    pub fn query(&self, query: &Query<'_>) -> QueryResult {
+        if let Some(interpretor) = query.interpretor {
+            if query.traffic_thresholds.is_none() && interpretor.has_traffic() {
+                // The rows of the sockets are enough to evaluate the thresholds, the other
+                // queries account the traffic of the matching sockets in a second pass.
+                if query.group_by == Some(GroupBy::Socket) && !query.series {
+                    return self.query_by_socket(query, interpretor);
+                }
+
+                let by_socket = Query {
+                    group_by: Some(GroupBy::Socket),
+                    series: false,
+                    ..query.with_traffic_thresholds(TrafficThresholds::Unknown)
+                };
+                let sockets = self
+                    .query_by_socket(&by_socket, interpretor)
+                    .rows
+                    .into_iter()
+                    .filter_map(|row| match row.key {
+                        GroupKey::Socket(socket) => Some(socket),
+                        _ => None,
+                    })
+                    .collect();
+                return self
+                    .query(&query.with_traffic_thresholds(TrafficThresholds::Matches(&sockets)));
+            }
+        }
+
+        let newest = self
+            .segments_view()
+            .newest()
+            .map(|time_segment| time_segment.ts);
+        let duration = newest
+            .unwrap_or(query.range.end)
+            .0
+            .saturating_sub(query.range.start.0);
+
+        let view = self.segments_view_covering(duration);
+
+        let mut rows: HashMap<GroupKey, QueryRow, FxBuildHasher> = HashMap::default();
+        let mut process_names: HashMap<u32, String, FxBuildHasher> = HashMap::default();
+        let mut process_name = |pid: u32| {
+            process_names
+                .entry(pid)
+                .or_insert_with(|| match query.process_name {
+                    Some(process_name) => process_name(pid),
+                    None => pid.to_string(),
+                })
+                .clone()
+        };
+        let mut series = Vec::new();
+        let mut range_total = Stat::default();
+        let mut lost_events = 0;
+        let mut rate_since = None;
+        let mut rate_range: Option<Range<Timestamp>> = None;
+
+        // From the newest to the oldest so the last activity is the first one found.
+        let segments = || {
+            view.iter()
+                .rev()
+                .skip_while(|time_segment| time_segment.ts >= query.range.end)
+                .take_while(|time_segment| time_segment.ts >= query.range.start)
+        };
+
+        let candidates = match (query.limit, query.group_by) {
+            (Some(limit), Some(group_by))
+                if query.interest == Interest::All && query.interpretor.is_none() =>
+            {
+                top_candidates(segments(), group_by, limit)
+            }
+            _ => None,
+        };
+
+        for time_segment in segments() {
+            let segment = &time_segment.segment;
+
+            let rate_since = *rate_since.get_or_insert_with(|| {
+                query
+                    .rate_window
+                    .map(|window| Timestamp::from(time_segment.ts.0.saturating_sub(window)))
+            });
+            let is_rate_eligible = matches!(rate_since, Some(since) if since <= time_segment.ts);
+            if is_rate_eligible {
+                rate_range = Some(
+                    time_segment.ts
+                        ..rate_range
+                            .as_ref()
+                            .map_or(time_segment.ts, |range| range.end),
+                );
+            }
+
+            let mut total = Stat::default();
+
+            let mut add_row = |key: GroupKey, stat: Stat| {
+                let row = rows.entry(key).or_insert_with_key(|key| QueryRow {
+                    key: key.clone(),
+                    stat: Stat::default(),
+                    rate_stat: Stat::default(),
+                    last_activity: time_segment.ts,
+                });
+                row.stat += stat;
+                if is_rate_eligible {
+                    row.rate_stat += stat;
+                }
+            };
+
+            if let Some(candidates) = &candidates {
+                total = segment
+                    .stat_by_interest(&query.interest)
+                    .unwrap_or_default();
+
+                for interest in candidates {
+                    let stat = match segment.stat_by_interest(interest) {
+                        Some(stat) => stat,
+                        None => continue,
+                    };
+                    let key = match *interest {
+                        Interest::Pid(pid) => GroupKey::Pid(pid),
+                        Interest::RemoteIp(ip) => GroupKey::RemoteIp(ip),
+                        Interest::LocalSocket(local) => match segment.socket(&local) {
+                            Some(socket) => GroupKey::Socket(socket),
+                            None => continue,
+                        },
+                        _ => continue,
+                    };
+                    add_row(key, stat);
+                }
+            } else if query.group_by.is_none()
+                && query.interpretor.is_none()
+                && self.is_indexed(&query.interest)
+            {
+                total = segment
+                    .stat_by_interest(&query.interest)
+                    .unwrap_or_default();
+            } else {
+                segment.for_each_socket(|socket| {
+                    if !query.accepts(socket) {
+                        return;
+                    }
+
+                    let stat = segment
+                        .stat_by_interest(&Interest::LocalSocket(socket.local))
+                        .unwrap_or_default();
+                    total += stat;
+
+                    let key = match query.group_by {
+                        None => return,
+                        Some(GroupBy::Pid) => GroupKey::Pid(socket.pid),
+                        Some(GroupBy::ProcessName) => {
+                            GroupKey::ProcessName(process_name(socket.pid))
+                        }
+                        Some(GroupBy::RemoteIp) => GroupKey::RemoteIp(socket.remote.ip()),
+                        Some(GroupBy::RemotePort) => GroupKey::RemotePort(socket.remote.port()),
+                        Some(GroupBy::Uid) => GroupKey::Uid(socket.uid),
+                        Some(GroupBy::Socket) => GroupKey::Socket(*socket),
+                    };
+                    add_row(key, stat);
+                });
+
+                // Sockets beyond the limits of the store are only accounted by process.
+                if query.interpretor.is_none() {
+                    segment.for_each_overflow(|pid, stat| {
+                        if query.interest != Interest::All && query.interest != Interest::Pid(pid) {
+                            return;
+                        }
+                        total += stat;
+
+                        let key = match query.group_by {
+                            Some(GroupBy::Pid) => GroupKey::Pid(pid),
+                            Some(GroupBy::ProcessName) => GroupKey::ProcessName(process_name(pid)),
+                            _ => return,
+                        };
+                        add_row(key, stat);
+                    });
+                }
+            }
+
+            range_total += total;
+            lost_events += segment.lost_events();
+            if query.series {
+                series.push((time_segment.ts, total));
+            }
+        }
+
+        series.reverse();
+
+        let mut rows: Vec<_> = rows.into_values().collect();
+        if let Some(limit) = query.limit {
+            rows.sort_unstable_by_key(|row| Reverse(row.stat.total()));
+            rows.truncate(limit);
+        }
+
+        QueryResult {
+            rows,
+            series,
+            total: range_total,
+            rate_range,
+            lost_events,
+        }
+    }
+
+    /// Runs the query grouped by socket over the sockets which may match the filter, and only
+    /// keeps the rows matching it once their traffic is aggregated.
+    fn query_by_socket(&self, query: &Query<'_>, interpretor: &Interpretor) -> QueryResult {
+        // The rates are over the range without rate window.
+        let mut result = self.query(&Query {
+            rate_window: Some(query.rate_window.unwrap_or(Duration::MAX)),
+            limit: None,
+            ..query.with_traffic_thresholds(TrafficThresholds::Unknown)
+        });
+
+        let rate_duration = result
+            .rate_range
+            .as_ref()
+            .map(|range| range.start.saturating_elapsed_since(&range.end))
+            .filter(|duration| !duration.is_zero());
+        let rate = |bytes: u64| rate_duration.map_or(0.0, |d| bytes as f64 / d.as_secs_f64());
+
+        result.rows.retain(|row| match row.key {
+            GroupKey::Socket(socket) => interpretor.filter(&SocketTraffic {
+                socket,
+                traffic: Traffic {
+                    rx_bytes: row.stat.rx,
+                    tx_bytes: row.stat.tx,
+                    rx_packets: row.stat.rx_packet_count,
+                    tx_packets: row.stat.tx_packet_count,
+                    rx_rate: rate(row.rate_stat.rx),
+                    tx_rate: rate(row.rate_stat.tx),
+                },
+            }),
+            _ => false,
+        });
+
+        result.total = Stat::default();
+        for row in &mut result.rows {
+            result.total += row.stat;
+            if query.rate_window.is_none() {
+                row.rate_stat = Stat::default();
+            }
+        }
+        if query.rate_window.is_none() {
+            result.rate_range = None;
+        }
+
+        if let Some(limit) = query.limit {
+            result
+                .rows
+                .sort_unstable_by_key(|row| Reverse(row.stat.total()));
+            result.rows.truncate(limit);
+        }
+
+        result
+    }
+}
+
+/// A socket with its traffic aggregated over a range.
+struct SocketTraffic {
+    socket: Socket,
+    traffic: Traffic,
+}
+
+impl Filterable for SocketTraffic {
+    fn pid(&self) -> u32 {
+        self.socket.pid()
+    }
+
+    fn protocol(&self) -> Option<Protocol> {
+        self.socket.protocol()
+    }
+
+    fn sock_type(&self) -> Option<SockType> {
+        self.socket.sock_type()
+    }
+
+    fn state(&self) -> Option<TcpState> {
+        self.socket.state()
+    }
+
+    fn ip_version(&self) -> IpVersion {
+        self.socket.ip_version()
+    }
+
+    fn local_address(&self) -> IpAddr {
+        self.socket.local_address()
+    }
+
+    fn remote_address(&self) -> IpAddr {
+        self.socket.remote_address()
+    }
+
+    fn local_port(&self) -> u16 {
+        self.socket.local_port()
+    }
+
+    fn remote_port(&self) -> u16 {
+        self.socket.remote_port()
+    }
+
+    fn uid(&self) -> Option<u32> {
+        self.socket.uid()
+    }
+
+    fn user_name(&self) -> Option<&str> {
+        self.socket.user_name()
+    }
+
+    fn gid(&self) -> Option<u32> {
+        self.socket.gid()
+    }
+
+    fn group_name(&self) -> Option<&str> {
+        self.socket.group_name()
+    }
+
+    fn comm(&self) -> Option<Cow<'_, str>> {
+        self.socket.comm()
+    }
+
+    fn exe(&self) -> Option<Arc<str>> {
+        self.socket.exe()
+    }
+
+    fn cmdline(&self) -> Option<Arc<str>> {
+        self.socket.cmdline()
+    }
+
+    fn host_name(&self, addr: IpAddr) -> Lookup<Arc<str>> {
+        self.socket.host_name(addr)
+    }
+
+    fn host_addrs(&self, name: &str) -> Lookup<Arc<[IpAddr]>> {
+        self.socket.host_addrs(name)
+    }
+
+    fn traffic(&self) -> Option<Traffic> {
+        Some(self.traffic)
+    }
+}
+
+/// Returns the interests likely to be the `limit` heaviest over the segments,
+/// or `None` if the segments don't track the top talkers by `group_by`.
+fn top_candidates<'a>(
+    segments: impl Iterator<Item = &'a TimeSegment>,
+    group_by: GroupBy,
+    limit: usize,
+) -> Option<Vec<Interest>> {
+    // Twice the limit since the counts of the sketches are approximate,
+    // the exact traffic of the candidates is then looked up in the segments.
+    let candidates = limit.saturating_mul(2);
+    let mut top = TopK::new(TOP_TALKERS_CAPACITY.max(candidates));
+
+    for time_segment in segments {
+        top.merge(time_segment.segment.top_talkers().get(group_by)?);
+    }
+
+    Some(
+        top.top(candidates)
+            .into_iter()
+            .map(|(interest, _)| interest)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::store::tests::msg;
+
+    use super::*;
+
+    #[test]
+    fn store_query() {
+        let window = Duration::from_millis(100);
+        let store = Store::new(window, 16);
+
+        let at = |ms| Timestamp::from(Duration::from_millis(ms));
+
+        store.batch_update([
+            (at(0), &msg(1, 1000, 32, 80, 10)),
+            (at(0), &msg(2, 2000, 32, 443, 20)),
+            (at(100), &msg(1, 1000, 32, 80, 30)),
+            (at(100), &msg(1, 1001, 35, 443, 40)),
+            (at(200), &msg(2, 2000, 32, 443, 50)),
+        ]);
+
+        let mut result = store.query(
+            &Query::new(at(0)..at(300))
+                .group_by(GroupBy::Pid)
+                .rate_window(Duration::from_millis(100))
+                .series(),
+        );
+        result.rows.sort_by_key(|row| format!("{:?}", row.key));
+
+        let rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row.key.clone(),
+                    row.stat.tx,
+                    row.rate_stat.tx,
+                    row.last_activity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (GroupKey::Pid(1), 80, 70, at(100)),
+                (GroupKey::Pid(2), 70, 50, at(200)),
+            ]
+        );
+        assert_eq!(
+            result
+                .series
+                .iter()
+                .map(|(ts, stat)| (*ts, stat.tx))
+                .collect::<Vec<_>>(),
+            vec![(at(0), 30), (at(100), 70), (at(200), 50)]
+        );
+        assert_eq!(result.rate_range, Some(at(100)..at(200)));
+        assert_eq!(result.lost_events, 0);
+
+        // The events lost over the range.
+        store.record_lost_events(at(100), 0, 3);
+        store.record_lost_events(at(200), 1, 4);
+        let result = store.query(&Query::new(at(0)..at(200)).group_by(GroupBy::Pid));
+        assert_eq!(result.lost_events, 3);
+
+        // Restricted range and interest.
+        let result = store.query(
+            &Query::new(at(0)..at(200))
+                .interest(Interest::RemoteIp(ptraf_common::IpAddr::v4(32).into()))
+                .group_by(GroupBy::RemotePort),
+        );
+        let mut rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| (row.key.clone(), row.stat.tx))
+            .collect();
+        rows.sort_by_key(|(key, _)| format!("{key:?}"));
+        assert_eq!(
+            rows,
+            vec![
+                (GroupKey::RemotePort(443), 20),
+                (GroupKey::RemotePort(80), 40)
+            ]
+        );
+
+        let result = store.query(
+            &Query::new(at(0)..at(300))
+                .group_by(GroupBy::ProcessName)
+                .process_name(&|pid| format!("proc-{}", pid % 2)),
+        );
+        let mut rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| (row.key.clone(), row.stat.tx))
+            .collect();
+        rows.sort_by_key(|(key, _)| format!("{key:?}"));
+        assert_eq!(
+            rows,
+            vec![
+                (GroupKey::ProcessName("proc-0".to_string()), 70),
+                (GroupKey::ProcessName("proc-1".to_string()), 80),
+            ]
+        );
+
+        let result = store.query(&Query::new(at(0)..at(300)).group_by(GroupBy::Uid));
+        let rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| (row.key.clone(), row.stat.tx))
+            .collect();
+        assert_eq!(rows, vec![(GroupKey::Uid(1000), 150)]);
+    }
+
+    #[test]
+    fn store_query_traffic() {
+        let window = Duration::from_millis(100);
+        let store = Store::new(window, 16);
+
+        let at = |ms| Timestamp::from(Duration::from_millis(ms));
+
+        store.batch_update([
+            (at(0), &msg(1, 1000, 32, 80, 10)),
+            (at(0), &msg(2, 2000, 32, 443, 20)),
+            (at(100), &msg(1, 1000, 32, 80, 30)),
+            (at(100), &msg(1, 1001, 35, 443, 40)),
+            (at(200), &msg(2, 2000, 32, 443, 50)),
+        ]);
+
+        let query = |filter: &str, group_by| {
+            let interpretor = Interpretor::parse(filter).unwrap();
+            let result = store.query(
+                &Query::new(at(0)..at(300))
+                    .filter(Some(&interpretor))
+                    .group_by(group_by)
+                    .rate_window(Duration::from_millis(100))
+                    .series(),
+            );
+            let mut rows: Vec<_> = result
+                .rows
+                .iter()
+                .map(|row| (row.key.clone(), row.stat.tx))
+                .collect();
+            rows.sort_by_key(|(key, _)| format!("{key:?}"));
+            (rows, result.total.tx)
+        };
+
+        // The thresholds apply to the traffic of the sockets, not of the rows.
+        assert_eq!(
+            query("bytes > 50", GroupBy::Pid),
+            (vec![(GroupKey::Pid(2), 70)], 70)
+        );
+        assert_eq!(
+            query("not txbytes > 50", GroupBy::Pid),
+            (vec![(GroupKey::Pid(1), 80)], 80)
+        );
+
+        // 300, 400 and 500 B/s over the rate window.
+        let (rows, total) = query("rate > 350 and rport[443]", GroupBy::Socket);
+        let ports: Vec<_> = rows
+            .iter()
+            .map(|(key, tx)| match key {
+                GroupKey::Socket(socket) => (socket.local.port(), *tx),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(ports, vec![(1001, 40), (2000, 70)]);
+        assert_eq!(total, 110);
+
+        // Without series, the rows of the sockets are filtered in a single pass.
+        let interpretor = Interpretor::parse("rate > 350 and rport[443]").unwrap();
+        let result = store.query(
+            &Query::new(at(0)..at(300))
+                .filter(Some(&interpretor))
+                .group_by(GroupBy::Socket)
+                .rate_window(Duration::from_millis(100)),
+        );
+        let mut ports: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| match row.key {
+                GroupKey::Socket(socket) => (socket.local.port(), row.stat.tx, row.rate_stat.tx),
+                _ => unreachable!(),
+            })
+            .collect();
+        ports.sort();
+        assert_eq!(ports, vec![(1001, 40, 40), (2000, 70, 50)]);
+        assert_eq!(result.total.tx, 110);
+    }
+
+    #[test]
+    fn store_query_limit() {
+        let window = Duration::from_millis(100);
+        let store = Store::new(window, 16);
+
+        let at = |ms| Timestamp::from(Duration::from_millis(ms));
+
+        let mut messages = Vec::new();
+        for segment in 0..4 {
+            // Many light remotes and a few heavy ones.
+            for remote in 0..200 {
+                messages.push((
+                    at(segment * 100),
+                    msg(1, 1000 + remote as u16, remote, 80, 1),
+                ));
+            }
+            for remote in 1000..1003 {
+                messages.push((
+                    at(segment * 100),
+                    msg(2, 4000 + remote as u16, remote, 443, remote as i32),
+                ));
+            }
+        }
+        store.batch_update(messages.iter().map(|(ts, msg)| (*ts, msg)));
+
+        for group_by in [GroupBy::RemoteIp, GroupBy::Pid, GroupBy::Socket] {
+            let query = Query::new(at(0)..at(400)).group_by(group_by);
+
+            let mut expected = store.query(&query).rows;
+            expected.sort_by_key(|row| Reverse(row.stat.total()));
+            expected.truncate(2);
+
+            let top = store.query(&query.limit(2)).rows;
+
+            assert_eq!(
+                top.iter()
+                    .map(|row| (row.key.clone(), row.stat.total(), row.last_activity))
+                    .collect::<Vec<_>>(),
+                expected
+                    .iter()
+                    .map(|row| (row.key.clone(), row.stat.total(), row.last_activity))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+}
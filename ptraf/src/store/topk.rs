@@ -0,0 +1,200 @@
+//! Approximate heavy hitters of the segments.
+//!
+//! The sketches only select the candidate keys of a limited query, their traffic is then read
+//! from the index of the segments. The queries with a filter or an interest don't use them,
+//! the sketches don't know which keys match, and scan the sockets instead.
+
+use std::{collections::HashMap, hash::Hash};
+
+use fxhash::FxBuildHasher;
+
+use super::{GroupBy, Interest};
+
+/// Number of keys tracked per dimension in each segment.
+pub(super) const TOP_TALKERS_CAPACITY: usize = 64;
+
+/// Space-saving sketch of the heaviest keys of a stream.
+///
+/// At most `capacity` counters are kept. When the sketch is full, a new key takes over the
+/// counter of the lightest key: counts are over-estimated by at most the weight of the lightest
+/// counter, and any key weighing more than `total / capacity` is kept.
+///
+/// The counters are a binary min-heap indexed by key, the lightest one is found in constant
+/// time and each insertion costs `O(log capacity)`.
+#[derive(Debug, Clone)]
+pub(super) struct TopK<K> {
+    capacity: usize,
+    /// Min-heap of the counters, the lightest first.
+    counters: Vec<(K, u64)>,
+    /// Position of the counter of each key in the heap.
+    positions: HashMap<K, usize, FxBuildHasher>,
+}
+
+impl<K: Copy + Eq + Hash> TopK<K> {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: Vec::new(),
+            positions: HashMap::default(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, key: K, weight: u64) {
+        if let Some(&position) = self.positions.get(&key) {
+            self.counters[position].1 += weight;
+            self.sift_down(position);
+        } else if self.counters.len() < self.capacity {
+            self.counters.push((key, weight));
+            self.positions.insert(key, self.counters.len() - 1);
+            self.sift_up(self.counters.len() - 1);
+        } else {
+            let (lightest, count) = self.counters[0];
+            self.positions.remove(&lightest);
+            self.counters[0] = (key, count + weight);
+            self.positions.insert(key, 0);
+            self.sift_down(0);
+        }
+    }
+
+    pub(super) fn merge(&mut self, other: &Self) {
+        for (key, count) in &other.counters {
+            self.insert(*key, *count);
+        }
+    }
+
+    /// Returns the `k` heaviest keys with their approximate count, from the heaviest.
+    pub(super) fn top(&self, k: usize) -> Vec<(K, u64)> {
+        let mut top = self.counters.clone();
+        top.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        top.truncate(k);
+        top
+    }
+
+    fn sift_up(&mut self, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if self.counters[parent].1 <= self.counters[position].1 {
+                break;
+            }
+            self.swap(parent, position);
+            position = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut position: usize) {
+        loop {
+            let lightest = [2 * position + 1, 2 * position + 2]
+                .into_iter()
+                .filter(|child| *child < self.counters.len())
+                .fold(position, |lightest, child| {
+                    if self.counters[child].1 < self.counters[lightest].1 {
+                        child
+                    } else {
+                        lightest
+                    }
+                });
+            if lightest == position {
+                break;
+            }
+            self.swap(lightest, position);
+            position = lightest;
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.counters.swap(a, b);
+        self.positions.insert(self.counters[a].0, a);
+        self.positions.insert(self.counters[b].0, b);
+    }
+}
+
+/// Heaviest processes, remote IPs and sockets of a segment, by bytes transferred.
+#[derive(Debug)]
+pub(super) struct TopTalkers {
+    pids: TopK<Interest>,
+    remote_ips: TopK<Interest>,
+    local_sockets: TopK<Interest>,
+}
+
+impl Default for TopTalkers {
+    fn default() -> Self {
+        Self::new(TOP_TALKERS_CAPACITY)
+    }
+}
+
+impl TopTalkers {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            pids: TopK::new(capacity),
+            remote_ips: TopK::new(capacity),
+            local_sockets: TopK::new(capacity),
+        }
+    }
+
+    pub(super) fn insert(&mut self, interest: Interest, weight: u64) {
+        match interest {
+            Interest::Pid(_) => self.pids.insert(interest, weight),
+            Interest::RemoteIp(_) => self.remote_ips.insert(interest, weight),
+            Interest::LocalSocket(_) => self.local_sockets.insert(interest, weight),
+            _ => {}
+        }
+    }
+
+    pub(super) fn merge(&mut self, other: &Self) {
+        self.pids.merge(&other.pids);
+        self.remote_ips.merge(&other.remote_ips);
+        self.local_sockets.merge(&other.local_sockets);
+    }
+
+    /// Returns the sketch of the interests matching `group_by`, if it is tracked.
+    pub(super) fn get(&self, group_by: GroupBy) -> Option<&TopK<Interest>> {
+        match group_by {
+            GroupBy::Pid => Some(&self.pids),
+            GroupBy::RemoteIp => Some(&self.remote_ips),
+            GroupBy::Socket => Some(&self.local_sockets),
+            GroupBy::ProcessName | GroupBy::RemotePort | GroupBy::Uid => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_k_keeps_heavy_hitters() {
+        let mut top = TopK::new(8);
+
+        for round in 0..100u32 {
+            top.insert(1000, 50);
+            top.insert(2000, 20);
+            // A stream of light keys that never repeat.
+            top.insert(round, 1);
+            top.insert(round + 100, 1);
+        }
+
+        let heaviest: Vec<_> = top.top(2).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(heaviest, vec![1000, 2000]);
+
+        let (_, count) = top.top(1)[0];
+        assert!(count >= 5000);
+
+        // The lightest counter is the one taken over.
+        let mut top = TopK::new(3);
+        top.insert(1, 30);
+        top.insert(2, 10);
+        top.insert(3, 20);
+        top.insert(2, 15);
+        top.insert(4, 1);
+        let mut counters = top.top(3);
+        counters.sort_unstable();
+        assert_eq!(counters, vec![(1, 30), (2, 25), (4, 21)]);
+
+        let mut other = TopK::new(8);
+        other.insert(3000, 10_000);
+        top.merge(&other);
+        let (key, count) = top.top(1)[0];
+        assert_eq!(key, 3000);
+        assert!(count >= 10_000);
+    }
+}
@@ -12,7 +12,7 @@ use ptraf_filter::Interpretor;
 use tui::layout::Rect;
 use tui::style::Style;
 use tui::text::{Span, Spans};
-use tui::widgets::Paragraph;
+use tui::widgets::{Paragraph, TableState};
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Layout},
@@ -25,15 +25,37 @@ use crate::store::{Interest, Store};
 use self::process_details::ProcessDetailsView;
 use self::remote_ip_details::RemoteIpDetailsView;
 use self::socktable::{SocketTableConfig, SocketTableView};
+use self::top_table::TopTableView;
 use self::traffic_sparkline::TrafficSparklineView;
+use self::unix_table::UnixTableView;
+use self::user_details::UserDetailsView;
+use self::user_table::UserTableView;
 
 mod filter_editor;
 mod format;
 mod process_details;
 mod remote_ip_details;
+mod size_distribution;
 mod socktable;
 mod styles;
+mod top_table;
 mod traffic_sparkline;
+mod unix_table;
+mod user_details;
+mod user_table;
+
+/// Windows over which the tables and the details aggregate the traffic, with their labels.
+const COLLECTION_WINDOWS: [(Duration, &str); 6] = [
+    (Duration::from_secs(60), "1m"),
+    (Duration::from_secs(5 * 60), "5m"),
+    (Duration::from_secs(15 * 60), "15m"),
+    (Duration::from_secs(3600), "1h"),
+    (Duration::from_secs(6 * 3600), "6h"),
+    (Duration::from_secs(24 * 3600), "24h"),
+];
+
+/// Index of the default collection window in [COLLECTION_WINDOWS].
+const DEFAULT_COLLECTION_WINDOW: usize = 1;
 
 pub struct App {
     clock: ClockNano,
@@ -61,6 +83,10 @@ enum UiEvent {
     Back,
     SelectProcess(u32),
     SelectRemoteIp(IpAddr),
+    SelectUser(u32),
+    ShowTopTalkers,
+    ShowUnixSockets,
+    ShowUsers,
     SetCustomFilter(Option<CustomFilter>),
 }
 
@@ -126,20 +152,52 @@ trait FrameRenderer {
 }
 
 impl FooterBar {
-    fn render<B: Backend>(&self, frame: &mut Frame<B>, rect: Rect, paused: bool) {
-        let paragraph = if paused {
-            let style = Style::default().bg(tui::style::Color::Red);
-            Paragraph::new(Spans::from(vec![Span::from(
-                " PAUSED (press SpaceBar to run) -- UP/DOWN: k/j, - FILTERS: p (process), r (remote IP) - QUIT/BACK: q",
-            )]))
-            .style(style)
+    fn render<B: Backend>(
+        &self,
+        frame: &mut Frame<B>,
+        rect: Rect,
+        paused: bool,
+        collection_window: &str,
+        store: &Store,
+    ) {
+        let mut spans = Vec::with_capacity(4);
+
+        let warning_style = Style::default()
+            .fg(tui::style::Color::Black)
+            .bg(tui::style::Color::Yellow);
+        let lost_events = store.lost_events();
+        let overflowed = store.overflowed();
+
+        // The traffic is under-reported when the perf buffers overflow.
+        if lost_events > 0 {
+            spans.push(Span::styled(
+                format!(" /!\\ LOST EVENTS: {lost_events} "),
+                warning_style,
+            ));
+        }
+
+        // Sockets beyond the limits of the store are only accounted by process.
+        if overflowed > 0 {
+            spans.push(Span::styled(
+                format!(" /!\\ OVERFLOW: {overflowed} "),
+                warning_style,
+            ));
+        }
+
+        let style = if paused {
+            spans.push(Span::from(
+                " PAUSED (press SpaceBar to run) -- UP/DOWN: k/j, - FILTERS: p (process), r (remote IP) - TOP: t - UNIX SOCKETS: u - USERS: U - QUIT/BACK: q",
+            ));
+            Style::default().bg(tui::style::Color::Red)
         } else {
-            let style = Style::default().bg(tui::style::Color::DarkGray);
-            Paragraph::new(
-                " RUNNING (press SpaceBar to pause) -- UP/DOWN: k/j, - FILTERS: p (process), r (remote IP) - QUIT/BACK: q",
-            )
-            .style(style)
+            spans.push(Span::from(
+                " RUNNING (press SpaceBar to pause) -- UP/DOWN: k/j, - FILTERS: p (process), r (remote IP) - TOP: t - UNIX SOCKETS: u - USERS: U - QUIT/BACK: q",
+            ));
+            Style::default().bg(tui::style::Color::DarkGray)
         };
+        spans.push(Span::from(format!(" - WINDOW: {collection_window} (w/W)")));
+
+        let paragraph = Paragraph::new(Spans::from(spans)).style(style);
 
         frame.render_widget(paragraph, rect);
     }
@@ -147,6 +205,8 @@ impl FooterBar {
 
 struct UiContext<'a> {
     ts: Timestamp,
+    /// Duration over which the traffic is aggregated.
+    collection_window: Duration,
     store: &'a Store,
     clock: &'a ClockNano,
     filter_interpretor: Option<&'a Interpretor>,
@@ -162,12 +222,41 @@ trait View {
     fn render<B: Backend>(&mut self, f: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>);
 }
 
+/// Selects the row below the selection of a table of `len` rows, or the first row.
+fn select_down(state: &mut TableState, len: usize) {
+    let selected = (len > 0).then(|| {
+        state
+            .selected()
+            .map_or(0, |selected| selected.saturating_add(1).min(len - 1))
+    });
+    state.select(selected);
+}
+
+/// Selects the row above the selection of a table of `len` rows, if any.
+fn select_up(state: &mut TableState, len: usize) {
+    let selected = state
+        .selected()
+        .filter(|_| len > 0)
+        .map(|selected| selected.saturating_sub(1).min(len - 1));
+    state.select(selected);
+}
+
+/// Returns the warning in the title of a table whose traffic is under-reported.
+fn lost_events_warning(lost_events: u64) -> String {
+    if lost_events > 0 {
+        format!(" /!\\ {lost_events} lost events")
+    } else {
+        String::new()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub(crate) enum Filter {
     #[default]
     None,
     Process(u32),
     RemoteIp(IpAddr),
+    User(u32),
 }
 
 impl Filter {
@@ -176,6 +265,7 @@ impl Filter {
             Self::None => Interest::All,
             Self::Process(pid) => Interest::Pid(pid),
             Self::RemoteIp(ip) => Interest::RemoteIp(ip),
+            Self::User(uid) => Interest::Uid(uid),
         }
     }
 }
@@ -185,6 +275,10 @@ enum RootView {
     Main(MainView),
     Process(ProcessView),
     RemoteIp(RemoteIpView),
+    User(UserView),
+    Top(TopTableView),
+    Unix(UnixTableView),
+    Users(UserTableView),
 }
 
 impl Default for RootView {
@@ -200,6 +294,10 @@ impl View for RootView {
             RootView::Main(inner) => inner.handle_event(event),
             RootView::Process(inner) => inner.handle_event(event),
             RootView::RemoteIp(inner) => inner.handle_event(event),
+            RootView::User(inner) => inner.handle_event(event),
+            RootView::Top(inner) => inner.handle_event(event),
+            RootView::Unix(inner) => inner.handle_event(event),
+            RootView::Users(inner) => inner.handle_event(event),
         }
     }
 
@@ -209,6 +307,10 @@ impl View for RootView {
             RootView::Main(inner) => inner.render(f, rect, ctx),
             RootView::Process(inner) => inner.render(f, rect, ctx),
             RootView::RemoteIp(inner) => inner.render(f, rect, ctx),
+            RootView::User(inner) => inner.render(f, rect, ctx),
+            RootView::Top(inner) => inner.render(f, rect, ctx),
+            RootView::Unix(inner) => inner.render(f, rect, ctx),
+            RootView::Users(inner) => inner.render(f, rect, ctx),
         }
     }
 }
@@ -224,6 +326,8 @@ struct Ui {
     dirty: bool,
     filter: Filter,
     custom_filter: Option<CustomFilter>,
+    /// Index in [COLLECTION_WINDOWS].
+    collection_window: usize,
     view: RootView,
     footer: FooterBar,
 }
@@ -236,8 +340,12 @@ impl Ui {
         let ts = app.clock().now();
         let ts = app.store.oldest_timestamp(ts);
 
+        let (collection_window, collection_window_label) =
+            COLLECTION_WINDOWS[self.collection_window];
+
         let ctx = UiContext {
             ts,
+            collection_window,
             clock: app.clock(),
             store: &app.store,
             paused: self.paused,
@@ -249,7 +357,13 @@ impl Ui {
             .split(frame.size());
 
         self.view.render(frame, rects[0], &ctx);
-        self.footer.render(frame, rects[1], ctx.paused);
+        self.footer.render(
+            frame,
+            rects[1],
+            ctx.paused,
+            collection_window_label,
+            &app.store,
+        );
     }
 }
 
@@ -260,6 +374,7 @@ impl Default for Ui {
             dirty: true,
             filter: Filter::default(),
             custom_filter: None,
+            collection_window: DEFAULT_COLLECTION_WINDOW,
             #[allow(clippy::box_default)]
             view: RootView::Main(MainView::default()),
             footer: FooterBar::default(),
@@ -277,6 +392,11 @@ impl Ui {
         self.paused = !self.paused;
     }
 
+    /// Selects the collection window `step` positions after the current one, wrapping around.
+    fn cycle_collection_window(&mut self, step: usize) {
+        self.collection_window = (self.collection_window + step) % COLLECTION_WINDOWS.len();
+    }
+
     fn needs_display(&self) -> bool {
         self.dirty
     }
@@ -292,8 +412,22 @@ impl Ui {
                 UiEvent::SelectProcess(pid) => {
                     self.update_filter(Filter::Process(pid));
                 }
+                UiEvent::SelectUser(uid) => {
+                    self.update_filter(Filter::User(uid));
+                }
                 UiEvent::Back => {
-                    self.update_filter(Filter::None);
+                    if !self.update_filter(Filter::None) {
+                        self.update_view();
+                    }
+                }
+                UiEvent::ShowTopTalkers => {
+                    self.view = RootView::Top(TopTableView::default());
+                }
+                UiEvent::ShowUnixSockets => {
+                    self.view = RootView::Unix(UnixTableView::default());
+                }
+                UiEvent::ShowUsers => {
+                    self.view = RootView::Users(UserTableView::default());
                 }
                 UiEvent::SetCustomFilter(filter) => self.custom_filter = filter,
                 _ => return ui_event.into(),
@@ -311,6 +445,16 @@ impl Ui {
                     self.toggle_pause();
                     return UiEvent::Change.into();
                 }
+                KeyCode::Char('w') => {
+                    self.set_dirty();
+                    self.cycle_collection_window(1);
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Char('W') => {
+                    self.set_dirty();
+                    self.cycle_collection_window(COLLECTION_WINDOWS.len() - 1);
+                    return UiEvent::Change.into();
+                }
                 _ => {}
             }
         }
@@ -338,6 +482,7 @@ impl Ui {
             Filter::RemoteIp(ipaddr) => {
                 RootView::RemoteIp(RemoteIpView::new(ipaddr, self.custom_filter.as_ref()))
             }
+            Filter::User(uid) => RootView::User(UserView::new(uid, self.custom_filter.as_ref())),
         }
     }
 }
@@ -388,6 +533,9 @@ impl View for MainView {
                         .selected()
                         .map(|entry| UiEvent::SelectRemoteIp(entry.socket.remote.ip()))
                 }
+                KeyCode::Char('t') => return UiEvent::ShowTopTalkers.into(),
+                KeyCode::Char('u') => return UiEvent::ShowUnixSockets.into(),
+                KeyCode::Char('U') => return UiEvent::ShowUsers.into(),
                 _ => {}
             }
         }
@@ -553,3 +701,83 @@ impl View for ProcessView {
         self.sock_table_view.render(frame, rects[2], ctx);
     }
 }
+
+#[derive(Debug)]
+struct UserView {
+    user_details_view: UserDetailsView,
+    traffic_sparkline_view: TrafficSparklineView,
+    sock_table_view: SocketTableView,
+}
+
+impl UserView {
+    fn new(uid: u32, custom_filter: Option<&CustomFilter>) -> Self {
+        let socket_table = SocketTableConfig::default()
+            .filter(Filter::User(uid))
+            .build();
+
+        Self {
+            user_details_view: UserDetailsView::new(uid),
+            traffic_sparkline_view: TrafficSparklineView::with_filter(Filter::User(uid)),
+            sock_table_view: SocketTableView::new(socket_table, custom_filter),
+        }
+    }
+}
+
+impl View for UserView {
+    fn handle_event(&mut self, event: &Event) -> Option<UiEvent> {
+        if let Some(ui_event) = self.sock_table_view.handle_event(event) {
+            return Some(ui_event);
+        }
+
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Backspace => {
+                    return UiEvent::Back.into();
+                }
+
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.sock_table_view.up();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.sock_table_view.down();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Char('p') | KeyCode::Enter => {
+                    return self
+                        .sock_table_view
+                        .selected_pid()
+                        .map(UiEvent::SelectProcess)
+                }
+                KeyCode::Char('r') => {
+                    return self
+                        .sock_table_view
+                        .selected()
+                        .map(|entry| UiEvent::SelectRemoteIp(entry.socket.remote.ip()))
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
+        let rects = Layout::default()
+            .constraints(
+                [
+                    Constraint::Percentage(15),
+                    Constraint::Percentage(15),
+                    Constraint::Percentage(70),
+                ]
+                .as_ref(),
+            )
+            .split(rect);
+
+        self.user_details_view.render(frame, rects[0], ctx);
+
+        self.traffic_sparkline_view.render(frame, rects[1], ctx);
+
+        self.sock_table_view.render(frame, rects[2], ctx);
+    }
+}
@@ -1,13 +1,15 @@
 use crossterm::event::Event;
 use std::fmt::Debug;
+use std::ops::Range;
 use tui::backend::Backend;
+use tui::buffer::Buffer;
 use tui::layout::{Constraint, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders};
+use tui::widgets::{Block, Borders, Widget};
 use tui::Frame;
 use tui_textarea::{Input, Key, TextArea};
 
-use ptraf_filter::Interpretor;
+use ptraf_filter::{Completion, Interpretor, Values};
 
 use super::{CustomFilter, UiContext, UiEvent, View};
 
@@ -16,6 +18,9 @@ pub(super) struct FilterView {
     draft_interpretor: Result<Option<Interpretor>, ptraf_filter::Error>,
     textarea: TextArea<'static>,
     editing: bool,
+    /// The candidates of the last completion and the one inserted, the next Tab inserts the
+    /// next one.
+    completion: Option<(Completion, usize)>,
 }
 
 impl Default for FilterView {
@@ -25,6 +30,7 @@ impl Default for FilterView {
             draft_interpretor: Ok(None),
             textarea: TextArea::default(),
             editing: false,
+            completion: None,
         }
     }
 }
@@ -46,6 +52,7 @@ impl FilterView {
             draft_interpretor: Ok(None),
             textarea: TextArea::default(),
             editing: false,
+            completion: None,
         }
     }
 
@@ -67,6 +74,7 @@ impl FilterView {
 
     pub(super) fn set_editing(&mut self) {
         self.editing = true;
+        self.completion = None;
         self.draft_interpretor = Ok(self.committed_interpretor().cloned());
 
         self.textarea = TextArea::new(vec![self
@@ -137,6 +145,46 @@ impl FilterView {
     fn update(&mut self) {
         self.draft_interpretor = self.draft_content().map(Interpretor::parse).transpose();
     }
+
+    /// Completes the word before the cursor with the keywords and the `values` of the
+    /// dataset, or replaces the last completion by the next candidate.
+    pub(super) fn complete(&mut self, values: &Values) {
+        let (completion, index) = match self.completion.take() {
+            Some((completion, index)) => {
+                let next = (index + 1) % completion.candidates.len();
+                let inserted = completion.candidates[index].chars().count();
+                self.replace(inserted, &completion.candidates[next]);
+                (completion, next)
+            }
+            None => {
+                let content = self.textarea.lines().first().cloned().unwrap_or_default();
+                let (_, column) = self.textarea.cursor();
+                let cursor = content
+                    .char_indices()
+                    .nth(column)
+                    .map_or(content.len(), |(i, _)| i);
+
+                let completion = ptraf_filter::complete(&content, cursor, values);
+                if completion.candidates.is_empty() {
+                    return;
+                }
+                let word = content[completion.span.clone()].chars().count();
+                self.replace(word, &completion.candidates[0]);
+                (completion, 0)
+            }
+        };
+
+        self.completion = Some((completion, index));
+        self.update();
+    }
+
+    /// Replaces the `len` characters before the cursor by the `text`.
+    fn replace(&mut self, len: usize, text: &str) {
+        for _ in 0..len {
+            self.textarea.delete_char();
+        }
+        self.textarea.insert_str(text);
+    }
 }
 
 impl View for FilterView {
@@ -166,6 +214,7 @@ impl View for FilterView {
                 }
             }
             input => {
+                self.completion = None;
                 if self.textarea.input(input) {
                     self.update()
                 }
@@ -188,7 +237,7 @@ impl View for FilterView {
                     self.textarea.set_block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("OK - accept: Enter abort: Esc"),
+                            .title("OK - accept: Enter abort: Esc complete: Tab"),
                     );
                 }
                 Err(err) => {
@@ -216,5 +265,58 @@ impl View for FilterView {
 
         let chunks = layout.split(rect);
         f.render_widget(self.textarea.widget(), chunks[0]);
+
+        if let (true, Err(err), Some(content)) = (
+            self.is_editing(),
+            &self.draft_interpretor,
+            self.draft_content(),
+        ) {
+            f.render_widget(ErrorSpan::new(content, err), chunks[0]);
+        }
+    }
+}
+
+/// Underlines the span of a parse error in the text area.
+struct ErrorSpan {
+    /// Columns of the span in the text.
+    columns: Range<usize>,
+    /// Columns of the text.
+    width: usize,
+}
+
+impl ErrorSpan {
+    fn new(content: &str, err: &ptraf_filter::Error) -> Self {
+        let start = content[..err.span.start].chars().count();
+        // The end of the input is underlined after the text.
+        let len = content[err.span.clone()].chars().count().max(1);
+
+        Self {
+            columns: start..start + len,
+            width: content.chars().count(),
+        }
+    }
+}
+
+impl Widget for ErrorSpan {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let inner = Block::default().borders(Borders::ALL).inner(area);
+
+        // The text area scrolls the text wider than it, the columns are unknown.
+        if self.width + 1 > inner.width as usize {
+            return;
+        }
+
+        let span = Rect {
+            x: inner.x + self.columns.start as u16,
+            y: inner.y,
+            width: self.columns.len() as u16,
+            height: 1,
+        };
+        buf.set_style(
+            span.intersection(inner),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::UNDERLINED),
+        );
     }
 }
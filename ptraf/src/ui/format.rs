@@ -13,6 +13,10 @@ impl Default for Formatter {
 }
 
 impl Formatter {
+    pub fn format_size(&self, val: u64) -> String {
+        humansize::format_size(val, self.0)
+    }
+
     pub fn format_rate(&self, rate_duration: Option<Duration>, val: u64) -> String {
         rate_duration
             .map(|rate_duration| {
@@ -13,7 +13,9 @@ use tui::{
     Frame,
 };
 
-use super::{styles::Styled, UiContext};
+use crate::store::Interest;
+
+use super::{size_distribution::SizeDistribution, styles::Styled, UiContext};
 
 #[derive(Debug)]
 pub(super) struct ProcessDetails {
@@ -111,6 +113,7 @@ impl ProcessDetails {
 pub(super) struct ProcessDetailsView {
     pid: u32,
     details: ProcessDetails,
+    sizes: SizeDistribution,
 }
 
 impl ProcessDetailsView {
@@ -119,6 +122,7 @@ impl ProcessDetailsView {
             pid,
             // FIXME(gwik): i32 ?
             details: ProcessDetails::from_procfs_pid(pid as i32),
+            sizes: SizeDistribution::new(Interest::Pid(pid)),
         }
     }
 
@@ -126,7 +130,7 @@ impl ProcessDetailsView {
         &mut self,
         frame: &mut Frame<B>,
         rect: Rect,
-        _ctx: &UiContext<'_>,
+        ctx: &UiContext<'_>,
     ) {
         let title_style = Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED);
         let title = match &self.details.name {
@@ -174,6 +178,7 @@ impl ProcessDetailsView {
                     Style::default(),
                 ),
             ]),
+            self.sizes.spans(ctx),
         ];
 
         let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
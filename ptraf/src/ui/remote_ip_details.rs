@@ -1,4 +1,4 @@
-use std::{borrow::Cow, net::IpAddr};
+use std::net::IpAddr;
 
 use tui::{
     style::{Modifier, Style},
@@ -6,23 +6,24 @@ use tui::{
     widgets::{Block, Borders, Paragraph, Wrap},
 };
 
-use crate::promise::Promise;
+use ptraf_filter::Lookup;
 
-use super::{styles::Styled, UiContext, View};
+use crate::{dns, store::Interest};
+
+use super::{size_distribution::SizeDistribution, styles::Styled, UiContext, View};
 
 #[derive(Debug)]
 pub(super) struct RemoteIpDetailsView {
     ip: IpAddr,
-    hostname: Promise<String>,
+    sizes: SizeDistribution,
 }
 
 impl RemoteIpDetailsView {
     pub(super) fn new(ip: IpAddr) -> Self {
-        let hostname = Promise::spawn_blocking(move || {
-            dns_lookup::lookup_addr(&ip).unwrap_or_else(|e| format!("[FAILED: {e}]"))
-        });
-
-        Self { ip, hostname }
+        Self {
+            ip,
+            sizes: SizeDistribution::new(Interest::RemoteIp(ip)),
+        }
     }
 }
 
@@ -31,23 +32,27 @@ impl View for RemoteIpDetailsView {
         &mut self,
         frame: &mut tui::Frame<B>,
         rect: tui::layout::Rect,
-        _ctx: &UiContext<'_>,
+        ctx: &UiContext<'_>,
     ) {
         let block = Block::default().borders(Borders::ALL).title(Span::styled(
             format!("remote IP: {}", self.ip),
             Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
         ));
 
-        let text = vec![Spans::from(vec![
-            Styled::label_span("hostname: "),
-            Span::styled(
-                self.hostname
-                    .value()
-                    .map(|hostname| Cow::Borrowed(hostname.as_str()))
-                    .unwrap_or(Cow::Borrowed("[RESOLVING]")),
-                Style::default(),
-            ),
-        ])];
+        let text = vec![
+            Spans::from(vec![
+                Styled::label_span("hostname: "),
+                Span::styled(
+                    match dns::host_name(self.ip) {
+                        Lookup::Pending => "[RESOLVING]".to_string(),
+                        Lookup::NotFound => "[NOT FOUND]".to_string(),
+                        Lookup::Found(hostname) => hostname.to_string(),
+                    },
+                    Style::default(),
+                ),
+            ]),
+            self.sizes.spans(ctx),
+        ];
 
         let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
         frame.render_widget(paragraph, rect);
@@ -0,0 +1,83 @@
+use tui::text::{Span, Spans};
+
+use crate::store::{Interest, Query, SizeHistogram};
+
+use super::{format::Formatter, styles::Styled, UiContext};
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Distribution of the message sizes of an interest over the collection window.
+#[derive(Debug)]
+pub(super) struct SizeDistribution {
+    interest: Interest,
+    sizes: SizeHistogram,
+}
+
+impl SizeDistribution {
+    pub(super) fn new(interest: Interest) -> Self {
+        Self {
+            interest,
+            sizes: SizeHistogram::default(),
+        }
+    }
+
+    fn collect(&mut self, ctx: &UiContext<'_>) {
+        let window = ctx.store.window();
+        let ts = ctx.ts.trunc(window);
+        let since = ts.0.saturating_sub(ctx.collection_window).into();
+
+        let query = Query::new(since..ts + window).interest(self.interest);
+        self.sizes = ctx.store.query(&query).total.sizes;
+    }
+
+    /// Returns the p50 and p99 of the sizes followed by a histogram, from the smallest
+    /// to the largest sizes.
+    pub(super) fn spans(&mut self, ctx: &UiContext<'_>) -> Spans<'static> {
+        if !ctx.paused {
+            self.collect(ctx);
+        }
+
+        let formatter = Formatter::default();
+
+        let max = self
+            .sizes
+            .buckets()
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or_default();
+        let histogram: String = self
+            .sizes
+            .buckets()
+            .iter()
+            .map(|&count| match count {
+                0 => ' ',
+                _ => BARS[(count as u64 * (BARS.len() as u64 - 1) / max as u64) as usize],
+            })
+            .collect();
+
+        Spans::from(vec![
+            Styled::label_span("message sizes: "),
+            Span::raw(format!(
+                "p50 {} p99 {} [{histogram}]",
+                format_quantile(&formatter, &self.sizes, 0.5),
+                format_quantile(&formatter, &self.sizes, 0.99),
+            )),
+        ])
+    }
+}
+
+/// Formats the bound of the bucket of the `quantile` of the sizes.
+pub(super) fn format_quantile(
+    formatter: &Formatter,
+    sizes: &SizeHistogram,
+    quantile: f64,
+) -> String {
+    sizes
+        .quantile(quantile)
+        .map(|bucket| match SizeHistogram::bounds(bucket) {
+            (_, Some(upper)) => format!("<{}", formatter.format_size(upper)),
+            (lower, None) => format!(">={}", formatter.format_size(lower)),
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
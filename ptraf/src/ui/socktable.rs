@@ -1,13 +1,11 @@
 use std::{
-    collections::HashMap,
-    net::SocketAddr,
     ops::Range,
     time::{Duration, SystemTime},
 };
 
 use crossterm::event::{Event, KeyCode, KeyEvent};
 use human_repr::HumanDuration;
-use ptraf_filter::Interpretor;
+use ptraf_filter::Values;
 use tui::{
     backend::Backend,
     layout::{Constraint, Layout, Rect},
@@ -18,26 +16,28 @@ use tui::{
 
 use crate::{
     clock::{ClockNano, Timestamp},
-    store::{Interest, Socket, Stat, Store, TimeSegment},
+    processes,
+    store::{GroupBy, GroupKey, Query, Socket, Stat, Store},
 };
 
 use super::{
-    filter_editor::FilterView, format::Formatter, CustomFilter, Filter, UiContext, UiEvent, View,
+    filter_editor::FilterView, format::Formatter, select_down, select_up,
+    size_distribution::format_quantile, CustomFilter, Filter, UiContext, UiEvent, View,
 };
 
 #[derive(Debug)]
 pub(crate) struct SocketTableConfig {
     filter: Filter,
-    collection_window: Duration,
     rate_window: Duration,
+    max_rows: usize,
 }
 
 impl Default for SocketTableConfig {
     fn default() -> Self {
         Self {
             filter: Filter::default(),
-            collection_window: Duration::from_secs(300), // 5 min
             rate_window: Duration::from_secs(1),
+            max_rows: 500,
         }
     }
 }
@@ -58,9 +58,10 @@ impl SocketTableConfig {
         self
     }
 
+    /// Only keeps the `max_rows` sockets with the most traffic.
     #[allow(unused)]
-    pub(crate) fn collection_window(mut self, window: Duration) -> Self {
-        self.collection_window = window;
+    pub(crate) fn max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows;
         self
     }
 }
@@ -101,9 +102,34 @@ impl SocketTable {
         self.rate_collection_range.as_ref()
     }
 
+    /// Returns the values of the dataset completed in the filters.
+    pub fn values(&self) -> Values {
+        let mut values = Values::default();
+        for entry in &self.dataset {
+            let socket = &entry.socket;
+            values.pids.insert(entry.pid);
+            let comm = String::from_utf8_lossy(socket.comm.as_bytes());
+            if !comm.is_empty() {
+                values.process_names.insert(comm.into_owned());
+            }
+            // Not connected.
+            if !socket.remote.ip().is_unspecified() {
+                values
+                    .remote_addrs
+                    .insert(ptraf_filter::canonical(socket.remote.ip()));
+            }
+            if socket.remote.port() != 0 {
+                values.remote_ports.insert(socket.remote.port());
+            }
+        }
+        values
+    }
+
+    /// Collects the traffic of the heaviest sockets over the `collection_window` before `ts`.
     pub fn collect(
         &mut self,
         ts: Timestamp,
+        collection_window: Duration,
         clock: &ClockNano,
         store: &Store,
         filter_interpretor: Option<&ptraf_filter::Interpretor>,
@@ -111,27 +137,31 @@ impl SocketTable {
         let window = store.window();
 
         let ts = ts.trunc(window);
-
-        let rate_until: Timestamp =
-            ts.0.saturating_sub(self.config.rate_window)
-                .max(window)
-                .into();
-        let rate_until = rate_until.trunc(window);
-
-        let mut collector = SocketTableCollector::new(self.filter, filter_interpretor, rate_until);
-
-        store
-            .segments_view()
-            .iter()
-            .rev()
-            .take_while(|time_segment| {
-                time_segment.ts.saturating_elapsed_since(&ts) <= self.config.collection_window
+        let since = ts.0.saturating_sub(collection_window).into();
+
+        let query = Query::new(since..ts + window)
+            .interest(self.filter.interest())
+            .filter(filter_interpretor)
+            .group_by(GroupBy::Socket)
+            .rate_window(self.config.rate_window)
+            .limit(self.config.max_rows);
+        let result = store.query(&query);
+
+        self.rate_collection_range = result.rate_range;
+        self.dataset = result
+            .rows
+            .into_iter()
+            .filter_map(|row| match row.key {
+                GroupKey::Socket(socket) => Some(Entry {
+                    socket,
+                    stat: row.stat,
+                    last_activity: clock.wall_time(row.last_activity),
+                    rate_stat: row.rate_stat,
+                    pid: socket.pid,
+                }),
+                _ => None,
             })
-            .for_each(|time_segment| collector.collect(time_segment, clock));
-
-        self.rate_collection_range
-            .replace(collector.oldest_rate_segment_ts.unwrap_or(ts)..ts);
-        self.dataset = collector.into_dataset(ts);
+            .collect();
     }
 }
 
@@ -144,86 +174,6 @@ pub(crate) struct Entry {
     pub pid: u32,
 }
 
-#[derive(Debug)]
-struct SocketTableCollector<'a> {
-    filter: Filter,
-    filter_interpretor: Option<&'a Interpretor>,
-    socket_cache: HashMap<SocketAddr, Entry, fxhash::FxBuildHasher>,
-    oldest_segment_ts: Option<Timestamp>,
-    oldest_rate_segment_ts: Option<Timestamp>,
-    rate_until: Timestamp,
-}
-
-impl<'a> SocketTableCollector<'a> {
-    fn new(
-        filter: Filter,
-        filter_interpretor: Option<&'a Interpretor>,
-        rate_until: Timestamp,
-    ) -> Self {
-        Self {
-            filter,
-            filter_interpretor,
-            socket_cache: HashMap::default(),
-            oldest_segment_ts: None,
-            oldest_rate_segment_ts: None,
-            rate_until,
-        }
-    }
-
-    fn into_dataset(self, _ts: Timestamp) -> Vec<Entry> {
-        self.socket_cache.into_values().collect()
-    }
-
-    fn collect(&mut self, time_segment: &TimeSegment, clock: &ClockNano) {
-        self.oldest_segment_ts.replace(time_segment.ts);
-        let is_rate_eligible = self.rate_until <= time_segment.ts;
-        if is_rate_eligible {
-            self.oldest_rate_segment_ts.replace(time_segment.ts);
-        }
-
-        let interest = self.filter.interest();
-
-        time_segment.segment.for_each_socket(|socket| {
-            if !socket.match_interest(interest) {
-                return;
-            }
-
-            if !self
-                .filter_interpretor
-                .map(|inter| inter.filter(socket))
-                .unwrap_or(true)
-            {
-                return;
-            }
-
-            let stat = time_segment
-                .segment
-                .stat_by_interest(&Interest::LocalSocket(socket.local))
-                .unwrap_or_default();
-
-            self.socket_cache
-                .entry(socket.local)
-                .and_modify(|datapoint| {
-                    datapoint.stat.merge(&stat);
-                    if is_rate_eligible {
-                        datapoint.rate_stat.merge(&stat);
-                    }
-                })
-                .or_insert_with(|| Entry {
-                    socket: *socket,
-                    stat,
-                    last_activity: clock.wall_time(time_segment.ts),
-                    rate_stat: if is_rate_eligible {
-                        stat
-                    } else {
-                        Stat::default()
-                    },
-                    pid: socket.pid,
-                });
-        });
-    }
-}
-
 #[derive(Debug)]
 pub(super) struct SocketTableView {
     socket_table: SocketTable,
@@ -251,42 +201,12 @@ impl SocketTableView {
         }
     }
 
-    #[inline]
-    fn len(&self) -> usize {
-        self.socket_table.len()
-    }
-
-    #[inline]
-    fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
-
     pub(super) fn down(&mut self) {
-        let selected = if self.is_empty() {
-            None
-        } else {
-            self.table_state
-                .selected()
-                .map_or(0, |selected| {
-                    selected.saturating_add(1).min(self.len().saturating_sub(1))
-                })
-                .into()
-        };
-        self.table_state.select(selected);
+        select_down(&mut self.table_state, self.socket_table.len());
     }
 
     pub(super) fn up(&mut self) {
-        let selected = if self.is_empty() || self.table_state.selected().is_none() {
-            None
-        } else {
-            self.table_state
-                .selected()
-                .unwrap()
-                .saturating_sub(1)
-                .min(self.len().saturating_sub(1))
-                .into()
-        };
-        self.table_state.select(selected);
+        select_up(&mut self.table_state, self.socket_table.len());
     }
 
     pub(super) fn selected_pid(&self) -> Option<u32> {
@@ -308,7 +228,15 @@ impl SocketTableView {
 impl View for SocketTableView {
     fn handle_event(&mut self, event: &Event) -> Option<UiEvent> {
         if self.filter_view.is_editing() {
-            self.filter_view.handle_event(event)
+            match event {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Tab, ..
+                }) => {
+                    self.filter_view.complete(&self.socket_table.values());
+                    UiEvent::Change.into()
+                }
+                _ => self.filter_view.handle_event(event),
+            }
         } else {
             #[allow(clippy::single_match)]
             match event {
@@ -326,8 +254,13 @@ impl View for SocketTableView {
 
     fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
         if !ctx.paused {
-            self.socket_table
-                .collect(ctx.ts, ctx.clock, ctx.store, self.filter_view.interpretor());
+            self.socket_table.collect(
+                ctx.ts,
+                ctx.collection_window,
+                ctx.clock,
+                ctx.store,
+                self.filter_view.interpretor(),
+            );
         }
 
         let now = SystemTime::now();
@@ -345,9 +278,11 @@ impl View for SocketTableView {
             "local".to_string(),
             "remote".to_string(),
             "type".to_string(),
+            "state".to_string(),
             "last activity".to_string(),
             "pid".to_string(),
             "process".to_string(),
+            "size p50/p99".to_string(),
             "rx/s".to_string(),
             "tx/s".to_string(),
         ]
@@ -366,9 +301,15 @@ impl View for SocketTableView {
                 Cell::from(datapoint.socket.local.to_string()),
                 Cell::from(datapoint.socket.remote.to_string()),
                 Cell::from(datapoint.socket.sock_type.to_string()),
+                Cell::from(datapoint.socket.state.name()),
                 Cell::from(last_activity.human_duration().to_string()),
                 Cell::from(datapoint.pid.to_string()),
                 Cell::from(pid_name(datapoint.pid)),
+                Cell::from(format!(
+                    "{}/{}",
+                    format_quantile(&formatter, &datapoint.stat.sizes, 0.5),
+                    format_quantile(&formatter, &datapoint.stat.sizes, 0.99),
+                )),
                 Cell::from(formatter.format_rate(rate_duration, datapoint.rate_stat.rx)),
                 Cell::from(formatter.format_rate(rate_duration, datapoint.rate_stat.tx)),
             ];
@@ -380,14 +321,16 @@ impl View for SocketTableView {
             .highlight_style(selected_style)
             .highlight_symbol("> ")
             .widths(&[
-                Constraint::Percentage(22),
-                Constraint::Percentage(22),
+                Constraint::Percentage(17),
+                Constraint::Percentage(17),
                 Constraint::Min(10),
+                Constraint::Min(12),
                 Constraint::Percentage(10),
                 Constraint::Percentage(5),
                 Constraint::Percentage(11),
                 Constraint::Percentage(10),
-                Constraint::Percentage(10),
+                Constraint::Percentage(8),
+                Constraint::Percentage(8),
             ]);
 
         let rects = Layout::default()
@@ -402,13 +345,6 @@ impl View for SocketTableView {
     }
 }
 
-fn pid_name(pid: u32) -> String {
-    procfs::process::Process::new(pid as i32)
-        .ok()
-        .and_then(|proc| proc.exe().ok())
-        .as_ref()
-        .and_then(|exe| exe.iter().last())
-        .and_then(|name| name.to_str())
-        .map(|name| name.to_string())
-        .unwrap_or_default()
+pub(super) fn pid_name(pid: u32) -> String {
+    processes::info(pid).name().unwrap_or_default().to_string()
 }
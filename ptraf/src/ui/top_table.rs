@@ -0,0 +1,217 @@
+use std::{
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use crossterm::event::{Event, KeyCode};
+use human_repr::HumanDuration;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    Frame,
+};
+
+use crate::{
+    clock::{ClockNano, Timestamp},
+    store::{GroupBy, GroupKey, Query, Stat, Store},
+};
+
+use super::{
+    format::Formatter, lost_events_warning, select_down, select_up, socktable::pid_name, UiContext,
+    UiEvent, View,
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Number of rows of the table.
+const TOP: usize = 50;
+
+/// Dimensions of the table, cycled through with `g`.
+const GROUP_BYS: [(GroupBy, &str); 4] = [
+    (GroupBy::Pid, "process"),
+    (GroupBy::ProcessName, "process name"),
+    (GroupBy::RemoteIp, "remote IP"),
+    (GroupBy::RemotePort, "remote port"),
+];
+
+#[derive(Debug, Clone)]
+pub(crate) struct TopEntry {
+    pub key: GroupKey,
+    pub stat: Stat,
+    pub rate_stat: Stat,
+    pub last_activity: SystemTime,
+}
+
+/// Table of the heaviest processes, remote IPs or remote ports.
+#[derive(Debug, Default)]
+pub(super) struct TopTableView {
+    /// Index in [GROUP_BYS].
+    group_by: usize,
+    dataset: Vec<TopEntry>,
+    rate_collection_range: Option<Range<Timestamp>>,
+    lost_events: u64,
+    table_state: TableState,
+}
+
+impl TopTableView {
+    fn collect(
+        &mut self,
+        ts: Timestamp,
+        collection_window: Duration,
+        clock: &ClockNano,
+        store: &Store,
+    ) {
+        let window = store.window();
+
+        let ts = ts.trunc(window);
+        let since = ts.0.saturating_sub(collection_window).into();
+
+        let query = Query::new(since..ts + window)
+            .group_by(GROUP_BYS[self.group_by].0)
+            .process_name(&process_name)
+            .rate_window(RATE_WINDOW)
+            .limit(TOP);
+        let result = store.query(&query);
+
+        self.rate_collection_range = result.rate_range;
+        self.lost_events = result.lost_events;
+        self.dataset = result
+            .rows
+            .into_iter()
+            .map(|row| TopEntry {
+                key: row.key,
+                stat: row.stat,
+                rate_stat: row.rate_stat,
+                last_activity: clock.wall_time(row.last_activity),
+            })
+            .collect();
+    }
+
+    fn cycle_group_by(&mut self) {
+        self.group_by = (self.group_by + 1) % GROUP_BYS.len();
+        self.dataset.clear();
+        self.table_state.select(None);
+    }
+
+    fn down(&mut self) {
+        select_down(&mut self.table_state, self.dataset.len());
+    }
+
+    fn up(&mut self) {
+        select_up(&mut self.table_state, self.dataset.len());
+    }
+}
+
+impl View for TopTableView {
+    fn handle_event(&mut self, event: &Event) -> Option<UiEvent> {
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Backspace => {
+                    return UiEvent::Back.into();
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.up();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.down();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Char('g') => {
+                    self.cycle_group_by();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Enter => {
+                    return self
+                        .table_state
+                        .selected()
+                        .and_then(|selected| self.dataset.get(selected))
+                        .and_then(|entry| match entry.key {
+                            GroupKey::Pid(pid) => Some(UiEvent::SelectProcess(pid)),
+                            GroupKey::RemoteIp(ip) => Some(UiEvent::SelectRemoteIp(ip)),
+                            _ => None,
+                        });
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
+        if !ctx.paused {
+            self.collect(ctx.ts, ctx.collection_window, ctx.clock, ctx.store);
+        }
+
+        let now = SystemTime::now();
+
+        let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+        let normal_style = Style::default().bg(Color::DarkGray);
+
+        let rate_duration = self
+            .rate_collection_range
+            .as_ref()
+            .map(|range| range.start.saturating_elapsed_since(&range.end))
+            .filter(|duration| !duration.is_zero());
+
+        let label = GROUP_BYS[self.group_by].1;
+        let header_cells = [label, "last activity", "rx", "tx", "rx/s", "tx/s"]
+            .into_iter()
+            .map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow)));
+        let header = Row::new(header_cells).style(normal_style).height(1);
+
+        let formatter = Formatter::default();
+
+        let rows = self.dataset.iter().map(|entry| {
+            let last_activity = now.duration_since(entry.last_activity).unwrap_or_default();
+
+            let key = match &entry.key {
+                GroupKey::Pid(pid) => format!("{pid} {}", pid_name(*pid)),
+                GroupKey::ProcessName(name) => name.clone(),
+                GroupKey::RemoteIp(ip) => ip.to_string(),
+                GroupKey::RemotePort(port) => port.to_string(),
+                GroupKey::Uid(uid) => uid.to_string(),
+                GroupKey::Socket(socket) => socket.local.to_string(),
+            };
+
+            let cells = [
+                Cell::from(key),
+                Cell::from(last_activity.human_duration().to_string()),
+                Cell::from(formatter.format_size(entry.stat.rx)),
+                Cell::from(formatter.format_size(entry.stat.tx)),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.rx)),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.tx)),
+            ];
+            Row::new(cells)
+        });
+
+        let t = Table::new(rows)
+            .header(header)
+            .block(Block::default().borders(Borders::TOP).title(format!(
+                "top {TOP} by {label} (g: group by){}",
+                lost_events_warning(self.lost_events)
+            )))
+            .highlight_style(selected_style)
+            .highlight_symbol("> ")
+            .widths(&[
+                Constraint::Percentage(30),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+            ]);
+
+        frame.render_stateful_widget(t, rect, &mut self.table_state);
+    }
+}
+
+/// Returns the name of the process, its pid if unknown.
+fn process_name(pid: u32) -> String {
+    Some(pid_name(pid))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| pid.to_string())
+}
@@ -10,7 +10,7 @@ use tui::{
 
 use crate::{
     clock::Timestamp,
-    store::{Interest, Store, TimeSegment},
+    store::{Query, Store},
 };
 
 use super::{format::Formatter, Filter, UiContext};
@@ -26,7 +26,6 @@ struct DataPoint {
 pub(crate) struct TrafficSparkline {
     filter: Filter,
     dataset: VecDeque<DataPoint>,
-    reverse_buffer: Vec<DataPoint>,
 }
 
 impl TrafficSparkline {
@@ -42,68 +41,40 @@ impl TrafficSparkline {
         store: &Store,
         filter_interpretor: Option<&ptraf_filter::Interpretor>,
     ) {
-        // the starting point for data already collected.
-        let start = self.dataset.back().map(|dp| dp.ts).unwrap_or_default();
-
-        let view = store.segments_view();
+        let oldest = match store.segments_view().oldest() {
+            Some(time_segment) => time_segment.ts,
+            None => return,
+        };
 
         // Discard the outdated datapoints.
-        if let Some(TimeSegment { ts: oldest, .. }) = view.oldest() {
-            while let Some(DataPoint { ts: front_ts, .. }) = self.dataset.front() {
-                if front_ts < oldest {
-                    self.dataset.pop_front();
-                } else {
-                    break;
-                }
+        while let Some(DataPoint { ts: front_ts, .. }) = self.dataset.front() {
+            if *front_ts < oldest {
+                self.dataset.pop_front();
+            } else {
+                break;
             }
         }
 
-        let interest = self.filter.interest();
-
-        // Insert new datapoints from newest to oldest in the reverse buffer.
-        view.iter()
-            .rev()
-            .take_while(|time_segment| time_segment.ts > start)
-            .for_each(|time_segment| {
-                let datapoint = {
-                    let mut datapoint = DataPoint {
-                        ts: time_segment.ts,
-                        rx: 0,
-                        tx: 0,
-                    };
-
-                    if let Some(interpretor) = filter_interpretor {
-                        time_segment.segment.for_each_socket(|socket| {
-                            if !interpretor.filter(socket) {
-                                return;
-                            }
-
-                            let stat = time_segment
-                                .segment
-                                .stat_by_interest(&Interest::LocalSocket(socket.local));
-
-                            datapoint.rx += stat.as_ref().map(|stat| stat.rx).unwrap_or_default();
-                            datapoint.tx += stat.as_ref().map(|stat| stat.tx).unwrap_or_default();
-                        });
-                    } else {
-                        let stat = time_segment.segment.stat_by_interest(&interest);
-                        datapoint.rx = stat.as_ref().map(|stat| stat.rx).unwrap_or_default();
-                        datapoint.tx = stat.as_ref().map(|stat| stat.tx).unwrap_or_default();
-                    }
-                    datapoint
-                };
-                self.reverse_buffer.push(datapoint);
-            });
-
-        // drop the view so it releases the read lock on the store.
-        drop(view);
-
-        // Drain the reverse buffer and insert in the dataset in chronological order.
-        self.reverse_buffer
-            .drain(..)
-            .skip(1) // skip the newest since the segment is incomplete
-            .rev()
-            .for_each(|datapoint| self.dataset.push_back(datapoint));
+        // the starting point for data not collected yet.
+        let start = self
+            .dataset
+            .back()
+            .map_or(oldest, |dp| dp.ts + Duration::from_nanos(1));
+
+        let query = Query::new(start..Duration::MAX.into())
+            .interest(self.filter.interest())
+            .filter(filter_interpretor)
+            .series();
+        let mut series = store.query(&query).series;
+
+        series.pop(); // skip the newest since the segment is incomplete
+
+        self.dataset
+            .extend(series.into_iter().map(|(ts, stat)| DataPoint {
+                ts,
+                rx: stat.rx,
+                tx: stat.tx,
+            }));
     }
 }
 
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use crossterm::event::{Event, KeyCode};
+use human_repr::HumanDuration;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    Frame,
+};
+
+use crate::{
+    clock::{ClockNano, Timestamp},
+    store::{Stat, Store, UnixPath, UnixSocket},
+};
+
+use super::{
+    format::Formatter, select_down, select_up, socktable::pid_name, UiContext, UiEvent, View,
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub(crate) struct UnixEntry {
+    pub pid: u32,
+    /// `None` for the traffic of the sockets beyond the limits of the store.
+    pub socket: Option<UnixSocket>,
+    pub stat: Stat,
+    pub rate_stat: Stat,
+    pub last_activity: SystemTime,
+}
+
+/// Table of the traffic over unix domain sockets.
+#[derive(Debug, Default)]
+pub(super) struct UnixTableView {
+    dataset: Vec<UnixEntry>,
+    rate_collection_range: Option<Range<Timestamp>>,
+    table_state: TableState,
+}
+
+impl UnixTableView {
+    fn collect(
+        &mut self,
+        ts: Timestamp,
+        collection_window: Duration,
+        clock: &ClockNano,
+        store: &Store,
+    ) {
+        let window = store.window();
+
+        let ts = ts.trunc(window);
+
+        let rate_until: Timestamp = ts.0.saturating_sub(RATE_WINDOW).max(window).into();
+        let rate_until = rate_until.trunc(window);
+
+        let mut oldest_rate_segment_ts = None;
+        let mut entries: HashMap<(u32, Option<UnixSocket>), UnixEntry, fxhash::FxBuildHasher> =
+            HashMap::default();
+
+        store
+            .segments_view_covering(collection_window)
+            .iter()
+            .rev()
+            .take_while(|time_segment| {
+                time_segment.ts.saturating_elapsed_since(&ts) <= collection_window
+            })
+            .for_each(|time_segment| {
+                let is_rate_eligible = rate_until <= time_segment.ts;
+                if is_rate_eligible {
+                    oldest_rate_segment_ts.replace(time_segment.ts);
+                }
+
+                let mut add = |pid: u32, socket: Option<&UnixSocket>, stat: Stat| {
+                    let entry =
+                        entries
+                            .entry((pid, socket.cloned()))
+                            .or_insert_with(|| UnixEntry {
+                                pid,
+                                socket: socket.cloned(),
+                                stat: Stat::default(),
+                                rate_stat: Stat::default(),
+                                last_activity: clock.wall_time(time_segment.ts),
+                            });
+
+                    entry.stat.merge(&stat);
+                    if is_rate_eligible {
+                        entry.rate_stat.merge(&stat);
+                    }
+                };
+
+                time_segment
+                    .segment
+                    .for_each_unix_socket(|socket, stat| add(socket.pid, Some(socket), stat));
+                time_segment
+                    .segment
+                    .for_each_unix_overflow(|pid, stat| add(pid, None, stat));
+            });
+
+        self.rate_collection_range
+            .replace(oldest_rate_segment_ts.unwrap_or(ts)..ts);
+
+        self.dataset = entries.into_values().collect();
+        // The untracked traffic of the processes last.
+        fn key(entry: &UnixEntry) -> (bool, Option<&UnixPath>, u32, Option<u32>) {
+            (
+                entry.socket.is_none(),
+                entry.socket.as_ref().map(|socket| &socket.path),
+                entry.pid,
+                entry.socket.as_ref().map(|socket| socket.peer_pid),
+            )
+        }
+        self.dataset.sort_by(|a, b| key(a).cmp(&key(b)));
+    }
+
+    fn down(&mut self) {
+        select_down(&mut self.table_state, self.dataset.len());
+    }
+
+    fn up(&mut self) {
+        select_up(&mut self.table_state, self.dataset.len());
+    }
+}
+
+impl View for UnixTableView {
+    fn handle_event(&mut self, event: &Event) -> Option<UiEvent> {
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Backspace => {
+                    return UiEvent::Back.into();
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.up();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.down();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Char('p') | KeyCode::Enter => {
+                    return self
+                        .table_state
+                        .selected()
+                        .and_then(|selected| self.dataset.get(selected))
+                        .map(|entry| UiEvent::SelectProcess(entry.pid));
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
+        if !ctx.paused {
+            self.collect(ctx.ts, ctx.collection_window, ctx.clock, ctx.store);
+        }
+
+        let now = SystemTime::now();
+
+        let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+        let normal_style = Style::default().bg(Color::DarkGray);
+
+        let rate_duration = self
+            .rate_collection_range
+            .as_ref()
+            .map(|range| range.start.saturating_elapsed_since(&range.end))
+            .filter(|duration| !duration.is_zero());
+
+        let header_cells = [
+            "path",
+            "type",
+            "last activity",
+            "pid",
+            "process",
+            "peer pid",
+            "peer process",
+            "rx/s",
+            "tx/s",
+        ]
+        .into_iter()
+        .map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow)));
+        let header = Row::new(header_cells).style(normal_style).height(1);
+
+        let formatter = Formatter::default();
+
+        let rows = self.dataset.iter().map(|entry| {
+            let last_activity = now.duration_since(entry.last_activity).unwrap_or_default();
+            let peer_pid = entry.socket.as_ref().map_or(0, |socket| socket.peer_pid);
+
+            let cells = [
+                Cell::from(match &entry.socket {
+                    Some(socket) => socket.path.to_string(),
+                    None => "(untracked sockets)".to_string(),
+                }),
+                Cell::from(
+                    entry
+                        .socket
+                        .as_ref()
+                        .map(|socket| socket.sock_type.to_string())
+                        .unwrap_or_default(),
+                ),
+                Cell::from(last_activity.human_duration().to_string()),
+                Cell::from(entry.pid.to_string()),
+                Cell::from(pid_name(entry.pid)),
+                Cell::from(if peer_pid == 0 {
+                    String::new()
+                } else {
+                    peer_pid.to_string()
+                }),
+                Cell::from(if peer_pid == 0 {
+                    String::new()
+                } else {
+                    pid_name(peer_pid)
+                }),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.rx)),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.tx)),
+            ];
+            Row::new(cells)
+        });
+
+        let t = Table::new(rows)
+            .header(header)
+            .block(Block::default().borders(Borders::TOP).title("unix sockets"))
+            .highlight_style(selected_style)
+            .highlight_symbol("> ")
+            .widths(&[
+                Constraint::Percentage(30),
+                Constraint::Min(10),
+                Constraint::Percentage(10),
+                Constraint::Percentage(5),
+                Constraint::Percentage(11),
+                Constraint::Percentage(5),
+                Constraint::Percentage(11),
+                Constraint::Percentage(8),
+                Constraint::Percentage(8),
+            ]);
+
+        frame.render_stateful_widget(t, rect, &mut self.table_state);
+    }
+}
@@ -0,0 +1,47 @@
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    text::Span,
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{store::Interest, users};
+
+use super::{size_distribution::SizeDistribution, UiContext, View};
+
+#[derive(Debug)]
+pub(super) struct UserDetailsView {
+    uid: u32,
+    sizes: SizeDistribution,
+}
+
+impl UserDetailsView {
+    pub(super) fn new(uid: u32) -> Self {
+        Self {
+            uid,
+            sizes: SizeDistribution::new(Interest::Uid(uid)),
+        }
+    }
+}
+
+impl View for UserDetailsView {
+    fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
+        let title_style = Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED);
+        // Cached with the names of the user table.
+        let title = match users::user_name(self.uid) {
+            Some(name) => format!("user: {name} ({})", self.uid),
+            None => format!("<UID {}>", self.uid),
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(Span::styled(title, title_style));
+
+        let text = vec![self.sizes.spans(ctx)];
+
+        let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
+        frame.render_widget(paragraph, rect);
+    }
+}
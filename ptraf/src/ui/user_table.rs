@@ -0,0 +1,180 @@
+use std::{
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use crossterm::event::{Event, KeyCode};
+use human_repr::HumanDuration;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    Frame,
+};
+
+use crate::{
+    clock::{ClockNano, Timestamp},
+    store::{GroupBy, GroupKey, Query, Stat, Store},
+    users,
+};
+
+use super::{
+    format::Formatter, lost_events_warning, select_down, select_up, UiContext, UiEvent, View,
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub(crate) struct UserEntry {
+    pub uid: u32,
+    pub stat: Stat,
+    pub rate_stat: Stat,
+    pub last_activity: SystemTime,
+}
+
+/// Table of the traffic by user.
+#[derive(Debug, Default)]
+pub(super) struct UserTableView {
+    dataset: Vec<UserEntry>,
+    rate_collection_range: Option<Range<Timestamp>>,
+    lost_events: u64,
+    table_state: TableState,
+}
+
+impl UserTableView {
+    fn collect(
+        &mut self,
+        ts: Timestamp,
+        collection_window: Duration,
+        clock: &ClockNano,
+        store: &Store,
+    ) {
+        let window = store.window();
+
+        let ts = ts.trunc(window);
+        let since = ts.0.saturating_sub(collection_window).into();
+
+        let query = Query::new(since..ts + window)
+            .group_by(GroupBy::Uid)
+            .rate_window(RATE_WINDOW);
+        let result = store.query(&query);
+
+        self.rate_collection_range = result.rate_range;
+        self.lost_events = result.lost_events;
+        self.dataset = result
+            .rows
+            .into_iter()
+            .filter_map(|row| match row.key {
+                GroupKey::Uid(uid) => Some(UserEntry {
+                    uid,
+                    stat: row.stat,
+                    rate_stat: row.rate_stat,
+                    last_activity: clock.wall_time(row.last_activity),
+                }),
+                _ => None,
+            })
+            .collect();
+        // The heaviest users first.
+        self.dataset
+            .sort_by(|a, b| (b.stat.total(), a.uid).cmp(&(a.stat.total(), b.uid)));
+    }
+
+    fn down(&mut self) {
+        select_down(&mut self.table_state, self.dataset.len());
+    }
+
+    fn up(&mut self) {
+        select_up(&mut self.table_state, self.dataset.len());
+    }
+}
+
+impl View for UserTableView {
+    fn handle_event(&mut self, event: &Event) -> Option<UiEvent> {
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Backspace => {
+                    return UiEvent::Back.into();
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.up();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.down();
+                    return UiEvent::Change.into();
+                }
+                KeyCode::Enter => {
+                    return self
+                        .table_state
+                        .selected()
+                        .and_then(|selected| self.dataset.get(selected))
+                        .map(|entry| UiEvent::SelectUser(entry.uid));
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn render<B: Backend>(&mut self, frame: &mut Frame<B>, rect: Rect, ctx: &UiContext<'_>) {
+        if !ctx.paused {
+            self.collect(ctx.ts, ctx.collection_window, ctx.clock, ctx.store);
+        }
+
+        let now = SystemTime::now();
+
+        let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+        let normal_style = Style::default().bg(Color::DarkGray);
+
+        let rate_duration = self
+            .rate_collection_range
+            .as_ref()
+            .map(|range| range.start.saturating_elapsed_since(&range.end))
+            .filter(|duration| !duration.is_zero());
+
+        let header_cells = ["user", "uid", "last activity", "rx", "tx", "rx/s", "tx/s"]
+            .into_iter()
+            .map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow)));
+        let header = Row::new(header_cells).style(normal_style).height(1);
+
+        let formatter = Formatter::default();
+
+        let rows = self.dataset.iter().map(|entry| {
+            let last_activity = now.duration_since(entry.last_activity).unwrap_or_default();
+
+            let cells = [
+                Cell::from(users::user_name(entry.uid).unwrap_or_default()),
+                Cell::from(entry.uid.to_string()),
+                Cell::from(last_activity.human_duration().to_string()),
+                Cell::from(formatter.format_size(entry.stat.rx)),
+                Cell::from(formatter.format_size(entry.stat.tx)),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.rx)),
+                Cell::from(formatter.format_rate(rate_duration, entry.rate_stat.tx)),
+            ];
+            Row::new(cells)
+        });
+
+        let t = Table::new(rows)
+            .header(header)
+            .block(
+                Block::default()
+                    .borders(Borders::TOP)
+                    .title(format!("users{}", lost_events_warning(self.lost_events))),
+            )
+            .highlight_style(selected_style)
+            .highlight_symbol("> ")
+            .widths(&[
+                Constraint::Percentage(20),
+                Constraint::Percentage(10),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+                Constraint::Percentage(14),
+            ]);
+
+        frame.render_stateful_widget(t, rect, &mut self.table_state);
+    }
+}
@@ -0,0 +1,47 @@
+//! Names of the users and groups.
+//!
+//! Names are looked up in the user database once per id and cached for the lifetime of
+//! the process, including the unknown ids. There are few of them, the names are leaked
+//! so the filters can borrow them.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use nix::unistd::{Gid, Group, Uid, User};
+
+type Names = Mutex<Option<HashMap<u32, Option<&'static str>>>>;
+
+static USERS: Names = Mutex::new(None);
+static GROUPS: Names = Mutex::new(None);
+
+/// Returns the name of the user `uid`, or `None` if it is not in the user database.
+pub fn user_name(uid: u32) -> Option<&'static str> {
+    cached(&USERS, uid, |uid| {
+        User::from_uid(Uid::from_raw(uid))
+            .ok()
+            .flatten()
+            .map(|user| user.name)
+    })
+}
+
+/// Returns the name of the group `gid`, or `None` if it is not in the group database.
+pub fn group_name(gid: u32) -> Option<&'static str> {
+    cached(&GROUPS, gid, |gid| {
+        Group::from_gid(Gid::from_raw(gid))
+            .ok()
+            .flatten()
+            .map(|group| group.name)
+    })
+}
+
+fn cached(
+    names: &Names,
+    id: u32,
+    lookup: impl FnOnce(u32) -> Option<String>,
+) -> Option<&'static str> {
+    *names
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::default)
+        .entry(id)
+        .or_insert_with(|| lookup(id).map(|name| &*Box::leak(name.into_boxed_str())))
+}
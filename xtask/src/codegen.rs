@@ -3,7 +3,15 @@ use std::{fs::File, io::Write, path::PathBuf};
 
 pub fn generate() -> Result<(), anyhow::Error> {
     let dir = PathBuf::from("ptraf-ebpf/src");
-    let names: Vec<&str> = vec!["socket", "sock_common", "sock_type", "in6_addr"];
+    let names: Vec<&str> = vec![
+        "socket",
+        "sock_common",
+        "sock_type",
+        "in6_addr",
+        "unix_sock",
+        "unix_address",
+        "sockaddr_un",
+    ];
     let bindings = aya_tool::generate(
         InputFile::Btf(PathBuf::from("/sys/kernel/btf/vmlinux")),
         &names,